@@ -16,11 +16,11 @@ fn main() {
 
 fn generate_resource_consts() {
     let in_path = Path::new(RESOURCES_FILE);
-    let rc_file = File::open(in_path).expect(&format!("Can't open {RESOURCES_FILE} file"));
+    let rc_file = File::open(in_path).unwrap_or_else(|e| panic!("Can't open {RESOURCES_FILE} file. {e}"));
 
     let mut out = String::new();
     out.push_str("/* Autogenerated by build.rs. Do not edit. */\n\n");
-    for line in BufReader::new(rc_file).lines().flatten() {
+    for line in BufReader::new(rc_file).lines().map_while(Result::ok) {
         if line.starts_with("#define ") {
             let parts: Vec<&str> = line.split_whitespace().collect();
             if parts.len() >= 3 && parts[1].starts_with("ID") {
@@ -30,5 +30,5 @@ fn generate_resource_consts() {
     }
 
     let out_path = Path::new(RESOURCES_IDS_FILE);
-    fs::write(out_path, out).expect(&format!("Can't write {RESOURCES_IDS_FILE} file"));
+    fs::write(out_path, out).unwrap_or_else(|e| panic!("Can't write {RESOURCES_IDS_FILE} file. {e}"));
 }
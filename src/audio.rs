@@ -1,76 +1,148 @@
-use crate::util::{from_utf16, sleep_cancelable, start_timer, stop_timer};
-use log::{debug, trace, warn};
-use std::ptr::null_mut;
-use std::time::Duration;
-use windows::core::PSTR;
-use windows::Win32::Foundation::HWND;
-use windows::Win32::Media::Audio::{
-    waveOutClose, waveOutGetErrorTextW, waveOutOpen, waveOutPrepareHeader, waveOutReset, waveOutUnprepareHeader, waveOutWrite,
-    CALLBACK_NULL, HWAVEOUT, WAVEFORMATEX, WAVEHDR, WAVE_FORMAT_PCM,
-    WAVE_MAPPER, WHDR_DONE,
-};
-use windows::Win32::Media::MMSYSERR_NOERROR;
-
-pub const TIMER_AUDIO: usize = 100;
+use crate::metrics::METRICS;
+use crate::settings::Settings;
+use log::{debug, warn};
+use serde::Serialize;
+use std::fmt;
+
+const SAMPLES_PER_SEC: u32 = 44100;
+const BLOCK_ALIGN: u16 = 2;
+
 #[cfg(not(feature = "debug"))]
-const TIMER_PERIOD_MS: u32 = 5000;
+const WAVEFORM_DURATION_MS: u32 = 10;
 #[cfg(feature = "debug")]
-const TIMER_PERIOD_MS: u32 = 2000;
+const WAVEFORM_DURATION_MS: u32 = 1000;
+/* the shortest buffer that audio drivers still treat as activity */
+const MIN_ENERGY_WAVEFORM_DURATION_MS: u32 = 1;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum PauseReason {
+    User,
+    UserIdle,
+    SessionLocked,
+    OnBattery,
+    Snooze,
+}
 
-const SAMPLES_PER_SEC: u32 = 44100;
+/// Audio device the keep-alive waveform is played on.
+pub trait AudioOutput {
+    fn open(&mut self) -> Result<(), String>;
+    /// Plays the waveform once. A closed device is reopened first.
+    fn play(&mut self) -> Result<(), String>;
+    fn close(&mut self);
+    fn device_names(&self) -> Vec<String>;
+
+    /// Opens the device again, e.g. after the system resumed. If it is not ready yet the next
+    /// ping retries.
+    fn reopen(&mut self) {
+        debug!("Reopening device...");
+        METRICS.count_reopen_attempt();
+        self.close();
+        /* failures are logged by device */
+        self.open().unwrap_or_default();
+    }
+}
 
-#[derive(Default)]
-pub struct AudioControl {
-    window: Option<HWND>,
-    device: HWAVEOUT,
-    buffer: Vec<u8>,
-    waveform: WAVEHDR,
+/// Failure of one device. Carries what the backend tells about it so that it can be logged as
+/// fields.
+#[derive(Debug, Clone, PartialEq)]
+struct AudioError {
+    message: String,
+    device: Option<String>,
+    /* waveOut device index */
+    device_id: Option<u32>,
+    /* MMSYSERR code on Windows, exit status of aplay on Linux */
+    code: Option<i64>,
+    detail: String,
 }
 
-impl AudioControl {
-    pub fn start(&mut self, hwnd: Option<HWND>) -> Result<(), String> {
-        self.window = hwnd;
-        self.device = open_device()?;
-        self.buffer = generate_waveform();
-        self.waveform = create_waveform(&mut self.buffer);
-        prepare_waveform(self.device, &mut self.waveform)?;
-        start_timer(self.window, TIMER_AUDIO, TIMER_PERIOD_MS)
+impl AudioError {
+    fn new(message: impl Into<String>) -> Self {
+        Self {
+            message: message.into(),
+            device: None,
+            device_id: None,
+            code: None,
+            detail: String::new(),
+        }
+    }
+
+    fn with_code(self, code: i64) -> Self {
+        Self {
+            code: Some(code),
+            ..self
+        }
+    }
+
+    fn with_detail(self, detail: impl Into<String>) -> Self {
+        Self {
+            detail: detail.into(),
+            ..self
+        }
     }
 
-    pub fn play(&mut self) -> Result<(), String> {
-        trace!("Playing waveform...");
-        
-        if let Err(e) = play_waveform(self.device, &mut self.waveform) {
-            warn!("{}", e);
-            debug!("Restarting...");
-        
-            reset_waveform(self.device)?;
+    fn on_device(self, device: &str, device_id: Option<u32>) -> Self {
+        Self {
+            device: Some(device.to_string()),
+            device_id: device_id.or(self.device_id),
+            ..self
         }
+    }
 
+    fn log(&self) {
+        warn!(
+            event = "audio_error",
+            device = self.device.as_deref(),
+            device_id = self.device_id,
+            code = self.code,
+            detail = self.detail.as_str();
+            "{}", self.message
+        );
+    }
+}
+
+impl fmt::Display for AudioError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        if let Some(device) = &self.device {
+            write!(f, "{}: ", device)?;
+        }
+        write!(f, "{}", self.message)?;
+        if let Some(code) = self.code {
+            write!(f, " (code: {})", code)?;
+        }
+        if !self.detail.is_empty() {
+            write!(f, ". {}", self.detail)?;
+        }
         Ok(())
     }
+}
 
-    pub fn stop(&mut self) {
-        stop_timer(self.window, TIMER_AUDIO);
-        unprepare_waveform(self.device, &mut self.waveform);
-        close_device(self.device);
+pub fn waveform_duration_ms(settings: &Settings) -> u32 {
+    if settings.min_energy {
+        MIN_ENERGY_WAVEFORM_DURATION_MS
+    } else {
+        WAVEFORM_DURATION_MS
     }
 }
 
+fn sample_count(duration_ms: u32) -> usize {
+    (SAMPLES_PER_SEC * duration_ms / 1000) as usize
+}
+
 #[cfg(not(feature = "debug"))]
-/// Generates 10 milliseconds of silence.
-fn generate_waveform() -> Vec<u8> {
-    vec![0; SAMPLES_PER_SEC as usize / 100]
+/// Generates silence of the given duration.
+fn generate_waveform(duration_ms: u32) -> Vec<u8> {
+    vec![0; sample_count(duration_ms) * BLOCK_ALIGN as usize]
 }
 
 #[cfg(feature = "debug")]
-/// Generates 1 second of 60Hz sine.
-fn generate_waveform() -> Vec<u8> {
-    let sample_count = SAMPLES_PER_SEC as usize;
+/// Generates 60Hz sine of the given duration.
+fn generate_waveform(duration_ms: u32) -> Vec<u8> {
+    let sample_count = sample_count(duration_ms);
     let amplitude = i16::MAX as f32;
     let frequency = 60.0;
 
-    let mut buffer = Vec::with_capacity(sample_count * 2);
+    let mut buffer = Vec::with_capacity(sample_count * BLOCK_ALIGN as usize);
     for n in 0..sample_count {
         let t = n as f32 / SAMPLES_PER_SEC as f32;
         let sample = (amplitude * (2.0 * std::f32::consts::PI * frequency * t).sin()) as i16;
@@ -80,180 +152,621 @@ fn generate_waveform() -> Vec<u8> {
     buffer
 }
 
-fn create_waveform(buffer: &mut [u8]) -> WAVEHDR {
-    WAVEHDR {
-        lpData: PSTR(buffer.as_mut_ptr()),
-        dwBufferLength: buffer.len() as u32,
-        dwBytesRecorded: 0,
-        dwUser: 0,
-        dwFlags: 0,
-        dwLoops: 0,
-        lpNext: null_mut(),
-        reserved: 0,
+/// Combines the results of playing on several devices. Fails if any device failed. Each failure
+/// is logged with its fields.
+fn join_errors(results: impl Iterator<Item = Result<(), AudioError>>) -> Result<(), String> {
+    let errors: Vec<String> = results
+        .filter_map(Result::err)
+        .inspect(AudioError::log)
+        .map(|e| e.to_string())
+        .collect();
+    if errors.is_empty() {
+        Ok(())
+    } else {
+        Err(errors.join(" "))
     }
 }
 
-macro_rules! win_api_call {
-    ($expr:expr, $error_message:expr) => {{
-        let result = unsafe { $expr };
-        check_result(result, $error_message)
-    }};
-}
-
-fn open_device() -> Result<HWAVEOUT, String> {
-    let audio_format = WAVEFORMATEX {
-        wFormatTag: WAVE_FORMAT_PCM as u16,
-        nChannels: 1,
-        nSamplesPerSec: SAMPLES_PER_SEC,
-        wBitsPerSample: 16,
-        nBlockAlign: 2,
-        nAvgBytesPerSec: SAMPLES_PER_SEC * 2,
-        cbSize: 0,
+#[cfg(windows)]
+pub use windows_audio::{WaveOutDevices as PlatformDeviceSource, WaveOutOutput as PlatformAudioOutput};
+
+#[cfg(target_os = "linux")]
+pub use linux_audio::{AplayDevices as PlatformDeviceSource, AplayOutput as PlatformAudioOutput};
+
+#[cfg(windows)]
+mod windows_audio {
+    use crate::audio::{generate_waveform, join_errors, AudioError, AudioOutput, BLOCK_ALIGN, SAMPLES_PER_SEC};
+    use crate::devices::DeviceSource;
+    use crate::metrics::METRICS;
+    use crate::util::from_utf16;
+    use log::{debug, trace};
+    use std::ptr::null_mut;
+    use std::sync::atomic::{AtomicU32, Ordering};
+    use std::time::Duration;
+    use windows::core::PSTR;
+    use windows::Win32::Media::Audio::{
+        waveOutClose, waveOutGetDevCapsW, waveOutGetErrorTextW, waveOutGetNumDevs, waveOutOpen, waveOutPrepareHeader, waveOutReset, waveOutUnprepareHeader, waveOutWrite,
+        CALLBACK_FUNCTION, HWAVEOUT, WAVEFORMATEX, WAVEHDR, WAVEOUTCAPSW, WAVE_FORMAT_PCM,
+        WAVE_MAPPER,
     };
+    use windows::Win32::Media::{timeGetTime, MMSYSERR_NOERROR, MM_WOM_DONE};
 
-    let mut handler = HWAVEOUT::default();
-
-    win_api_call!(
-        waveOutOpen(
-            Some(&mut handler),
-            WAVE_MAPPER,
-            &audio_format,
-            Some(0),
-            Some(0),
-            CALLBACK_NULL,
-        ),
-        "Error opening audio device"
-    )?;
-
-    Ok(handler)
-}
+    /* how the wave mapper is called in the log */
+    const DEFAULT_DEVICE: &str = "default";
+    /* `WaveOutDevice::done_at` before the device completes the waveform */
+    const NOT_DONE: u32 = 0;
 
-fn close_device(device: HWAVEOUT) {
-    win_api_call!(waveOutClose(device), "Error closing audio device").unwrap_or_else(|e| {
-        warn!("{}", e);
-    });
-}
+    /// waveOut devices selected by name, or the default device.
+    pub struct WaveOutOutput {
+        devices: Vec<WaveOutDevice>,
+    }
 
-fn prepare_waveform(device: HWAVEOUT, waveform: &mut WAVEHDR) -> Result<(), String> {
-    win_api_call!(
-        waveOutPrepareHeader(device, waveform, size_of::<WAVEHDR>() as u32),
-        "Error preparing waveform"
-    )
-}
+    /// One opened device with its own copy of the waveform.
+    struct WaveOutDevice {
+        /* `None` is the default device */
+        name: Option<String>,
+        /* waveOut index while the device is open */
+        id: Option<u32>,
+        handle: HWAVEOUT,
+        /* the waveform header points into the buffer */
+        _buffer: Vec<u8>,
+        waveform: WAVEHDR,
+        /* `timeGetTime` when the device completed the waveform, set by the driver callback */
+        done_at: Box<AtomicU32>,
+        /* `timeGetTime` when the waveform was written, if its latency is measured */
+        written_at: Option<u32>,
+    }
 
-fn unprepare_waveform(device: HWAVEOUT, waveform: &mut WAVEHDR) {
-    win_api_call!(
-        waveOutUnprepareHeader(device, waveform, size_of::<WAVEHDR>() as u32),
-        "Error unpreparing waveform"
-    )
-    .unwrap_or_else(|e| {
-        warn!("{}", e);
-    });
-}
+    impl WaveOutOutput {
+        /// An empty `names` selects the default device.
+        pub fn new(duration_ms: u32, names: &[String]) -> Self {
+            let names = match names {
+                [] => vec![None],
+                names => names.iter().cloned().map(Some).collect(),
+            };
+
+            let devices = names
+                .into_iter()
+                .map(|name| {
+                    let mut buffer = generate_waveform(duration_ms);
+                    let waveform = create_waveform(&mut buffer);
+                    WaveOutDevice {
+                        name,
+                        id: None,
+                        handle: HWAVEOUT::default(),
+                        _buffer: buffer,
+                        waveform,
+                        done_at: Box::new(AtomicU32::new(NOT_DONE)),
+                        written_at: None,
+                    }
+                })
+                .collect();
+
+            Self { devices }
+        }
+    }
 
-fn play_waveform(device: HWAVEOUT, waveform: &mut WAVEHDR) -> Result<(), String> {
-    win_api_call!(
-        waveOutWrite(device, waveform, size_of::<WAVEHDR>() as u32),
-        "Error playing waveform"
-    )
-}
+    impl WaveOutDevice {
+        fn open(&mut self) -> Result<(), AudioError> {
+            let id = match &self.name {
+                Some(name) => find_device(name).map_err(|e| e.on_device(self.label(), None))?,
+                None => WAVE_MAPPER,
+            };
+            let handle = open_device(id, &self.done_at).map_err(|e| e.on_device(self.label(), Some(id)))?;
+            if let Err(e) = prepare_waveform(handle, &mut self.waveform) {
+                close_device(handle);
+                return Err(e.on_device(self.label(), Some(id)));
+            }
+            self.handle = handle;
+            self.id = Some(id);
 
-fn reset_waveform(device: HWAVEOUT) -> Result<(), String> {
-    win_api_call!(waveOutReset(device), "Error resetting waveform")
-}
+            Ok(())
+        }
 
-fn await_play_done(waveform: &WAVEHDR) {
-    /* wait for the flag no more than 5 seconds.*/
-    if sleep_cancelable(Duration::from_secs(5), || {
-        (waveform.dwFlags & WHDR_DONE) != 0
-    }) {
-        trace!("Waveform is done");
-    } else {
-        warn!("Waveform await timeout expired");
-    };
-}
+        fn play(&mut self) -> Result<(), AudioError> {
+            if self.handle.is_invalid() {
+                debug!(event = "reopen", device = self.label(); "Reopening device...");
+                METRICS.count_reopen_attempt();
+                self.open()?;
+            }
 
-fn check_result(result: u32, message: &str) -> Result<(), String> {
-    if result == MMSYSERR_NOERROR {
-        Ok(())
-    } else {
-        let error_text = unsafe {
-            let mut text_buffer = [0u16; 256];
-            let inner_result = waveOutGetErrorTextW(result, &mut text_buffer);
-            if inner_result == MMSYSERR_NOERROR {
-                from_utf16(&text_buffer)
+            trace!(event = "play", device = self.label(), device_id = self.id; "Playing waveform...");
+
+            self.observe_latency();
+            if METRICS.is_enabled() {
+                self.written_at = Some(unsafe { timeGetTime() });
+            }
+
+            if let Err(e) = play_waveform(self.handle, &mut self.waveform) {
+                METRICS.count_write_failure();
+                debug!(event = "reset", device = self.label(); "Restarting...");
+                METRICS.count_reset();
+
+                if let Err(e) = reset_waveform(self.handle) {
+                    e.on_device(self.label(), self.id).log();
+                    /* the handle is stale. reopen it on the next ping */
+                    self.close();
+                }
+                return Err(e.on_device(self.label(), self.id));
+            }
+
+            Ok(())
+        }
+
+        /// Reports how long the device took to complete the previous waveform. The driver
+        /// callback records when it is done, so playing never waits for the device.
+        fn observe_latency(&mut self) {
+            let done_at = self.done_at.swap(NOT_DONE, Ordering::Acquire);
+            let Some(written_at) = self.written_at.take() else {
+                return;
+            };
+
+            if done_at == NOT_DONE {
+                AudioError::new("Waveform is not done by the next ping").on_device(self.label(), self.id).log();
             } else {
-                format!("Error getting error text (code: {})", inner_result)
+                let latency = Duration::from_millis(done_at.wrapping_sub(written_at) as u64);
+                let latency_ms = latency.as_millis() as u64;
+                trace!(event = "played", device = self.label(), latency_ms; "Waveform is done");
+                METRICS.observe_latency(latency);
+            }
+        }
+
+        fn close(&mut self) {
+            if !self.handle.is_invalid() {
+                unprepare_waveform(self.handle, &mut self.waveform);
+                close_device(self.handle);
+                self.handle = HWAVEOUT::default();
+                self.id = None;
             }
+        }
+
+        fn label(&self) -> &str {
+            self.name.as_deref().unwrap_or(DEFAULT_DEVICE)
+        }
+
+        fn display_name(&self) -> Option<String> {
+            match &self.name {
+                Some(name) => Some(name.clone()),
+                None => device_name(WAVE_MAPPER).ok(),
+            }
+        }
+    }
+
+    impl AudioOutput for WaveOutOutput {
+        fn open(&mut self) -> Result<(), String> {
+            /* a missing device does not keep the others from opening */
+            join_errors(self.devices.iter_mut().map(|device| device.open()))
+        }
+
+        fn play(&mut self) -> Result<(), String> {
+            join_errors(self.devices.iter_mut().map(|device| device.play()))
+        }
+
+        fn close(&mut self) {
+            self.devices.iter_mut().for_each(WaveOutDevice::close);
+        }
+
+        fn device_names(&self) -> Vec<String> {
+            self.devices.iter().filter_map(WaveOutDevice::display_name).collect()
+        }
+    }
+
+    impl Drop for WaveOutOutput {
+        fn drop(&mut self) {
+            self.close();
+        }
+    }
+
+    /// Lists waveOut devices by name.
+    #[derive(Default)]
+    pub struct WaveOutDevices;
+
+    impl DeviceSource for WaveOutDevices {
+        fn devices(&self) -> Result<Vec<String>, String> {
+            let count = unsafe { waveOutGetNumDevs() };
+            (0..count).map(|id| device_name(id).map_err(|e| e.to_string())).collect()
+        }
+    }
+
+    fn find_device(name: &str) -> Result<u32, AudioError> {
+        let count = unsafe { waveOutGetNumDevs() };
+        (0..count)
+            .find(|id| device_name(*id).is_ok_and(|device| device == name))
+            .ok_or(AudioError::new("Audio device is not connected"))
+    }
+
+    fn create_waveform(buffer: &mut [u8]) -> WAVEHDR {
+        WAVEHDR {
+            lpData: PSTR(buffer.as_mut_ptr()),
+            dwBufferLength: buffer.len() as u32,
+            dwBytesRecorded: 0,
+            dwUser: 0,
+            dwFlags: 0,
+            dwLoops: 0,
+            lpNext: null_mut(),
+            reserved: 0,
+        }
+    }
+
+    macro_rules! win_api_call {
+        ($expr:expr, $error_message:expr) => {{
+            let result = unsafe { $expr };
+            check_result(result, $error_message)
+        }};
+    }
+
+    /// `done_at` gets the `timeGetTime` of each completed waveform. It has to outlive the device.
+    fn open_device(device_id: u32, done_at: &AtomicU32) -> Result<HWAVEOUT, AudioError> {
+        let audio_format = WAVEFORMATEX {
+            wFormatTag: WAVE_FORMAT_PCM as u16,
+            nChannels: 1,
+            nSamplesPerSec: SAMPLES_PER_SEC,
+            wBitsPerSample: 16,
+            nBlockAlign: BLOCK_ALIGN,
+            nAvgBytesPerSec: SAMPLES_PER_SEC * BLOCK_ALIGN as u32,
+            cbSize: 0,
         };
-        Err(format!("{} (code: {}). {}", message, result, error_text))
+
+        let mut handler = HWAVEOUT::default();
+
+        win_api_call!(
+            waveOutOpen(
+                Some(&mut handler),
+                device_id,
+                &audio_format,
+                Some(on_wave_out_message as *const () as usize),
+                Some(done_at as *const AtomicU32 as usize),
+                CALLBACK_FUNCTION,
+            ),
+            "Error opening audio device"
+        )?;
+
+        Ok(handler)
+    }
+
+    fn device_name(device_id: u32) -> Result<String, AudioError> {
+        let mut caps = WAVEOUTCAPSW::default();
+
+        win_api_call!(
+            waveOutGetDevCapsW(device_id as usize, &mut caps, size_of::<WAVEOUTCAPSW>() as u32),
+            "Error getting device capabilities"
+        )?;
+
+        /* the struct is packed. copy the field to read it */
+        let name = caps.szPname;
+        Ok(from_utf16(&name))
+    }
+
+    fn close_device(device: HWAVEOUT) {
+        win_api_call!(waveOutClose(device), "Error closing audio device").unwrap_or_else(|e| e.log());
+    }
+
+    fn prepare_waveform(device: HWAVEOUT, waveform: &mut WAVEHDR) -> Result<(), AudioError> {
+        win_api_call!(
+            waveOutPrepareHeader(device, waveform, size_of::<WAVEHDR>() as u32),
+            "Error preparing waveform"
+        )
+    }
+
+    fn unprepare_waveform(device: HWAVEOUT, waveform: &mut WAVEHDR) {
+        win_api_call!(
+            waveOutUnprepareHeader(device, waveform, size_of::<WAVEHDR>() as u32),
+            "Error unpreparing waveform"
+        )
+        .unwrap_or_else(|e| e.log());
+    }
+
+    fn play_waveform(device: HWAVEOUT, waveform: &mut WAVEHDR) -> Result<(), AudioError> {
+        win_api_call!(
+            waveOutWrite(device, waveform, size_of::<WAVEHDR>() as u32),
+            "Error playing waveform"
+        )
+    }
+
+    fn reset_waveform(device: HWAVEOUT) -> Result<(), AudioError> {
+        win_api_call!(waveOutReset(device), "Error resetting waveform")
+    }
+
+    /// Driver callback of an open device. It must not call waveOut functions, so it only records
+    /// the time.
+    unsafe extern "system" fn on_wave_out_message(_: HWAVEOUT, message: u32, instance: usize, _: usize, _: usize) {
+        if message == MM_WOM_DONE {
+            let done_at = unsafe { &*(instance as *const AtomicU32) };
+            done_at.store(unsafe { timeGetTime() }.max(NOT_DONE + 1), Ordering::Release);
+        }
+    }
+
+    fn check_result(result: u32, message: &str) -> Result<(), AudioError> {
+        if result == MMSYSERR_NOERROR {
+            Ok(())
+        } else {
+            let error_text = unsafe {
+                let mut text_buffer = [0u16; 256];
+                let inner_result = waveOutGetErrorTextW(result, &mut text_buffer);
+                if inner_result == MMSYSERR_NOERROR {
+                    from_utf16(&text_buffer)
+                } else {
+                    format!("Error getting error text (code: {})", inner_result)
+                }
+            };
+            Err(AudioError::new(message).with_code(result as i64).with_detail(error_text))
+        }
+    }
+
+    #[cfg(test)]
+    mod tests {
+        use crate::audio::windows_audio::{
+            check_result, close_device, create_waveform, open_device, play_waveform,
+            prepare_waveform, unprepare_waveform,
+        };
+        use crate::audio::{generate_waveform, WAVEFORM_DURATION_MS};
+        use std::sync::atomic::{AtomicU32, Ordering};
+        use std::thread;
+        use std::time::{Duration, Instant};
+        use windows::Win32::Media::Audio::WAVE_MAPPER;
+        use windows::Win32::Media::{MMSYSERR_INVALPARAM, MMSYSERR_NOERROR};
+
+        #[test]
+        fn test_check_result() {
+            assert!(check_result(MMSYSERR_NOERROR, "Error message").is_ok());
+            assert!(check_result(MMSYSERR_INVALPARAM, "Error message").is_err())
+        }
+
+        #[test]
+        fn test_create_audio() {
+            let mut buffer = generate_waveform(WAVEFORM_DURATION_MS);
+            let waveform = create_waveform(&mut buffer);
+            assert_ne!(0u32, waveform.dwBufferLength as u32);
+        }
+
+        #[test]
+        fn test_open_close_device() {
+            let device = open_device(WAVE_MAPPER, &AtomicU32::new(0)).unwrap();
+            close_device(device);
+        }
+
+        #[test]
+        fn test_play_waveform() {
+            let done_at = AtomicU32::new(0);
+            let device = open_device(WAVE_MAPPER, &done_at).unwrap();
+            let mut buffer = generate_waveform(WAVEFORM_DURATION_MS);
+            let mut waveform = create_waveform(&mut buffer);
+
+            prepare_waveform(device, &mut waveform).unwrap();
+            play_waveform(device, &mut waveform).unwrap();
+            let started = Instant::now();
+            while done_at.load(Ordering::Acquire) == 0 {
+                assert!(started.elapsed() < Duration::from_secs(5), "Waveform is not done");
+                thread::sleep(Duration::from_millis(10));
+            }
+            unprepare_waveform(device, &mut waveform);
+            close_device(device);
+        }
     }
 }
 
-// pub fn keep_audio_awake(running: Arc<AtomicBool>, event_sink: Sender<u8>) -> Result<(), String> {
-//     let device = open_device()?;
-//     let mut buffer = generate_waveform();
-//     let mut waveform = create_waveform(&mut buffer);
-//     prepare_waveform(device, &mut waveform)?;
-//
-//     while running.load(Ordering::SeqCst) {
-//         play_waveform(device, &mut waveform)?;
-//         await_play_done(&waveform);
-//
-//         sleep_cancelable(Duration::from_millis(TIMER_PLAY_PERIOD_MS as u64), || {
-//             !running.load(Ordering::Relaxed)
-//         });
-//     }
-//
-//     unprepare_waveform(device, &mut waveform);
-//     close_device(device);
-//
-//     Ok(())
-// }
+#[cfg(target_os = "linux")]
+mod linux_audio {
+    use crate::audio::{generate_waveform, join_errors, AudioError, AudioOutput, SAMPLES_PER_SEC};
+    use crate::devices::DeviceSource;
+    use crate::metrics::METRICS;
+    use log::trace;
+    use std::io::{Read, Write};
+    use std::process::{Child, Command, ExitStatus, Stdio};
+    use std::thread;
+    use std::time::{Duration, Instant};
+
+    const DEFAULT_DEVICE: &str = "default";
+    /* aplay takes this long to start and drain on top of the waveform itself */
+    const PLAY_TIMEOUT_MARGIN: Duration = Duration::from_secs(2);
+    const POLL_INTERVAL: Duration = Duration::from_millis(10);
+
+    /// ALSA devices fed through `aplay` so that no audio library is needed.
+    pub struct AplayOutput {
+        devices: Vec<String>,
+        buffer: Vec<u8>,
+        /* aplay is killed if it runs longer, as on a wedged device */
+        timeout: Duration,
+    }
+
+    impl AplayOutput {
+        /// An empty `names` selects the default device.
+        pub fn new(duration_ms: u32, names: &[String]) -> Self {
+            let devices = match names {
+                [] => vec![DEFAULT_DEVICE.to_string()],
+                names => names.to_vec(),
+            };
+
+            Self {
+                devices,
+                buffer: generate_waveform(duration_ms),
+                timeout: Duration::from_millis(duration_ms as u64) + PLAY_TIMEOUT_MARGIN,
+            }
+        }
+
+        fn play_on(&self, device: &str) -> Result<(), AudioError> {
+            trace!(event = "play", device; "Playing waveform...");
+            let started = Instant::now();
+
+            let mut child = Command::new("aplay")
+                .args(["-q", "-t", "raw", "-f", "S16_LE", "-c", "1", "-D", device])
+                .arg(format!("-r{}", SAMPLES_PER_SEC))
+                .stdin(Stdio::piped())
+                .stderr(Stdio::piped())
+                .spawn()
+                .map_err(|e| run_error(device, e))?;
+
+            /* aplay stops reading on a wedged device, and the write would block. the writer fails
+             * once aplay is killed */
+            if let Some(mut stdin) = child.stdin.take() {
+                let buffer = self.buffer.clone();
+                thread::Builder::new()
+                    .name("aplay".to_string())
+                    .spawn(move || stdin.write_all(&buffer).unwrap_or_default())
+                    .map_err(|e| run_error(device, e))?;
+            }
+
+            let status = wait_timeout(&mut child, self.timeout);
+
+            if METRICS.is_enabled() {
+                METRICS.observe_latency(started.elapsed());
+            }
+
+            let status = match status {
+                Ok(Some(status)) => status,
+                Ok(None) => {
+                    METRICS.count_write_failure();
+                    let error = AudioError::new("Timed out playing waveform")
+                        .with_detail(format!("aplay ran longer than {} ms", self.timeout.as_millis()));
+                    Err(error.on_device(device, None))?
+                }
+                Err(e) => Err(run_error(device, e))?,
+            };
+
+            if !status.success() {
+                METRICS.count_write_failure();
+                let mut stderr = String::new();
+                if let Some(mut pipe) = child.stderr.take() {
+                    pipe.read_to_string(&mut stderr).unwrap_or_default();
+                }
+                let error = AudioError::new("Error playing waveform").with_detail(stderr.trim().to_string());
+                /* no exit status if killed by a signal */
+                let error = match status.code() {
+                    Some(code) => error.with_code(code as i64),
+                    None => error,
+                };
+                Err(error.on_device(device, None))?
+            }
+
+            Ok(())
+        }
+    }
+
+    /// Waits for the child to exit, or kills it after `timeout` and returns `None`.
+    fn wait_timeout(child: &mut Child, timeout: Duration) -> std::io::Result<Option<ExitStatus>> {
+        let started = Instant::now();
+        loop {
+            if let Some(status) = child.try_wait()? {
+                return Ok(Some(status));
+            }
+            if started.elapsed() >= timeout {
+                child.kill().unwrap_or_default();
+                child.wait()?;
+                return Ok(None);
+            }
+            thread::sleep(POLL_INTERVAL);
+        }
+    }
+
+    fn run_error(device: &str, e: std::io::Error) -> AudioError {
+        AudioError::new("Failed to run aplay")
+            .with_detail(e.to_string())
+            .on_device(device, None)
+    }
+
+    impl AudioOutput for AplayOutput {
+        fn open(&mut self) -> Result<(), String> {
+            Ok(())
+        }
+
+        fn play(&mut self) -> Result<(), String> {
+            join_errors(self.devices.iter().map(|device| self.play_on(device)))
+        }
+
+        fn close(&mut self) {}
+
+        fn device_names(&self) -> Vec<String> {
+            self.devices.clone()
+        }
+    }
+
+    /// Lists ALSA devices by the names `aplay -L` prints.
+    #[derive(Default)]
+    pub struct AplayDevices;
+
+    impl DeviceSource for AplayDevices {
+        fn devices(&self) -> Result<Vec<String>, String> {
+            let output = Command::new("aplay")
+                .arg("-L")
+                .stderr(Stdio::null())
+                .output()
+                .map_err(|e| format!("Failed to list audio devices. {}", e))?;
+
+            Ok(parse_device_list(&String::from_utf8_lossy(&output.stdout)))
+        }
+    }
+
+    /// Device names start a line, their descriptions are indented.
+    fn parse_device_list(list: &str) -> Vec<String> {
+        list.lines()
+            .filter(|line| !line.is_empty() && !line.starts_with(char::is_whitespace))
+            .filter(|name| *name != "null" && *name != DEFAULT_DEVICE)
+            .map(str::to_string)
+            .collect()
+    }
+
+    #[cfg(test)]
+    mod tests {
+        use crate::audio::linux_audio::{parse_device_list, wait_timeout};
+        use std::process::Command;
+        use std::time::{Duration, Instant};
+
+        #[test]
+        fn test_parse_device_list() {
+            let list = "null\n    Discard all samples\ndefault\n    Default ALSA Output\nhw:CARD=PCH,DEV=0\n    HDA Intel PCH, ALC3246 Analog\n        Direct hardware device\n";
+            assert_eq!(vec!["hw:CARD=PCH,DEV=0"], parse_device_list(list));
+        }
+
+        #[test]
+        fn test_wait_timeout() {
+            let mut child = Command::new("sleep").arg("5").spawn().unwrap();
+            let started = Instant::now();
+            assert!(wait_timeout(&mut child, Duration::from_millis(200)).unwrap().is_none());
+            assert!(started.elapsed() < Duration::from_secs(2));
+
+            let mut child = Command::new("true").spawn().unwrap();
+            let status = wait_timeout(&mut child, Duration::from_secs(5)).unwrap();
+            assert!(status.is_some_and(|status| status.success()));
+        }
+    }
+}
 
 #[cfg(test)]
 mod tests {
     use crate::audio::{
-        await_play_done, check_result, close_device, create_waveform, generate_waveform,
-        open_device, play_waveform, prepare_waveform, unprepare_waveform,
+        generate_waveform, join_errors, AudioError, MIN_ENERGY_WAVEFORM_DURATION_MS, WAVEFORM_DURATION_MS,
     };
-    use windows::Win32::Media::{MMSYSERR_INVALPARAM, MMSYSERR_NOERROR};
-
-    #[test]
-    fn test_check_result() {
-        assert!(check_result(MMSYSERR_NOERROR, "Error message").is_ok());
-        assert!(check_result(MMSYSERR_INVALPARAM, "Error message").is_err())
-    }
 
     #[test]
     fn test_generate_waveform() {
-        let buffer = generate_waveform();
+        let buffer = generate_waveform(WAVEFORM_DURATION_MS);
         assert_ne!(0, buffer.len());
+        assert_eq!(0, buffer.len() % 2);
     }
 
     #[test]
-    fn test_create_audio() {
-        let mut buffer = generate_waveform();
-        let waveform = create_waveform(&mut buffer);
-        assert_ne!(0u32, waveform.dwBufferLength as u32);
-    }
-
-    #[test]
-    fn test_open_close_device() {
-        let device = open_device().unwrap();
-        close_device(device);
+    fn test_generate_min_energy_waveform() {
+        let buffer = generate_waveform(MIN_ENERGY_WAVEFORM_DURATION_MS);
+        assert_ne!(0, buffer.len());
+        assert!(buffer.len() < generate_waveform(WAVEFORM_DURATION_MS).len());
     }
 
     #[test]
-    fn test_play_waveform() {
-        let device = open_device().unwrap();
-        let mut buffer = generate_waveform();
-        let mut waveform = create_waveform(&mut buffer);
-
-        prepare_waveform(device, &mut waveform).unwrap();
-        play_waveform(device, &mut waveform).unwrap();
-        await_play_done(&waveform);
-        unprepare_waveform(device, &mut waveform);
-        close_device(device);
+    fn test_join_errors() {
+        let results = [
+            Ok(()),
+            Err(AudioError::new("Error playing waveform")
+                .with_code(11)
+                .with_detail("Invalid parameter")
+                .on_device("Speakers", Some(1))),
+            Err(AudioError::new("Audio device is not connected").on_device("Headphones", None)),
+        ];
+        assert_eq!(
+            Err("Speakers: Error playing waveform (code: 11). Invalid parameter \
+                 Headphones: Audio device is not connected"
+                .to_string()),
+            join_errors(results.into_iter())
+        );
+        assert_eq!(Ok(()), join_errors([Ok(()), Ok(())].into_iter()));
     }
 }
@@ -0,0 +1,174 @@
+/* starting at login is kept where the system looks for it rather than in the settings file,
+so that changes made with the system tools show up too */
+
+#[cfg(windows)]
+pub use windows_autostart::{is_enabled, set_enabled};
+
+#[cfg(target_os = "linux")]
+pub use linux_autostart::{is_enabled, set_enabled};
+
+#[cfg(windows)]
+mod windows_autostart {
+    use std::ffi::c_void;
+    use windows::core::HSTRING;
+    use windows::Win32::Foundation::ERROR_FILE_NOT_FOUND;
+    use windows::Win32::System::Registry::{
+        RegDeleteKeyValueW, RegGetValueW, RegSetKeyValueW, HKEY_CURRENT_USER, REG_SZ, RRF_RT_REG_SZ,
+    };
+
+    const RUN_KEY: &str = r"Software\Microsoft\Windows\CurrentVersion\Run";
+    const VALUE_NAME: &str = "KeepAuAwake";
+
+    /// Tells whether the `Run` key of the user has an entry for the application.
+    pub fn is_enabled() -> Result<bool, String> {
+        let result = unsafe {
+            RegGetValueW(
+                HKEY_CURRENT_USER,
+                &HSTRING::from(RUN_KEY),
+                &HSTRING::from(VALUE_NAME),
+                RRF_RT_REG_SZ,
+                None,
+                None,
+                None,
+            )
+        };
+        match result {
+            ERROR_FILE_NOT_FOUND => Ok(false),
+            result => result
+                .ok()
+                .map(|_| true)
+                .map_err(|e| format!("Failed to read autostart entry. {}", e)),
+        }
+    }
+
+    /// Adds the current executable to the `Run` key of the user or removes it from there.
+    pub fn set_enabled(enabled: bool) -> Result<(), String> {
+        if enabled {
+            let exe = std::env::current_exe().map_err(|e| format!("Failed to get executable path. {}", e))?;
+            let command = format!("\"{}\"", exe.display());
+            let data: Vec<u16> = command.encode_utf16().chain([0]).collect();
+            unsafe {
+                RegSetKeyValueW(
+                    HKEY_CURRENT_USER,
+                    &HSTRING::from(RUN_KEY),
+                    &HSTRING::from(VALUE_NAME),
+                    REG_SZ.0,
+                    Some(data.as_ptr() as *const c_void),
+                    (data.len() * size_of::<u16>()) as u32,
+                )
+            }
+            .ok()
+            .map_err(|e| format!("Failed to add autostart entry. {}", e))
+        } else {
+            let result = unsafe {
+                RegDeleteKeyValueW(
+                    HKEY_CURRENT_USER,
+                    &HSTRING::from(RUN_KEY),
+                    &HSTRING::from(VALUE_NAME),
+                )
+            };
+            match result {
+                ERROR_FILE_NOT_FOUND => Ok(()),
+                result => result
+                    .ok()
+                    .map_err(|e| format!("Failed to remove autostart entry. {}", e)),
+            }
+        }
+    }
+}
+
+#[cfg(target_os = "linux")]
+mod linux_autostart {
+    use crate::settings::config_home;
+    use std::fs;
+    use std::io::ErrorKind;
+    use std::path::{Path, PathBuf};
+
+    const DESKTOP_FILE: &str = "keep-audio-awake.desktop";
+    /* the same string as in the Windows resources */
+    const APP_TITLE: &str = "KeepAuAwake";
+
+    /// Tells whether the XDG autostart directory has a desktop entry for the application.
+    pub fn is_enabled() -> Result<bool, String> {
+        Ok(desktop_file_path()?.exists())
+    }
+
+    /// Writes a desktop entry for the current executable or removes it.
+    pub fn set_enabled(enabled: bool) -> Result<(), String> {
+        let path = desktop_file_path()?;
+        if enabled {
+            let exe = std::env::current_exe().map_err(|e| format!("Failed to get executable path. {}", e))?;
+            if let Some(dir) = path.parent() {
+                fs::create_dir_all(dir).map_err(|e| format!("Failed to create {}. {}", dir.display(), e))?;
+            }
+            fs::write(&path, desktop_entry(&exe)).map_err(|e| format!("Failed to write {}. {}", path.display(), e))
+        } else {
+            match fs::remove_file(&path) {
+                Err(e) if e.kind() != ErrorKind::NotFound => {
+                    Err(format!("Failed to remove {}. {}", path.display(), e))
+                }
+                _ => Ok(()),
+            }
+        }
+    }
+
+    fn desktop_file_path() -> Result<PathBuf, String> {
+        Ok(config_home()?.join("autostart").join(DESKTOP_FILE))
+    }
+
+    pub(super) fn desktop_entry(exe: &Path) -> String {
+        format!(
+            "[Desktop Entry]\n\
+             Type=Application\n\
+             Name={}\n\
+             Comment=Keeps the audio device awake\n\
+             Exec={}\n\
+             Icon=audio-speakers\n\
+             Terminal=false\n\
+             X-GNOME-Autostart-enabled=true\n",
+            APP_TITLE,
+            exec_arg(&exe.to_string_lossy())
+        )
+    }
+
+    /// Quotes an argument of the `Exec` key as the desktop entry specification requires.
+    fn exec_arg(arg: &str) -> String {
+        const RESERVED: &str = " \t\n\"'\\><~|&;$*?#()`";
+
+        let arg = if arg.chars().any(|c| RESERVED.contains(c)) {
+            let mut quoted = String::from("\"");
+            for c in arg.chars() {
+                if "\"`$\\".contains(c) {
+                    quoted.push('\\');
+                }
+                quoted.push(c);
+            }
+            quoted.push('"');
+            quoted
+        } else {
+            arg.to_string()
+        };
+
+        /* the string escapes of the key value apply on top of the quoting */
+        arg.replace('\\', "\\\\").replace('%', "%%")
+    }
+}
+
+#[cfg(all(test, target_os = "linux"))]
+mod tests {
+    use crate::autostart::linux_autostart::desktop_entry;
+    use std::path::Path;
+
+    #[test]
+    fn test_desktop_entry() {
+        let entry = desktop_entry(Path::new("/usr/bin/keep-audio-awake"));
+        assert!(entry.starts_with("[Desktop Entry]\n"));
+        assert!(entry.contains("\nExec=/usr/bin/keep-audio-awake\n"));
+
+        let entry = desktop_entry(Path::new("/opt/Keep Audio/100%/keep-audio-awake"));
+        assert!(entry.contains("\nExec=\"/opt/Keep Audio/100%%/keep-audio-awake\"\n"));
+
+        let entry = desktop_entry(Path::new("/opt/$HOME/keep-audio-awake"));
+        assert!(entry.contains("\nExec=\"/opt/\\\\$HOME/keep-audio-awake\"\n"));
+    }
+}
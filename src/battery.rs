@@ -0,0 +1,206 @@
+use log::warn;
+
+#[cfg(windows)]
+pub const TIMER_BATTERY: usize = 300;
+pub const TIMER_BATTERY_PERIOD_MS: u32 = 30_000;
+
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub struct PowerStatus {
+    pub on_battery: bool,
+    /// Remaining battery charge if known.
+    pub charge_percent: Option<u8>,
+}
+
+/// Source of the system power state.
+pub trait PowerSource {
+    fn status(&self) -> Result<PowerStatus, String>;
+}
+
+/// Tracks the power state and decides when keep-alive should save battery.
+pub struct BatteryMonitor<S: PowerSource> {
+    source: S,
+    threshold_percent: u8,
+    status: Option<PowerStatus>,
+}
+
+impl<S: PowerSource> BatteryMonitor<S> {
+    /// Battery saving applies on battery when the charge is below `threshold_percent`.
+    /// A threshold of 100 applies it whenever the system runs on battery.
+    pub fn new(source: S, threshold_percent: u8) -> Self {
+        Self {
+            source,
+            threshold_percent,
+            status: None,
+        }
+    }
+
+    /// Returns the new power status if it has changed since the previous poll.
+    pub fn poll(&mut self) -> Option<PowerStatus> {
+        let status = self
+            .source
+            .status()
+            .inspect_err(|e| warn!("{}", e))
+            .ok()?;
+
+        if self.status == Some(status) {
+            None
+        } else {
+            self.status = Some(status);
+            Some(status)
+        }
+    }
+
+    pub fn is_saving(&self) -> bool {
+        match self.status {
+            Some(status) if status.on_battery => match status.charge_percent {
+                Some(charge) => self.threshold_percent >= 100 || charge < self.threshold_percent,
+                None => true,
+            },
+            _ => false,
+        }
+    }
+}
+
+#[cfg(windows)]
+pub use windows_battery::SystemPowerSource as PlatformPowerSource;
+
+#[cfg(target_os = "linux")]
+pub use linux_battery::SysfsPowerSource as PlatformPowerSource;
+
+#[cfg(windows)]
+mod windows_battery {
+    use crate::battery::{PowerSource, PowerStatus};
+    use windows::Win32::System::Power::{GetSystemPowerStatus, SYSTEM_POWER_STATUS};
+
+    const AC_LINE_OFFLINE: u8 = 0;
+    const BATTERY_FLAG_NO_BATTERY: u8 = 128;
+    const BATTERY_PERCENT_UNKNOWN: u8 = 255;
+
+    pub struct SystemPowerSource;
+
+    impl PowerSource for SystemPowerSource {
+        fn status(&self) -> Result<PowerStatus, String> {
+            let mut status = SYSTEM_POWER_STATUS::default();
+            unsafe { GetSystemPowerStatus(&mut status) }
+                .map_err(|e| format!("Failed to get power status. {}", e.message()))?;
+
+            let has_battery = status.BatteryFlag & BATTERY_FLAG_NO_BATTERY == 0;
+
+            Ok(PowerStatus {
+                on_battery: has_battery && status.ACLineStatus == AC_LINE_OFFLINE,
+                charge_percent: if has_battery && status.BatteryLifePercent != BATTERY_PERCENT_UNKNOWN {
+                    Some(status.BatteryLifePercent)
+                } else {
+                    None
+                },
+            })
+        }
+    }
+}
+
+#[cfg(target_os = "linux")]
+mod linux_battery {
+    use crate::battery::{PowerSource, PowerStatus};
+    use std::fs;
+    use std::path::Path;
+
+    const POWER_SUPPLY_DIR: &str = "/sys/class/power_supply";
+
+    pub struct SysfsPowerSource;
+
+    impl PowerSource for SysfsPowerSource {
+        fn status(&self) -> Result<PowerStatus, String> {
+            let entries = fs::read_dir(POWER_SUPPLY_DIR)
+                .map_err(|e| format!("Failed to read {}. {}", POWER_SUPPLY_DIR, e))?;
+
+            let mut mains_online = false;
+            let mut discharging = false;
+            let mut charge_percent = None;
+
+            for entry in entries.flatten() {
+                let path = entry.path();
+                match read_property(&path, "type").as_deref() {
+                    Some("Mains") => {
+                        mains_online |= read_property(&path, "online").as_deref() == Some("1");
+                    }
+                    Some("Battery") => {
+                        discharging |= read_property(&path, "status").as_deref() == Some("Discharging");
+                        if charge_percent.is_none() {
+                            charge_percent = read_property(&path, "capacity").and_then(|c| c.parse().ok());
+                        }
+                    }
+                    _ => {}
+                }
+            }
+
+            Ok(PowerStatus {
+                on_battery: discharging && !mains_online,
+                charge_percent,
+            })
+        }
+    }
+
+    fn read_property(supply: &Path, name: &str) -> Option<String> {
+        fs::read_to_string(supply.join(name))
+            .ok()
+            .map(|value| value.trim().to_string())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::battery::{BatteryMonitor, PowerSource, PowerStatus};
+    use std::cell::Cell;
+    use std::rc::Rc;
+
+    #[derive(Clone, Default)]
+    struct FakePowerSource(Rc<Cell<PowerStatus>>);
+
+    impl PowerSource for FakePowerSource {
+        fn status(&self) -> Result<PowerStatus, String> {
+            Ok(self.0.get())
+        }
+    }
+
+    fn on_battery(charge: u8) -> PowerStatus {
+        PowerStatus {
+            on_battery: true,
+            charge_percent: Some(charge),
+        }
+    }
+
+    #[test]
+    fn test_saving_on_battery() {
+        let source = FakePowerSource::default();
+        let mut monitor = BatteryMonitor::new(source.clone(), 100);
+
+        assert_eq!(Some(PowerStatus::default()), monitor.poll());
+        assert!(!monitor.is_saving());
+        assert_eq!(None, monitor.poll());
+
+        source.0.set(on_battery(100));
+        assert_eq!(Some(on_battery(100)), monitor.poll());
+        assert!(monitor.is_saving());
+    }
+
+    #[test]
+    fn test_saving_below_threshold() {
+        let source = FakePowerSource::default();
+        let mut monitor = BatteryMonitor::new(source.clone(), 30);
+
+        source.0.set(on_battery(50));
+        monitor.poll();
+        assert!(!monitor.is_saving());
+
+        source.0.set(on_battery(29));
+        monitor.poll();
+        assert!(monitor.is_saving());
+
+        source.0.set(PowerStatus {
+            on_battery: false,
+            charge_percent: Some(29),
+        });
+        monitor.poll();
+        assert!(!monitor.is_saving());
+    }
+}
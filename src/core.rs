@@ -0,0 +1,1111 @@
+use crate::audio::PauseReason;
+use crate::battery::PowerStatus;
+use crate::hooks::{HookEvent, Payload};
+use crate::idle::IdleState;
+use crate::ipc::{Command, Response};
+use crate::metrics::METRICS;
+use crate::notifications::{Notice, RateLimiter};
+use crate::power::PowerEvent;
+use crate::scheduler::Scheduler;
+use crate::session::SessionEvent;
+use crate::settings::{BatteryMode, Settings};
+use crate::status::{State, Status};
+use crate::supervisor::RestartGuard;
+use crate::tray::{DeviceItem, MenuAction, TrayIcon};
+use chrono::{DateTime, Utc};
+use log::{debug, error, info, warn};
+use std::collections::HashSet;
+use std::time::{Duration, Instant};
+
+/* consecutive failures after which the device is shown as lost rather than retrying */
+const ERROR_FAILURES: u32 = 5;
+
+/// One-shot timers the front-end runs for the core. A timer is stopped before its event is
+/// delivered.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Timer {
+    /// The next keep-alive ping.
+    Audio,
+    /// The end of a snooze.
+    Snooze,
+}
+
+/// Something that happened outside of the core.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Event {
+    Timer(Timer),
+    /// Outcome of [`Effect::Play`].
+    Played(Result<(), String>),
+    /// The audio output now plays on these devices.
+    DevicesChanged(Vec<String>),
+    /// An output device was connected or disconnected. Lists all connected devices.
+    DevicesConnected(Vec<String>),
+    Menu(MenuAction),
+    Idle(IdleState),
+    Power(PowerEvent),
+    Session(SessionEvent),
+    /// The power status has changed. `saving` tells whether battery saving applies.
+    Battery { status: PowerStatus, saving: bool },
+    /// The user has edited the settings and whether the application starts at login.
+    #[cfg(any(windows, test))]
+    SettingsChanged { settings: Box<Settings>, autostart: bool },
+    /// Whether the application starts at login, found out or changed by the front-end.
+    Autostart(bool),
+    /// The audio output or an event handler of the front-end panicked with this message. The
+    /// output may be left half done and has to be restarted.
+    AudioPanicked(String),
+}
+
+/// Something the front-end has to do.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Effect {
+    /// Play the waveform and report back with [`Event::Played`].
+    Play,
+    StartTimer(Timer, u32),
+    StopTimer(Timer),
+    CloseDevice,
+    ReopenDevice,
+    /// Show the busy tray icon for a moment.
+    Blink,
+    UpdateIcon(TrayIcon),
+    UpdateTip(Vec<TipLine>),
+    /// Fill the devices submenu.
+    UpdateDevices(Vec<DeviceItem>),
+    /// Check or uncheck the start at login menu item.
+    UpdateAutostart(bool),
+    RunHooks(Payload),
+    /// Recreate the audio output for these devices. Empty is the default device.
+    SelectDevices(Vec<String>),
+    /// Write the settings file.
+    SaveSettings(Box<Settings>),
+    /// Let the user edit the settings.
+    ShowSettings,
+    /// Show a desktop notification.
+    Notify(Notice),
+    /// Make the application start at login or not and report back with [`Event::Autostart`].
+    SetAutostart(bool),
+    /// Settings have changed. Recreate the audio output and event sources from them.
+    Reconfigure,
+    /// Drop the audio output and create it anew.
+    RestartAudio,
+    /// Stop and exit with this error so that a service manager can restart the application.
+    Abort(String),
+    Exit,
+}
+
+/// Tooltip content. Front-ends render it with their own strings.
+#[derive(Debug, Clone, PartialEq)]
+pub enum TipLine {
+    State(State),
+    FailedPings(u32),
+    Device(String),
+    LastPing(DateTime<Utc>),
+    OnBattery(Option<u8>),
+    PausedOnBattery,
+    SlowedDownOnBattery,
+}
+
+/// Keep-alive state machine. Knows nothing about windows, devices or threads: it consumes
+/// events and returns effects for the front-end to carry out.
+pub struct Core {
+    settings: Settings,
+    load_settings: fn() -> Result<Settings, String>,
+    scheduler: Scheduler,
+    pause_reasons: HashSet<PauseReason>,
+    /* when the snooze timer fires */
+    snooze_until: Option<Instant>,
+    suspended: bool,
+    failures: u32,
+    last_play: Option<DateTime<Utc>>,
+    devices: Vec<String>,
+    connected: Vec<String>,
+    battery: Option<PowerStatus>,
+    battery_saving: bool,
+    /* unknown until the front-end finds out */
+    autostart: Option<bool>,
+    started: Instant,
+    notices: RateLimiter,
+    /* recovery and resume are only told about if the failure or the pause was */
+    failure_notified: bool,
+    pause_notified: bool,
+    /* what the tray shows and the logged state, to update them only on changes */
+    shown: Option<Shown>,
+    audio_restarts: RestartGuard,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+struct Shown {
+    state: State,
+    icon: TrayIcon,
+    tip: Vec<TipLine>,
+    devices: Vec<DeviceItem>,
+    autostart: Option<bool>,
+}
+
+impl Default for Core {
+    fn default() -> Self {
+        Self::new(Settings::default(), Settings::load)
+    }
+}
+
+impl Core {
+    /// `load_settings` is called on the reload command.
+    pub fn new(settings: Settings, load_settings: fn() -> Result<Settings, String>) -> Self {
+        Self {
+            scheduler: Scheduler::new(&settings),
+            settings,
+            load_settings,
+            pause_reasons: HashSet::new(),
+            snooze_until: None,
+            suspended: false,
+            failures: 0,
+            last_play: None,
+            devices: Vec::new(),
+            connected: Vec::new(),
+            battery: None,
+            battery_saving: false,
+            autostart: None,
+            started: Instant::now(),
+            notices: RateLimiter::default(),
+            failure_notified: false,
+            pause_notified: false,
+            shown: None,
+            audio_restarts: RestartGuard::default(),
+        }
+    }
+
+    pub fn settings(&self) -> &Settings {
+        &self.settings
+    }
+
+    pub fn start(&mut self) -> Vec<Effect> {
+        let mut effects = vec![Effect::RunHooks(Payload::new(HookEvent::Started))];
+        self.schedule_next(&mut effects);
+        self.refresh(&mut effects);
+        effects
+    }
+
+    pub fn stop(&mut self) -> Vec<Effect> {
+        vec![
+            Effect::StopTimer(Timer::Audio),
+            Effect::StopTimer(Timer::Snooze),
+            Effect::CloseDevice,
+            Effect::RunHooks(Payload::new(HookEvent::Stopped)),
+        ]
+    }
+
+    pub fn handle(&mut self, event: Event) -> Vec<Effect> {
+        let mut effects = Vec::new();
+
+        match event {
+            Event::Timer(Timer::Audio) => {
+                if !self.is_paused() && !self.suspended {
+                    effects.extend([Effect::Blink, Effect::Play]);
+                }
+            }
+            Event::Timer(Timer::Snooze) => {
+                debug!("Snooze is over");
+                self.resume(PauseReason::Snooze, &mut effects);
+            }
+            Event::Played(Ok(())) => {
+                if self.failures > 0 {
+                    effects.push(Effect::RunHooks(Payload::new(HookEvent::DeviceRecovered)));
+                    if std::mem::take(&mut self.failure_notified) {
+                        self.notify(Notice::DeviceRecovered, &mut effects);
+                    }
+                }
+                self.failures = 0;
+                self.last_play = Some(Utc::now());
+                METRICS.count_play();
+                self.schedule_next(&mut effects);
+            }
+            Event::Played(Err(e)) => {
+                self.failures += 1;
+                /* each device failure is logged with its fields where it happens */
+                debug!(event = "ping_failed", failures = self.failures, error = e.as_str(); "Ping failed");
+                if self.failures == 1 {
+                    effects.push(Effect::RunHooks(
+                        Payload::new(HookEvent::DeviceLost).with_error(&e),
+                    ));
+                    self.failure_notified |= self.notify(Notice::DeviceLost(e.clone()), &mut effects);
+                }
+                if self.failures == ERROR_FAILURES {
+                    self.failure_notified |= self.notify(Notice::KeepsFailing(self.failures), &mut effects);
+                }
+                effects.push(Effect::RunHooks(
+                    Payload::new(HookEvent::PlayFailed).with_error(&e),
+                ));
+                self.schedule_next(&mut effects);
+            }
+            Event::DevicesChanged(devices) => {
+                self.devices = devices;
+            }
+            Event::DevicesConnected(devices) => {
+                debug!("Connected devices: {:?}", devices);
+                self.connected = devices;
+                /* a lost device may be back */
+                if self.failures > 0 {
+                    effects.push(Effect::ReopenDevice);
+                }
+            }
+            Event::Menu(MenuAction::ToggleDevice(device)) => {
+                self.toggle_device(device, &mut effects);
+            }
+            Event::Menu(MenuAction::ToggleAutostart) => {
+                effects.push(Effect::SetAutostart(self.autostart != Some(true)));
+            }
+            Event::Menu(MenuAction::Settings) => effects.push(Effect::ShowSettings),
+            Event::Menu(MenuAction::Exit) => {
+                debug!("Exiting application");
+                effects = self.stop();
+                effects.push(Effect::Exit);
+            }
+            Event::Idle(IdleState::Idle) => {
+                debug!("User is idle");
+                self.pause(PauseReason::UserIdle, &mut effects);
+            }
+            Event::Idle(IdleState::Active) => {
+                debug!("User is back");
+                self.resume(PauseReason::UserIdle, &mut effects);
+            }
+            Event::Power(PowerEvent::Suspend) => {
+                debug!("System is going to sleep");
+                self.suspended = true;
+                effects.extend([Effect::StopTimer(Timer::Audio), Effect::CloseDevice]);
+            }
+            Event::Power(PowerEvent::Resume) => {
+                debug!("System resumed");
+                self.suspended = false;
+                /* if the device is not ready yet it is reopened on the next ping */
+                effects.push(Effect::ReopenDevice);
+                self.schedule_next(&mut effects);
+            }
+            Event::Session(SessionEvent::Locked) => {
+                debug!("Session locked");
+                self.pause(PauseReason::SessionLocked, &mut effects);
+            }
+            Event::Session(SessionEvent::Unlocked) => {
+                debug!("Session unlocked");
+                self.resume(PauseReason::SessionLocked, &mut effects);
+            }
+            Event::Battery { status, saving } => {
+                self.on_battery(status, saving, &mut effects);
+            }
+            #[cfg(any(windows, test))]
+            Event::SettingsChanged { settings, autostart } => match settings.validate() {
+                Ok(()) => {
+                    effects.push(Effect::SaveSettings(settings.clone()));
+                    effects.extend(self.reload(*settings));
+                    if self.autostart != Some(autostart) {
+                        effects.push(Effect::SetAutostart(autostart));
+                    }
+                }
+                Err(e) => warn!("{}", e),
+            },
+            Event::Autostart(enabled) => {
+                self.autostart = Some(enabled);
+            }
+            Event::AudioPanicked(message) => {
+                self.on_audio_panic(message, &mut effects);
+            }
+        }
+
+        self.refresh(&mut effects);
+        effects
+    }
+
+    /// Executes a command from another process and returns the response to it.
+    pub fn execute(&mut self, command: Command) -> (Response, Vec<Effect>) {
+        let mut effects = Vec::new();
+
+        let result = match command {
+            Command::Pause => {
+                self.pause(PauseReason::User, &mut effects);
+                Ok(String::new())
+            }
+            Command::Resume => {
+                effects.push(Effect::StopTimer(Timer::Snooze));
+                self.resume(PauseReason::Snooze, &mut effects);
+                self.resume(PauseReason::User, &mut effects);
+                Ok(String::new())
+            }
+            Command::Snooze(minutes) => self.snooze(minutes, &mut effects).map(|_| String::new()),
+            Command::Period(period_ms) => self
+                .set_period_ms(period_ms, &mut effects)
+                .map(|_| String::new()),
+            Command::Status => Ok(self.status().to_json()),
+            Command::Stop => {
+                effects = self.stop();
+                effects.push(Effect::Exit);
+                Ok(String::new())
+            }
+            Command::Reload => (self.load_settings)().map(|settings| {
+                effects = self.reload(settings);
+                String::new()
+            }),
+        };
+
+        self.refresh(&mut effects);
+
+        match result {
+            Ok(text) => (Response::Ok(text), effects),
+            Err(e) => {
+                warn!("{}", e);
+                (Response::Error(e), effects)
+            }
+        }
+    }
+
+    pub fn state(&self) -> State {
+        if self.is_paused() {
+            State::Paused
+        } else if self.suspended {
+            State::Suspended
+        } else if self.failures > 0 {
+            State::Failing
+        } else {
+            State::Active
+        }
+    }
+
+    pub fn icon(&self) -> TrayIcon {
+        match self.state() {
+            State::Active => TrayIcon::Active,
+            State::Paused | State::Suspended => TrayIcon::Paused,
+            State::Failing if self.failures >= ERROR_FAILURES => TrayIcon::Error,
+            State::Failing => TrayIcon::Degraded,
+        }
+    }
+
+    pub fn status(&self) -> Status {
+        Status {
+            state: self.state(),
+            devices: self.devices.clone(),
+            period_ms: self.scheduler.period_ms(),
+            last_play: self.last_play,
+            consecutive_failures: self.failures,
+            uptime_secs: self.started.elapsed().as_secs(),
+            pause_reasons: self.pause_reasons.iter().copied().collect(),
+            autostart: self.autostart,
+        }
+    }
+
+    fn is_paused(&self) -> bool {
+        !self.pause_reasons.is_empty()
+    }
+
+    /// Restarts keep-alive with new settings. A manual pause, a snooze, a locked session and a
+    /// system going to sleep survive the reload.
+    fn reload(&mut self, settings: Settings) -> Vec<Effect> {
+        debug!("Reloading");
+
+        /* the sources recreated on Reconfigure report idleness and the battery again, but not these */
+        let pause_reasons: Vec<PauseReason> = [PauseReason::User, PauseReason::SessionLocked, PauseReason::Snooze]
+            .into_iter()
+            .filter(|reason| self.pause_reasons.contains(reason))
+            .collect();
+        let mut effects = self.stop();
+
+        *self = Self {
+            snooze_until: self.snooze_until,
+            suspended: self.suspended,
+            started: self.started,
+            devices: std::mem::take(&mut self.devices),
+            connected: std::mem::take(&mut self.connected),
+            autostart: self.autostart,
+            last_play: self.last_play,
+            shown: self.shown.take(),
+            notices: std::mem::take(&mut self.notices),
+            /* or reloading would hide a crash loop */
+            audio_restarts: std::mem::take(&mut self.audio_restarts),
+            ..Self::new(settings, self.load_settings)
+        };
+        self.pause_reasons.extend(pause_reasons);
+
+        effects.push(Effect::Reconfigure);
+        effects.extend(self.start());
+        if self.pause_reasons.contains(&PauseReason::Snooze)
+            && let Some(until) = self.snooze_until
+        {
+            let remaining_ms = until.saturating_duration_since(Instant::now()).as_millis();
+            effects.push(Effect::StartTimer(Timer::Snooze, remaining_ms as u32));
+        }
+        effects
+    }
+
+    /// Stops pinging until all pause reasons are withdrawn.
+    fn pause(&mut self, reason: PauseReason, effects: &mut Vec<Effect>) {
+        if self.pause_reasons.insert(reason) && self.pause_reasons.len() == 1 {
+            debug!("Pausing ({:?})", reason);
+            effects.push(Effect::StopTimer(Timer::Audio));
+            effects.push(Effect::RunHooks(Payload::new(HookEvent::Paused).with_reason(reason)));
+            /* the user knows about their own pause */
+            self.pause_notified = reason != PauseReason::User && self.notify(Notice::Paused(reason), effects);
+        }
+    }
+
+    fn resume(&mut self, reason: PauseReason, effects: &mut Vec<Effect>) {
+        if self.pause_reasons.remove(&reason) && self.pause_reasons.is_empty() {
+            debug!("Resuming ({:?})", reason);
+            effects.push(Effect::RunHooks(Payload::new(HookEvent::Resumed).with_reason(reason)));
+            if std::mem::take(&mut self.pause_notified) {
+                self.notify(Notice::Resumed, effects);
+            }
+            self.schedule_next(effects);
+        }
+    }
+
+    fn snooze(&mut self, minutes: u32, effects: &mut Vec<Effect>) -> Result<(), String> {
+        if minutes == 0 {
+            Err("Snooze must be at least one minute")?
+        }
+        debug!("Snoozing for {} min", minutes);
+
+        let duration_ms = minutes.saturating_mul(60_000);
+        self.pause(PauseReason::Snooze, effects);
+        self.snooze_until = Some(Instant::now() + Duration::from_millis(duration_ms as u64));
+        effects.push(Effect::StartTimer(Timer::Snooze, duration_ms));
+        Ok(())
+    }
+
+    /// Changes the period until the next reload. The battery period still takes over on battery.
+    fn set_period_ms(&mut self, period_ms: u32, effects: &mut Vec<Effect>) -> Result<(), String> {
+        let settings = Settings {
+            period_ms,
+            ..self.settings.clone()
+        };
+        settings.validate()?;
+        self.settings = settings;
+
+        if !self.is_slowed_down() {
+            self.change_period(period_ms, effects);
+        }
+        Ok(())
+    }
+
+    fn on_battery(&mut self, status: PowerStatus, saving: bool, effects: &mut Vec<Effect>) {
+        self.battery = Some(status);
+
+        if saving != self.battery_saving {
+            debug!("Battery saving {}", if saving { "on" } else { "off" });
+            self.battery_saving = saving;
+
+            match (self.settings.battery_mode, saving) {
+                (BatteryMode::Pause, true) => self.pause(PauseReason::OnBattery, effects),
+                (BatteryMode::Pause, false) => self.resume(PauseReason::OnBattery, effects),
+                (BatteryMode::SlowDown, true) => {
+                    self.change_period(self.settings.battery_period_ms, effects)
+                }
+                (BatteryMode::SlowDown, false) => self.change_period(self.settings.period_ms, effects),
+                (BatteryMode::Ignore, _) => {}
+            }
+        }
+    }
+
+    fn is_slowed_down(&self) -> bool {
+        self.battery_saving && self.settings.battery_mode == BatteryMode::SlowDown
+    }
+
+    /// Changes the keep-alive period starting from the next ping.
+    fn change_period(&mut self, period_ms: u32, effects: &mut Vec<Effect>) {
+        self.scheduler.set_period_ms(period_ms);
+        self.schedule_next(effects);
+    }
+
+    /// Shows the notice unless notifications are off or have been shown too often. Returns
+    /// whether it is shown.
+    fn notify(&mut self, notice: Notice, effects: &mut Vec<Effect>) -> bool {
+        if !self.settings.notifications || !self.notices.allow(&notice, Instant::now()) {
+            return false;
+        }
+        effects.push(Effect::Notify(notice));
+        true
+    }
+
+    /// Restarts the audio output after a panic unless it keeps panicking.
+    fn on_audio_panic(&mut self, message: String, effects: &mut Vec<Effect>) {
+        if !self.audio_restarts.allow(Instant::now()) {
+            error!(event = "crash_loop", error = message.as_str(); "Audio output keeps crashing, giving up");
+            effects.extend(self.stop());
+            effects.push(Effect::Abort(format!("Audio output keeps crashing. {}", message)));
+            return;
+        }
+
+        warn!(event = "audio_restart", error = message.as_str(); "Restarting audio output after a crash");
+        effects.extend([Effect::StopTimer(Timer::Audio), Effect::RestartAudio]);
+        self.notify(Notice::AudioRestarted, effects);
+        self.schedule_next(effects);
+    }
+
+    /// Re-arms the audio timer with the next delay computed by the scheduler.
+    fn schedule_next(&mut self, effects: &mut Vec<Effect>) {
+        if self.is_paused() || self.suspended {
+            return;
+        }
+
+        let delay = self.scheduler.next_delay_ms();
+        effects.push(Effect::StartTimer(Timer::Audio, delay));
+    }
+
+    /// Selects or deselects a device and saves the selection. Selecting the default device or
+    /// deselecting the last one falls back to the default device.
+    fn toggle_device(&mut self, device: Option<String>, effects: &mut Vec<Effect>) {
+        let mut devices = self.settings.devices.clone();
+        match device {
+            None => devices.clear(),
+            Some(name) => match devices.iter().position(|selected| *selected == name) {
+                Some(i) => {
+                    devices.remove(i);
+                }
+                None => devices.push(name),
+            },
+        }
+        if devices == self.settings.devices {
+            return;
+        }
+
+        debug!("Selecting devices {:?}", devices);
+        self.settings.devices = devices.clone();
+        effects.push(Effect::SaveSettings(Box::new(self.settings.clone())));
+        effects.push(Effect::SelectDevices(devices));
+    }
+
+    /// The default device, then the connected devices, then the selected devices that are not
+    /// connected.
+    fn device_items(&self) -> Vec<DeviceItem> {
+        let selected = &self.settings.devices;
+        let missing = selected.iter().filter(|name| !self.connected.contains(name));
+
+        let mut items = vec![DeviceItem {
+            name: None,
+            checked: selected.is_empty(),
+        }];
+        items.extend(self.connected.iter().chain(missing).map(|name| DeviceItem {
+            name: Some(name.clone()),
+            checked: selected.contains(name),
+        }));
+        items
+    }
+
+    fn tip(&self) -> Vec<TipLine> {
+        let mut lines = vec![TipLine::State(self.state())];
+        if self.failures > 0 {
+            lines.push(TipLine::FailedPings(self.failures));
+        }
+        lines.extend(self.devices.iter().cloned().map(TipLine::Device));
+        if let Some(last_play) = self.last_play {
+            lines.push(TipLine::LastPing(last_play));
+        }
+
+        if let Some(status) = self.battery
+            && status.on_battery
+        {
+            lines.push(TipLine::OnBattery(status.charge_percent));
+
+            if self.battery_saving {
+                match self.settings.battery_mode {
+                    BatteryMode::Pause => lines.push(TipLine::PausedOnBattery),
+                    BatteryMode::SlowDown => lines.push(TipLine::SlowedDownOnBattery),
+                    BatteryMode::Ignore => {}
+                }
+            }
+        }
+
+        lines
+    }
+
+    /// Publishes the state and updates the tray if it has changed.
+    fn refresh(&mut self, effects: &mut Vec<Effect>) {
+        METRICS.set_state(self.state());
+
+        let shown = Shown {
+            state: self.state(),
+            icon: self.icon(),
+            tip: self.tip(),
+            devices: self.device_items(),
+            autostart: self.autostart,
+        };
+        let previous = self.shown.as_ref();
+        if previous.map(|previous| previous.state) != Some(shown.state) {
+            info!(state = shown.state.as_str(); "Keep-alive is {}", shown.state.as_str());
+        }
+        if previous.map(|previous| previous.icon) != Some(shown.icon) {
+            effects.push(Effect::UpdateIcon(shown.icon));
+        }
+        if previous.map(|previous| &previous.tip) != Some(&shown.tip) {
+            effects.push(Effect::UpdateTip(shown.tip.clone()));
+        }
+        if previous.map(|previous| &previous.devices) != Some(&shown.devices) {
+            effects.push(Effect::UpdateDevices(shown.devices.clone()));
+        }
+        if let Some(autostart) = shown.autostart
+            && previous.and_then(|previous| previous.autostart) != Some(autostart)
+        {
+            effects.push(Effect::UpdateAutostart(autostart));
+        }
+        self.shown = Some(shown);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::audio::PauseReason;
+    use crate::battery::PowerStatus;
+    use crate::core::{Core, Effect, Event, TipLine, Timer, ERROR_FAILURES};
+    use crate::hooks::HookEvent;
+    use crate::ipc::{Command, Response};
+    use crate::notifications::Notice;
+    use crate::power::PowerEvent;
+    use crate::session::SessionEvent;
+    use crate::settings::{BatteryMode, Settings};
+    use crate::status::State;
+    use crate::supervisor::MAX_RESTARTS;
+    use crate::tray::{DeviceItem, MenuAction, TrayIcon};
+
+    fn settings() -> Settings {
+        Settings {
+            period_ms: 5000,
+            jitter_percent: 0,
+            ..Settings::default()
+        }
+    }
+
+    fn core(settings: Settings) -> Core {
+        Core::new(settings, || Err("No settings file".to_string()))
+    }
+
+    fn hooks(effects: &[Effect]) -> Vec<HookEvent> {
+        effects
+            .iter()
+            .filter_map(|effect| match effect {
+                Effect::RunHooks(payload) => Some(payload.event),
+                _ => None,
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_start() {
+        let mut core = core(settings());
+        let effects = core.start();
+        assert!(effects.contains(&Effect::StartTimer(Timer::Audio, 5000)));
+        assert_eq!(vec![HookEvent::Started], hooks(&effects));
+        assert_eq!(State::Active, core.state());
+    }
+
+    #[test]
+    fn test_tick_plays_and_reschedules() {
+        let mut core = core(settings());
+        core.start();
+
+        assert_eq!(vec![Effect::Blink, Effect::Play], core.handle(Event::Timer(Timer::Audio)));
+
+        let effects = core.handle(Event::Played(Ok(())));
+        assert!(effects.contains(&Effect::StartTimer(Timer::Audio, 5000)));
+        assert!(core.status().last_play.is_some());
+    }
+
+    #[test]
+    fn test_play_failures() {
+        let mut core = core(settings());
+        core.start();
+
+        let effects = core.handle(Event::Played(Err("Device is gone".to_string())));
+        assert_eq!(vec![HookEvent::DeviceLost, HookEvent::PlayFailed], hooks(&effects));
+        assert!(effects.contains(&Effect::StartTimer(Timer::Audio, 5000)));
+
+        let effects = core.handle(Event::Played(Err("Device is gone".to_string())));
+        assert_eq!(vec![HookEvent::PlayFailed], hooks(&effects));
+        assert_eq!(State::Failing, core.state());
+        assert_eq!(2, core.status().consecutive_failures);
+
+        let effects = core.handle(Event::Played(Ok(())));
+        assert_eq!(vec![HookEvent::DeviceRecovered], hooks(&effects));
+        assert_eq!(State::Active, core.state());
+    }
+
+    #[test]
+    fn test_tray() {
+        let mut core = core(settings());
+        let effects = core.handle(Event::DevicesChanged(vec!["Speakers".to_string()]));
+        assert!(effects.contains(&Effect::UpdateIcon(TrayIcon::Active)));
+        assert!(effects.contains(&Effect::UpdateTip(vec![
+            TipLine::State(State::Active),
+            TipLine::Device("Speakers".to_string()),
+        ])));
+
+        let effects = core.handle(Event::Played(Err("Device is gone".to_string())));
+        assert!(effects.contains(&Effect::UpdateIcon(TrayIcon::Degraded)));
+        assert!(effects.contains(&Effect::UpdateTip(vec![
+            TipLine::State(State::Failing),
+            TipLine::FailedPings(1),
+            TipLine::Device("Speakers".to_string()),
+        ])));
+
+        for _ in 1..4 {
+            let effects = core.handle(Event::Played(Err("Device is gone".to_string())));
+            assert!(!effects.iter().any(|effect| matches!(effect, Effect::UpdateIcon(_))));
+        }
+        let effects = core.handle(Event::Played(Err("Device is gone".to_string())));
+        assert!(effects.contains(&Effect::UpdateIcon(TrayIcon::Error)));
+
+        let effects = core.handle(Event::Played(Ok(())));
+        assert!(effects.contains(&Effect::UpdateIcon(TrayIcon::Active)));
+        let last_play = core.status().last_play.unwrap();
+        assert!(effects.contains(&Effect::UpdateTip(vec![
+            TipLine::State(State::Active),
+            TipLine::Device("Speakers".to_string()),
+            TipLine::LastPing(last_play),
+        ])));
+
+        let (_, effects) = core.execute(Command::Pause);
+        assert!(effects.contains(&Effect::UpdateIcon(TrayIcon::Paused)));
+    }
+
+    #[test]
+    fn test_devices_menu() {
+        let mut core = core(settings());
+        core.start();
+
+        let effects = core.handle(Event::DevicesConnected(vec!["Speakers".to_string()]));
+        assert!(effects.contains(&Effect::UpdateDevices(vec![
+            DeviceItem {
+                name: None,
+                checked: true,
+            },
+            DeviceItem {
+                name: Some("Speakers".to_string()),
+                checked: false,
+            },
+        ])));
+
+        let effects = core.handle(Event::Menu(MenuAction::ToggleDevice(Some("Speakers".to_string()))));
+        assert!(effects.contains(&Effect::SelectDevices(vec!["Speakers".to_string()])));
+        assert!(matches!(&effects[0], Effect::SaveSettings(settings) if settings.devices == ["Speakers"]));
+        assert!(effects.contains(&Effect::UpdateDevices(vec![
+            DeviceItem {
+                name: None,
+                checked: false,
+            },
+            DeviceItem {
+                name: Some("Speakers".to_string()),
+                checked: true,
+            },
+        ])));
+
+        /* a selected device stays in the menu while disconnected */
+        let effects = core.handle(Event::DevicesConnected(Vec::new()));
+        assert!(!effects.iter().any(|effect| matches!(effect, Effect::UpdateDevices(_))));
+
+        let effects = core.handle(Event::Menu(MenuAction::ToggleDevice(None)));
+        assert!(effects.contains(&Effect::SelectDevices(Vec::new())));
+        assert!(core.settings().devices.is_empty());
+        assert!(core.handle(Event::Menu(MenuAction::ToggleDevice(None))).is_empty());
+    }
+
+    #[test]
+    fn test_notifications() {
+        let mut quiet = core(Settings {
+            notifications: false,
+            ..settings()
+        });
+        quiet.start();
+        let effects = quiet.handle(Event::Played(Err("Device is gone".to_string())));
+        assert!(!effects.iter().any(|effect| matches!(effect, Effect::Notify(_))));
+
+        let mut core = core(settings());
+        core.start();
+
+        let effects = core.handle(Event::Played(Err("Device is gone".to_string())));
+        assert!(effects.contains(&Effect::Notify(Notice::DeviceLost("Device is gone".to_string()))));
+        for _ in 1..ERROR_FAILURES - 1 {
+            core.handle(Event::Played(Err("Device is gone".to_string())));
+        }
+        let effects = core.handle(Event::Played(Err("Device is gone".to_string())));
+        assert!(effects.contains(&Effect::Notify(Notice::KeepsFailing(ERROR_FAILURES))));
+        let effects = core.handle(Event::Played(Ok(())));
+        assert!(effects.contains(&Effect::Notify(Notice::DeviceRecovered)));
+
+        /* a manual pause is not announced, and neither is the resume */
+        let (_, effects) = core.execute(Command::Pause);
+        assert!(!effects.iter().any(|effect| matches!(effect, Effect::Notify(_))));
+        let (_, effects) = core.execute(Command::Resume);
+        assert!(!effects.iter().any(|effect| matches!(effect, Effect::Notify(_))));
+    }
+
+    #[test]
+    fn test_pause_reasons() {
+        let mut core = core(settings());
+        core.start();
+
+        let (response, effects) = core.execute(Command::Pause);
+        assert_eq!(Response::Ok(String::new()), response);
+        assert!(effects.contains(&Effect::StopTimer(Timer::Audio)));
+        assert_eq!(vec![HookEvent::Paused], hooks(&effects));
+
+        /* another reason does not pause twice */
+        assert!(core.handle(Event::Idle(crate::idle::IdleState::Idle)).is_empty());
+        assert!(core.handle(Event::Timer(Timer::Audio)).is_empty());
+
+        let (_, effects) = core.execute(Command::Resume);
+        assert!(hooks(&effects).is_empty());
+        assert_eq!(State::Paused, core.state());
+
+        let effects = core.handle(Event::Idle(crate::idle::IdleState::Active));
+        assert_eq!(vec![HookEvent::Resumed], hooks(&effects));
+        assert!(effects.contains(&Effect::StartTimer(Timer::Audio, 5000)));
+        assert_eq!(State::Active, core.state());
+    }
+
+    #[test]
+    fn test_snooze() {
+        let mut core = core(settings());
+        core.start();
+
+        let (_, effects) = core.execute(Command::Snooze(15));
+        assert!(effects.contains(&Effect::StartTimer(Timer::Snooze, 15 * 60_000)));
+        assert_eq!(vec![PauseReason::Snooze], core.status().pause_reasons);
+
+        let effects = core.handle(Event::Timer(Timer::Snooze));
+        assert!(effects.contains(&Effect::StartTimer(Timer::Audio, 5000)));
+        assert_eq!(State::Active, core.state());
+
+        assert!(matches!(core.execute(Command::Snooze(0)).0, Response::Error(_)));
+    }
+
+    #[test]
+    fn test_period() {
+        let mut core = core(settings());
+        core.start();
+
+        let (response, effects) = core.execute(Command::Period(10000));
+        assert_eq!(Response::Ok(String::new()), response);
+        assert_eq!(vec![Effect::StartTimer(Timer::Audio, 10000)], effects);
+        assert_eq!(10000, core.status().period_ms);
+
+        assert!(matches!(core.execute(Command::Period(10)).0, Response::Error(_)));
+        assert_eq!(10000, core.status().period_ms);
+    }
+
+    #[test]
+    fn test_suspend_resume() {
+        let mut core = core(settings());
+        core.start();
+
+        let effects = core.handle(Event::Power(PowerEvent::Suspend));
+        assert_eq!(
+            vec![Effect::StopTimer(Timer::Audio), Effect::CloseDevice],
+            effects[..2]
+        );
+        assert_eq!(State::Suspended, core.state());
+
+        let effects = core.handle(Event::Power(PowerEvent::Resume));
+        assert_eq!(
+            vec![Effect::ReopenDevice, Effect::StartTimer(Timer::Audio, 5000)],
+            effects[..2]
+        );
+        assert_eq!(State::Active, core.state());
+    }
+
+    #[test]
+    fn test_battery() {
+        let mut core = core(Settings {
+            battery_mode: BatteryMode::SlowDown,
+            battery_period_ms: 60000,
+            ..settings()
+        });
+        core.start();
+
+        let status = PowerStatus {
+            on_battery: true,
+            charge_percent: Some(40),
+        };
+        let effects = core.handle(Event::Battery { status, saving: true });
+        assert!(effects.contains(&Effect::StartTimer(Timer::Audio, 60000)));
+        assert!(effects.contains(&Effect::UpdateTip(vec![
+            TipLine::State(State::Active),
+            TipLine::OnBattery(Some(40)),
+            TipLine::SlowedDownOnBattery,
+        ])));
+
+        /* a manual period change waits for the mains */
+        let (_, effects) = core.execute(Command::Period(10000));
+        assert!(effects.is_empty());
+
+        let effects = core.handle(Event::Battery {
+            status: PowerStatus::default(),
+            saving: false,
+        });
+        assert!(effects.contains(&Effect::StartTimer(Timer::Audio, 10000)));
+        assert!(effects.contains(&Effect::UpdateTip(vec![TipLine::State(State::Active)])));
+    }
+
+    #[test]
+    fn test_reload_keeps_user_pause() {
+        let mut core = Core::new(settings(), || {
+            Ok(Settings {
+                period_ms: 20000,
+                ..Settings::default()
+            })
+        });
+        core.start();
+        core.execute(Command::Pause);
+
+        let (response, effects) = core.execute(Command::Reload);
+        assert_eq!(Response::Ok(String::new()), response);
+        assert!(effects.contains(&Effect::Reconfigure));
+        assert_eq!(20000, core.settings().period_ms);
+        assert_eq!(vec![PauseReason::User], core.status().pause_reasons);
+    }
+
+    #[test]
+    fn test_reload_keeps_runtime_state() {
+        let mut core = core(settings());
+        core.start();
+        core.execute(Command::Snooze(15));
+        core.handle(Event::Session(SessionEvent::Locked));
+        core.handle(Event::Power(PowerEvent::Suspend));
+
+        let effects = core.handle(Event::SettingsChanged {
+            settings: Box::new(settings()),
+            autostart: false,
+        });
+        assert!(effects.contains(&Effect::Reconfigure));
+        assert!(!effects.contains(&Effect::StartTimer(Timer::Audio, 5000)));
+        assert!(effects.iter().any(|effect| matches!(
+            effect,
+            Effect::StartTimer(Timer::Snooze, ms) if (14 * 60_000..=15 * 60_000).contains(ms)
+        )));
+        let mut pause_reasons = core.status().pause_reasons;
+        pause_reasons.sort_by_key(|reason| *reason as u8);
+        assert_eq!(vec![PauseReason::SessionLocked, PauseReason::Snooze], pause_reasons);
+
+        core.handle(Event::Session(SessionEvent::Unlocked));
+        let effects = core.handle(Event::Timer(Timer::Snooze));
+        assert!(!effects.contains(&Effect::StartTimer(Timer::Audio, 5000)));
+        assert_eq!(State::Suspended, core.state());
+
+        let effects = core.handle(Event::Power(PowerEvent::Resume));
+        assert!(effects.contains(&Effect::StartTimer(Timer::Audio, 5000)));
+    }
+
+    #[test]
+    fn test_settings_changed() {
+        let mut core = core(settings());
+        core.start();
+        core.execute(Command::Pause);
+
+        let changed = Settings {
+            period_ms: 20000,
+            ..settings()
+        };
+        core.handle(Event::Autostart(false));
+        let effects = core.handle(Event::SettingsChanged {
+            settings: Box::new(changed.clone()),
+            autostart: true,
+        });
+        assert_eq!(Effect::SaveSettings(Box::new(changed.clone())), effects[0]);
+        assert!(effects.contains(&Effect::Reconfigure));
+        assert!(effects.contains(&Effect::SetAutostart(true)));
+        assert_eq!(20000, core.settings().period_ms);
+        assert_eq!(State::Paused, core.state());
+
+        /* autostart is only changed when the user changed it */
+        core.handle(Event::Autostart(true));
+        let effects = core.handle(Event::SettingsChanged {
+            settings: Box::new(changed),
+            autostart: true,
+        });
+        assert!(!effects.iter().any(|effect| matches!(effect, Effect::SetAutostart(_))));
+
+        let invalid = Settings {
+            period_ms: 10,
+            ..settings()
+        };
+        let effects = core.handle(Event::SettingsChanged {
+            settings: Box::new(invalid),
+            autostart: false,
+        });
+        assert!(effects.is_empty());
+        assert_eq!(20000, core.settings().period_ms);
+    }
+
+    #[test]
+    fn test_reload_error() {
+        let mut core = core(settings());
+        core.start();
+
+        let (response, effects) = core.execute(Command::Reload);
+        assert_eq!(Response::Error("No settings file".to_string()), response);
+        assert!(effects.is_empty());
+    }
+
+    #[test]
+    fn test_autostart() {
+        let mut core = core(settings());
+        let effects = core.start();
+        assert!(!effects.iter().any(|effect| matches!(effect, Effect::UpdateAutostart(_))));
+        assert_eq!(None, core.status().autostart);
+
+        assert_eq!(vec![Effect::UpdateAutostart(false)], core.handle(Event::Autostart(false)));
+        assert!(core.handle(Event::Autostart(false)).is_empty());
+
+        let effects = core.handle(Event::Menu(MenuAction::ToggleAutostart));
+        assert_eq!(vec![Effect::SetAutostart(true)], effects);
+
+        assert_eq!(vec![Effect::UpdateAutostart(true)], core.handle(Event::Autostart(true)));
+        assert_eq!(Some(true), core.status().autostart);
+        assert_eq!(
+            vec![Effect::SetAutostart(false)],
+            core.handle(Event::Menu(MenuAction::ToggleAutostart))
+        );
+    }
+
+    #[test]
+    fn test_exit() {
+        let mut core = core(settings());
+        core.start();
+
+        let effects = core.handle(Event::Menu(MenuAction::Exit));
+        assert_eq!(Some(&Effect::Exit), effects.last());
+        assert!(effects.contains(&Effect::CloseDevice));
+        assert_eq!(vec![HookEvent::Stopped], hooks(&effects));
+    }
+
+    #[test]
+    fn test_audio_panic() {
+        let mut core = core(settings());
+        core.start();
+
+        for _ in 0..MAX_RESTARTS {
+            let effects = core.handle(Event::AudioPanicked("Device is gone".to_string()));
+            assert!(effects.contains(&Effect::RestartAudio));
+            assert!(effects.contains(&Effect::StartTimer(Timer::Audio, 5000)));
+        }
+
+        let effects = core.handle(Event::AudioPanicked("Device is gone".to_string()));
+        assert_eq!(
+            Some(&Effect::Abort("Audio output keeps crashing. Device is gone".to_string())),
+            effects.last()
+        );
+        assert!(!effects.contains(&Effect::RestartAudio));
+    }
+
+    #[test]
+    fn test_audio_panic_after_reload() {
+        let mut core = Core::new(settings(), || Ok(settings()));
+        core.start();
+
+        for _ in 0..MAX_RESTARTS {
+            core.handle(Event::AudioPanicked("Device is gone".to_string()));
+        }
+        let (response, _) = core.execute(Command::Reload);
+        assert_eq!(Response::Ok(String::new()), response);
+
+        let effects = core.handle(Event::AudioPanicked("Device is gone".to_string()));
+        assert_eq!(
+            Some(&Effect::Abort("Audio output keeps crashing. Device is gone".to_string())),
+            effects.last()
+        );
+    }
+}
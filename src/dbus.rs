@@ -0,0 +1,285 @@
+use crate::ipc::{Command, Request, Response};
+use crate::status::{State, Status};
+use log::{debug, warn};
+use std::sync::mpsc::{channel, Receiver, Sender};
+use std::sync::{Mutex, MutexGuard, PoisonError};
+use std::time::Duration;
+use zbus::blocking::connection::Builder;
+use zbus::blocking::Connection;
+use zbus::fdo;
+use zbus::interface;
+use zbus::object_server::SignalEmitter;
+
+pub const BUS_NAME: &str = "io.github.KeepAudioAwake";
+pub const OBJECT_PATH: &str = "/io/github/KeepAudioAwake";
+
+const RESPONSE_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Object exported on the session bus. Method calls are forwarded to the application
+/// thread, properties mirror the last published status.
+struct KeepAudioAwake {
+    requests: Sender<Request>,
+    wake: Box<dyn Fn() + Send + Sync>,
+    /* a method call holds a shared borrow of the object until the application replies, so the
+    application thread updates the properties through a shared borrow too */
+    properties: Mutex<Properties>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+struct Properties {
+    state: State,
+    period_ms: u32,
+    devices: Vec<String>,
+}
+
+impl KeepAudioAwake {
+    /// Waits for the reply off the bus executor, which takes one message loop iteration.
+    async fn execute(&self, command: Command) -> fdo::Result<()> {
+        debug!("D-Bus command: {:?}", command);
+
+        let (request, response) = Request::new(command);
+        self.requests
+            .send(request)
+            .map_err(|_| fdo::Error::Failed("Application is not running".to_string()))?;
+        (self.wake)();
+
+        match blocking::unblock(move || response.recv_timeout(RESPONSE_TIMEOUT)).await {
+            Ok(Response::Ok(_)) => Ok(()),
+            Ok(Response::Error(e)) => Err(fdo::Error::Failed(e)),
+            Err(_) => Err(fdo::Error::Failed("No response from the application".to_string())),
+        }
+    }
+
+    fn properties(&self) -> MutexGuard<'_, Properties> {
+        self.properties.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+}
+
+#[interface(name = "io.github.KeepAudioAwake1")]
+impl KeepAudioAwake {
+    async fn pause(&self) -> fdo::Result<()> {
+        self.execute(Command::Pause).await
+    }
+
+    async fn resume(&self) -> fdo::Result<()> {
+        self.execute(Command::Resume).await
+    }
+
+    async fn snooze(&self, minutes: u32) -> fdo::Result<()> {
+        self.execute(Command::Snooze(minutes)).await
+    }
+
+    async fn reload(&self) -> fdo::Result<()> {
+        self.execute(Command::Reload).await
+    }
+
+    #[zbus(property)]
+    fn state(&self) -> String {
+        self.properties().state.as_str().to_string()
+    }
+
+    /// Keep-alive period in milliseconds.
+    #[zbus(property)]
+    fn period(&self) -> u32 {
+        self.properties().period_ms
+    }
+
+    #[zbus(property)]
+    fn devices(&self) -> Vec<String> {
+        self.properties().devices.clone()
+    }
+
+    #[zbus(signal, name = "StateChanged")]
+    async fn emit_state_changed(emitter: &SignalEmitter<'_>, state: &str) -> zbus::Result<()>;
+}
+
+/// Owns the well-known name on the session bus for the lifetime of the application.
+pub struct DbusService {
+    connection: Connection,
+}
+
+impl DbusService {
+    /// Exports the object and requests the bus name. Method calls are queued to the returned
+    /// receiver, and `wake` is called after each one.
+    pub fn start<F>(wake: F) -> Result<(Self, Receiver<Request>), String>
+    where
+        F: Fn() + Send + Sync + 'static,
+    {
+        let builder = Builder::session().map_err(|e| format!("Failed to start D-Bus service. {}", e))?;
+        Self::start_on(builder, wake)
+    }
+
+    fn start_on<F>(builder: Builder<'static>, wake: F) -> Result<(Self, Receiver<Request>), String>
+    where
+        F: Fn() + Send + Sync + 'static,
+    {
+        let (sender, receiver) = channel();
+        let object = KeepAudioAwake {
+            requests: sender,
+            wake: Box::new(wake),
+            properties: Mutex::new(Properties {
+                state: State::Active,
+                period_ms: 0,
+                devices: Vec::new(),
+            }),
+        };
+
+        let connection = builder
+            .name(BUS_NAME)
+            .and_then(|builder| builder.serve_at(OBJECT_PATH, object))
+            .and_then(|builder| builder.build())
+            .map_err(|e| format!("Failed to start D-Bus service. {}", e))?;
+
+        debug!("D-Bus service started as {}", BUS_NAME);
+
+        Ok((Self { connection }, receiver))
+    }
+
+    /// Updates the properties and emits change signals for what has changed.
+    pub fn publish(&self, status: &Status) {
+        self.try_publish(status)
+            .unwrap_or_else(|e| warn!("Failed to publish D-Bus properties. {}", e));
+    }
+
+    fn try_publish(&self, status: &Status) -> zbus::Result<()> {
+        let object = self
+            .connection
+            .object_server()
+            .interface::<_, KeepAudioAwake>(OBJECT_PATH)?;
+        let emitter = object.signal_emitter();
+        /* a shared borrow, not to wait for a method call that waits for this thread */
+        let iface = object.get();
+
+        let new = Properties {
+            state: status.state,
+            period_ms: status.period_ms,
+            devices: status.devices.clone(),
+        };
+        let old = std::mem::replace(&mut *iface.properties(), new);
+
+        if old.period_ms != status.period_ms {
+            zbus::block_on(iface.period_changed(emitter))?;
+        }
+        if old.devices != status.devices {
+            zbus::block_on(iface.devices_changed(emitter))?;
+        }
+        if old.state != status.state {
+            zbus::block_on(iface.state_changed(emitter))?;
+            zbus::block_on(KeepAudioAwake::emit_state_changed(emitter, status.state.as_str()))?;
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use crate::dbus::{DbusService, BUS_NAME, OBJECT_PATH};
+    use crate::ipc::{Command, Response};
+    use crate::status::{State, Status};
+    use std::io::{BufRead, BufReader};
+    use std::process::{Child, Command as Process, Stdio};
+    use std::thread;
+    use std::time::{Duration, Instant};
+    use zbus::blocking::connection::Builder;
+    use zbus::blocking::Proxy;
+
+    const INTERFACE: &str = "io.github.KeepAudioAwake1";
+
+    /// Private session bus, killed on drop.
+    pub(crate) struct Bus {
+        process: Child,
+        pub(crate) address: String,
+    }
+
+    impl Bus {
+        /// None if dbus-daemon is not installed.
+        pub(crate) fn start() -> Option<Self> {
+            let mut process = Process::new("dbus-daemon")
+                .args(["--session", "--nofork", "--print-address"])
+                .stdout(Stdio::piped())
+                .stderr(Stdio::null())
+                .spawn()
+                .inspect_err(|e| eprintln!("Skipped, failed to run dbus-daemon. {}", e))
+                .ok()?;
+
+            let mut address = String::new();
+            let stdout = process.stdout.take().unwrap();
+            BufReader::new(stdout).read_line(&mut address).unwrap();
+            Some(Self {
+                process,
+                address: address.trim().to_string(),
+            })
+        }
+    }
+
+    impl Drop for Bus {
+        fn drop(&mut self) {
+            self.process.kill().unwrap_or_default();
+            self.process.wait().map(|_| ()).unwrap_or_default();
+        }
+    }
+
+    fn status(state: State) -> Status {
+        Status {
+            state,
+            devices: vec!["Speakers".to_string()],
+            period_ms: 5000,
+            last_play: None,
+            consecutive_failures: 0,
+            uptime_secs: 0,
+            pause_reasons: Vec::new(),
+            autostart: None,
+        }
+    }
+
+    #[test]
+    fn test_publish_during_call() {
+        let Some(bus) = Bus::start() else {
+            return;
+        };
+        let builder = Builder::address(bus.address.as_str()).unwrap();
+        let (service, requests) = DbusService::start_on(builder, || {}).unwrap();
+        service.publish(&status(State::Active));
+
+        let client = Builder::address(bus.address.as_str()).unwrap().build().unwrap();
+        let proxy = Proxy::new(&client, BUS_NAME, OBJECT_PATH, INTERFACE).unwrap();
+        let caller = {
+            let proxy = proxy.clone();
+            thread::spawn(move || proxy.call::<_, _, ()>("Pause", &()))
+        };
+
+        /* the application publishes while the call waits for it */
+        let request = requests.recv_timeout(Duration::from_secs(5)).unwrap();
+        assert_eq!(Command::Pause, request.command);
+        let published = Instant::now();
+        service.publish(&status(State::Paused));
+        assert!(published.elapsed() < Duration::from_secs(1));
+        request.reply(Response::Ok(String::new()));
+
+        assert!(caller.join().unwrap().is_ok());
+        assert_eq!("paused", proxy.get_property::<String>("State").unwrap());
+        assert_eq!(5000, proxy.get_property::<u32>("Period").unwrap());
+        assert_eq!(vec!["Speakers".to_string()], proxy.get_property::<Vec<String>>("Devices").unwrap());
+    }
+
+    #[test]
+    fn test_call_error() {
+        let Some(bus) = Bus::start() else {
+            return;
+        };
+        let builder = Builder::address(bus.address.as_str()).unwrap();
+        let (_service, requests) = DbusService::start_on(builder, || {}).unwrap();
+
+        let client = Builder::address(bus.address.as_str()).unwrap().build().unwrap();
+        let proxy = Proxy::new(&client, BUS_NAME, OBJECT_PATH, INTERFACE).unwrap();
+        let caller = thread::spawn(move || proxy.call::<_, _, ()>("Snooze", &(15u32)));
+
+        let request = requests.recv_timeout(Duration::from_secs(5)).unwrap();
+        assert_eq!(Command::Snooze(15), request.command);
+        request.reply(Response::Error("Already paused".to_string()));
+
+        let error = caller.join().unwrap().unwrap_err();
+        assert!(error.to_string().contains("Already paused"));
+    }
+}
@@ -0,0 +1,73 @@
+use log::warn;
+
+#[cfg(windows)]
+pub const TIMER_DEVICES: usize = 700;
+pub const TIMER_DEVICES_PERIOD_MS: u32 = 5000;
+
+/// Source of the names of the connected output devices.
+pub trait DeviceSource {
+    fn devices(&self) -> Result<Vec<String>, String>;
+}
+
+/// Tracks connected output devices and reports when one is added or removed.
+pub struct DeviceMonitor<S: DeviceSource> {
+    source: S,
+    devices: Option<Vec<String>>,
+}
+
+impl<S: DeviceSource> DeviceMonitor<S> {
+    pub fn new(source: S) -> Self {
+        Self {
+            source,
+            devices: None,
+        }
+    }
+
+    /// Returns the device list if it has changed since the previous poll. The first poll always
+    /// returns the list.
+    pub fn poll(&mut self) -> Option<Vec<String>> {
+        let devices = self
+            .source
+            .devices()
+            .inspect_err(|e| warn!("{}", e))
+            .ok()?;
+
+        if self.devices.as_ref() == Some(&devices) {
+            None
+        } else {
+            self.devices = Some(devices.clone());
+            Some(devices)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::devices::{DeviceMonitor, DeviceSource};
+    use std::cell::RefCell;
+    use std::rc::Rc;
+
+    #[derive(Clone, Default)]
+    struct FakeDeviceSource(Rc<RefCell<Option<Vec<String>>>>);
+
+    impl DeviceSource for FakeDeviceSource {
+        fn devices(&self) -> Result<Vec<String>, String> {
+            self.0.borrow().clone().ok_or("Not available".to_string())
+        }
+    }
+
+    #[test]
+    fn test_device_changes() {
+        let source = FakeDeviceSource::default();
+        let mut monitor = DeviceMonitor::new(source.clone());
+
+        assert_eq!(None, monitor.poll());
+
+        source.0.replace(Some(vec!["Speakers".to_string()]));
+        assert_eq!(Some(vec!["Speakers".to_string()]), monitor.poll());
+        assert_eq!(None, monitor.poll());
+
+        source.0.replace(Some(Vec::new()));
+        assert_eq!(Some(Vec::new()), monitor.poll());
+    }
+}
@@ -1,46 +1,296 @@
-use crate::audio::AudioControl;
-use crate::gui::res_ids::{IDS_APP_IS_ALREADY_RUNNING, IDS_APP_TITLE};
+use crate::audio::{waveform_duration_ms, AudioOutput, PlatformAudioOutput, PlatformDeviceSource};
+use crate::battery::{BatteryMonitor, PlatformPowerSource, TIMER_BATTERY, TIMER_BATTERY_PERIOD_MS};
+use crate::audio::PauseReason;
+use crate::core::{Core, Effect, Event, TipLine, Timer};
+use crate::devices::{DeviceMonitor, TIMER_DEVICES, TIMER_DEVICES_PERIOD_MS};
+use crate::gui::res_ids::{
+    IDS_APP_IS_ALREADY_RUNNING, IDS_APP_TITLE, IDS_AUDIO_RESTARTED, IDS_DEFAULT_DEVICE, IDS_DEVICE, IDS_DEVICE_LOST,
+    IDS_DEVICE_RECOVERED, IDS_FAILED_PINGS, IDS_KEEPING_AUDIO_DEVICE_AWAKE, IDS_KEEPS_FAILING, IDS_LAST_PING,
+    IDS_ON_BATTERY, IDS_PAUSED, IDS_PAUSED_ON_BATTERY, IDS_REASON_ON_BATTERY, IDS_REASON_SESSION_LOCKED,
+    IDS_REASON_SNOOZE, IDS_REASON_USER, IDS_REASON_USER_IDLE, IDS_RETRYING, IDS_SLOWED_DOWN_ON_BATTERY, IDS_SUSPENDED,
+};
+use crate::gui::settings_window::SettingsWindow;
 use crate::gui::tray_icon::start_blink_icon;
-use crate::util::hwnd;
-use crate::{rs, util};
-use log::{debug};
+use crate::hooks::Hooks;
+use crate::instance::{InstanceLock, LockError};
+use crate::ipc::Request;
+use crate::idle::{IdleMonitor, PlatformIdleSource, TIMER_IDLE, TIMER_IDLE_PERIOD_MS};
+use crate::mqtt::{MqttClient, MqttSettings, TIMER_MQTT, TIMER_MQTT_PERIOD_MS};
+use crate::power::{PowerEvent, PowerNotifications};
+use crate::session::{SessionEvent, SessionNotifications};
+use crate::settings::{BatteryMode, Settings};
+use crate::settings_form::SettingsForm;
+use crate::status::State;
+use crate::supervisor::catch_panic;
+use crate::tray::{DeviceItem, MenuAction, Tray};
+use crate::util::{hwnd, start_timer, stop_timer};
+use crate::{autostart, ipc, logging, metrics, notifications, rs};
+use chrono::Local;
+use log::{debug, warn};
 use native_windows_gui::{
-    dispatch_thread_events, message, stop_thread_dispatch, GlobalCursor, Menu, MenuItem, MessageButtons,
-    MessageIcons, MessageParams, MessageWindow, NativeUi, TrayNotification,
+    dispatch_thread_events, message, stop_thread_dispatch, ControlHandle, GlobalCursor, Menu, MenuItem,
+    MenuSeparator, MessageButtons, MessageIcons, MessageParams, MessageWindow, NativeUi, Notice,
+    TrayNotification, TrayNotificationFlags,
 };
 use res::RESOURCES;
 use std::cell::RefCell;
+use std::sync::mpsc::Receiver;
+use std::time::{Duration, Instant};
 use tray_icon::stop_blink_icon;
-use util::check_app_running;
 
 mod res;
 mod res_ids;
+mod settings_window;
 mod tray_icon;
 
+pub const TIMER_AUDIO: usize = 100;
+pub const TIMER_SNOOZE: usize = 600;
+
+/// Windows front-end of the core. Turns window messages into core events and carries out the
+/// effects.
 #[derive(Default)]
 pub struct App {
     window: MessageWindow,
     tray: TrayNotification,
     tray_menu: Menu,
+    devices_menu: Menu,
+    /* rebuilt on each change, dropping an item removes it from the menu */
+    device_items: RefCell<Vec<(MenuItem, Option<String>)>>,
+    autostart_menu_item: MenuItem,
+    settings_menu_item: MenuItem,
+    settings_window: SettingsWindow,
+    exit_menu_separator: MenuSeparator,
     exit_menu_item: MenuItem,
-    audio: RefCell<AudioControl>,
+    ipc_notice: Notice,
+    core: RefCell<Core>,
+    output: RefCell<Option<PlatformAudioOutput>>,
+    hooks: RefCell<Hooks>,
+    idle: RefCell<Option<IdleMonitor<PlatformIdleSource>>>,
+    power: RefCell<PowerNotifications>,
+    session: RefCell<SessionNotifications>,
+    battery: RefCell<Option<BatteryMonitor<PlatformPowerSource>>>,
+    devices: RefCell<Option<DeviceMonitor<PlatformDeviceSource>>>,
+    ipc_requests: RefCell<Option<Receiver<Request>>>,
+    mqtt: RefCell<Option<MqttClient>>,
+    mqtt_requests: RefCell<Option<Receiver<Request>>>,
+    /* the error to exit with if the application aborts */
+    abort_error: RefCell<Option<String>>,
 }
 
 impl App {
-    fn on_app_exit(&self) {
-        debug!("Exiting application");
+    fn dispatch(&self, event: Event) {
+        let effects = self.core.borrow_mut().handle(event);
+        self.perform(effects);
+    }
+
+    fn perform(&self, effects: Vec<Effect>) {
+        for effect in effects {
+            match effect {
+                Effect::Play => {
+                    let result = match self.output.borrow_mut().as_mut() {
+                        Some(output) => catch_panic(|| output.play()),
+                        None => Ok(Err("No audio output".to_string())),
+                    };
+                    match result {
+                        Ok(result) => self.dispatch(Event::Played(result)),
+                        Err(message) => self.on_audio_panic(message),
+                    }
+                }
+                Effect::StartTimer(timer, delay_ms) => {
+                    start_timer(hwnd(self.window.handle), timer_id(timer), delay_ms)
+                        .unwrap_or_else(|e| warn!("{}", e));
+                }
+                Effect::StopTimer(timer) => stop_timer(hwnd(self.window.handle), timer_id(timer)),
+                Effect::CloseDevice => {
+                    let result = self.output.borrow_mut().as_mut().map(|output| catch_panic(|| output.close()));
+                    if let Some(Err(message)) = result {
+                        self.on_audio_panic(message);
+                    }
+                }
+                Effect::ReopenDevice => {
+                    let result = self.output.borrow_mut().as_mut().map(|output| catch_panic(|| output.reopen()));
+                    if let Some(Err(message)) = result {
+                        self.on_audio_panic(message);
+                    }
+                }
+                Effect::Blink => start_blink_icon(&self.window, &self.tray),
+                Effect::UpdateIcon(icon) => self.tray.show_icon(icon),
+                Effect::UpdateTip(lines) => self.tray.show_tip(&tip_text(&lines)),
+                Effect::UpdateDevices(items) => self.show_devices(items),
+                Effect::UpdateAutostart(enabled) => self.autostart_menu_item.set_checked(enabled),
+                Effect::RunHooks(payload) => self.hooks.borrow_mut().fire(payload),
+                Effect::SelectDevices(devices) => self.open_output(&devices),
+                Effect::SaveSettings(settings) => settings.save().unwrap_or_else(|e| warn!("{}", e)),
+                Effect::ShowSettings => self.show_settings(),
+                Effect::SetAutostart(enabled) => {
+                    autostart::set_enabled(enabled).unwrap_or_else(|e| warn!("{}", e));
+                    self.check_autostart();
+                }
+                Effect::Notify(notice) => {
+                    let (text, flags) = notice_text(&notice);
+                    self.tray.show(&text, Some(rs!(IDS_APP_TITLE)), Some(flags), None);
+                }
+                Effect::Reconfigure => {
+                    logging::reconfigure(&self.core.borrow().settings().log);
+                    self.stop_sources();
+                    self.start_sources();
+                }
+                Effect::RestartAudio => {
+                    let devices = self.core.borrow().settings().devices.clone();
+                    self.open_output(&devices);
+                }
+                Effect::Exit => self.shut_down(),
+                Effect::Abort(e) => {
+                    warn_message(&e);
+                    self.abort_error.replace(Some(e));
+                    self.shut_down();
+                }
+            }
+        }
+    }
 
-        stop_blink_icon(&self.window, &self.tray);
-        self.audio.borrow_mut().stop();
+    fn shut_down(&self) {
+        stop_blink_icon(&self.window, &self.tray, self.core.borrow().icon());
+        self.stop_sources();
         stop_thread_dispatch();
     }
 
-    fn on_timer(&self) {
-        start_blink_icon(&self.window, &self.tray);
-        self.audio
-            .borrow_mut()
-            .play()
-            .expect("Failed to play audio");
+    /// Drops the output a panic has left half done and lets the core decide on a restart.
+    fn on_audio_panic(&self, message: String) {
+        /* its panic is logged by the hook, nothing more to do with it */
+        catch_panic(|| self.output.replace(None)).unwrap_or_default();
+        self.dispatch(Event::AudioPanicked(message));
+    }
+
+    /// Recovers from a panic in an event handler like from one of the audio output, as the
+    /// handler may have stopped the audio timer before it panicked.
+    fn on_handler_panic(&self, message: String) {
+        /* the panic hook has logged both panics, and another one would unwind into the window procedure */
+        catch_panic(|| self.on_audio_panic(message)).unwrap_or_default();
+    }
+
+    /// Timers of the core are one-shot.
+    fn on_core_timer(&self, timer: Timer) {
+        stop_timer(hwnd(self.window.handle), timer_id(timer));
+        self.dispatch(Event::Timer(timer));
+    }
+
+    fn on_idle_timer(&self) {
+        let change = self.idle.borrow_mut().as_mut().and_then(|monitor| monitor.poll());
+        if let Some(state) = change {
+            self.dispatch(Event::Idle(state));
+        }
+    }
+
+    fn on_power_event(&self, event: PowerEvent) {
+        self.dispatch(Event::Power(event));
+    }
+
+    fn on_session_event(&self, event: SessionEvent) {
+        self.dispatch(Event::Session(event));
+    }
+
+    fn on_battery_timer(&self) {
+        let change = self.battery.borrow_mut().as_mut().and_then(|monitor| {
+            monitor.poll().map(|status| Event::Battery {
+                status,
+                saving: monitor.is_saving(),
+            })
+        });
+        if let Some(event) = change {
+            self.dispatch(event);
+        }
+    }
+
+    fn on_devices_timer(&self) {
+        let change = self.devices.borrow_mut().as_mut().and_then(|monitor| monitor.poll());
+        if let Some(devices) = change {
+            self.dispatch(Event::DevicesConnected(devices));
+        }
+    }
+
+    fn on_mqtt_timer(&self) {
+        let status = self.core.borrow().status();
+        if let Some(mqtt) = self.mqtt.borrow_mut().as_mut() {
+            mqtt.publish(&status);
+        }
+    }
+
+    fn on_ipc_notice(&self) {
+        /* MQTT commands share the notice with IPC */
+        let requests: Vec<Request> = [&self.ipc_requests, &self.mqtt_requests]
+            .iter()
+            .filter_map(|receiver| {
+                receiver
+                    .borrow()
+                    .as_ref()
+                    .map(|receiver| receiver.try_iter().collect::<Vec<_>>())
+            })
+            .flatten()
+            .collect();
+
+        if !requests.is_empty() {
+            /* the command line may have changed it */
+            self.check_autostart();
+        }
+        for request in requests {
+            let (response, effects) = self.core.borrow_mut().execute(request.command);
+            request.reply(response);
+            self.perform(effects);
+        }
+    }
+
+    fn check_autostart(&self) {
+        match autostart::is_enabled() {
+            Ok(enabled) => self.dispatch(Event::Autostart(enabled)),
+            Err(e) => warn!("{}", e),
+        }
+    }
+
+    fn on_menu_action(&self, action: MenuAction) {
+        self.dispatch(Event::Menu(action));
+    }
+
+    fn on_device_item_selected(&self, handle: ControlHandle) {
+        let device = self
+            .device_items
+            .borrow()
+            .iter()
+            .find(|(item, _)| item.handle == handle)
+            .map(|(_, device)| device.clone());
+
+        /* the menu is rebuilt while the event is handled */
+        if let Some(device) = device {
+            self.on_menu_action(MenuAction::ToggleDevice(device));
+        }
+    }
+
+    fn show_settings(&self) {
+        /* the devices of the menu, connected or selected */
+        let devices: Vec<String> = self
+            .device_items
+            .borrow()
+            .iter()
+            .filter_map(|(_, device)| device.clone())
+            .collect();
+        let autostart = self.core.borrow().status().autostart == Some(true);
+        let form = SettingsForm::new(self.core.borrow().settings(), &devices, autostart);
+        self.settings_window.show(&form);
+    }
+
+    fn on_settings_ok(&self) {
+        let form = self.settings_window.form();
+        let result = form.apply(self.core.borrow().settings());
+        match result {
+            Ok(settings) => {
+                self.settings_window.hide();
+                self.dispatch(Event::SettingsChanged {
+                    settings: Box::new(settings),
+                    autostart: form.autostart,
+                });
+            }
+            Err(e) => self.settings_window.show_error(&e),
+        }
     }
 
     fn on_show_menu(&self) {
@@ -48,31 +298,276 @@ impl App {
         self.tray_menu.popup(x, y);
     }
 
-    pub fn run(&self) {
-        self.audio
-            .borrow_mut()
-            .start(hwnd(self.window.handle))
-            .expect("Failed to start audio controller");
+    /// Runs until the exit menu item or command. Fails if the application aborts.
+    pub fn run(&self) -> Result<(), String> {
+        self.start_sources();
+        let effects = self.core.borrow_mut().start();
+        self.perform(effects);
+        self.check_autostart();
+        self.start_ipc_server();
+
+        if let Some(port) = self.core.borrow().settings().metrics_port {
+            metrics::start_server(port).unwrap_or_else(|e| warn!("{}", e));
+        }
 
         debug!("Application started");
 
         dispatch_thread_events();
+
+        /* a hook of the stopped event would not even start if the process exited now */
+        let timeout = self.hooks.borrow().timeout();
+        self.hooks.borrow_mut().wait(Instant::now() + timeout);
+
+        self.abort_error.take().map_or(Ok(()), Err)
+    }
+
+    /// Creates the audio output and event sources from the current settings.
+    fn start_sources(&self) {
+        let settings = self.core.borrow().settings().clone();
+
+        self.open_output(&settings.devices);
+        self.start_device_monitor();
+
+        self.hooks.replace(Hooks::new(&settings.hooks));
+
+        if settings.idle_timeout_min > 0 {
+            self.start_idle_monitor(&settings);
+        }
+
+        self.power
+            .borrow_mut()
+            .register(hwnd(self.window.handle))
+            .unwrap_or_else(|e| warn!("{}", e));
+
+        if settings.battery_mode != BatteryMode::Ignore {
+            self.start_battery_monitor(&settings);
+        }
+
+        if settings.pause_when_locked {
+            self.session
+                .borrow_mut()
+                .register(hwnd(self.window.handle))
+                .unwrap_or_else(|e| warn!("{}", e));
+        }
+
+        if let Some(mqtt) = &settings.mqtt {
+            self.start_mqtt_client(mqtt);
+        }
+    }
+
+    fn stop_sources(&self) {
+        stop_timer(hwnd(self.window.handle), TIMER_IDLE);
+        stop_timer(hwnd(self.window.handle), TIMER_BATTERY);
+        stop_timer(hwnd(self.window.handle), TIMER_DEVICES);
+        stop_timer(hwnd(self.window.handle), TIMER_MQTT);
+        if let Some(mqtt) = self.mqtt.take() {
+            mqtt.stop();
+        }
+        self.mqtt_requests.replace(None);
+        self.idle.replace(None);
+        self.battery.replace(None);
+        self.devices.replace(None);
+        self.power.borrow_mut().unregister();
+        self.session.borrow_mut().unregister();
+        self.output.replace(None);
+    }
+
+    /// Replaces the audio output with one playing on the given devices.
+    fn open_output(&self, devices: &[String]) {
+        /* the devices of the old output are released first */
+        self.output.replace(None);
+
+        let duration_ms = waveform_duration_ms(self.core.borrow().settings());
+        let opened = catch_panic(|| {
+            let mut output = PlatformAudioOutput::new(duration_ms, devices);
+            /* the first ping retries. failures are logged by device */
+            output.open().unwrap_or_default();
+            let names = output.device_names();
+            (output, names)
+        });
+        match opened {
+            Ok((output, names)) => {
+                self.output.replace(Some(output));
+                self.dispatch(Event::DevicesChanged(names));
+            }
+            Err(message) => self.on_audio_panic(message),
+        }
+    }
+
+    fn start_device_monitor(&self) {
+        self.devices
+            .replace(Some(DeviceMonitor::new(PlatformDeviceSource)));
+
+        /* fill the menu right away */
+        self.on_devices_timer();
+
+        start_timer(hwnd(self.window.handle), TIMER_DEVICES, TIMER_DEVICES_PERIOD_MS)
+            .unwrap_or_else(|e| warn!("{}", e));
+    }
+
+    fn show_devices(&self, items: Vec<DeviceItem>) {
+        let mut menu_items = self.device_items.borrow_mut();
+        menu_items.clear();
+
+        for device in items {
+            /* ampersands mark access keys */
+            let text = match &device.name {
+                Some(name) => name.replace('&', "&&"),
+                None => rs!(IDS_DEFAULT_DEVICE).to_string(),
+            };
+            let mut item = MenuItem::default();
+            match MenuItem::builder()
+                .text(&text)
+                .check(device.checked)
+                .parent(&self.devices_menu)
+                .build(&mut item)
+            {
+                Ok(()) => menu_items.push((item, device.name)),
+                Err(e) => warn!("Failed to add device menu item. {}", e),
+            }
+        }
+    }
+
+    fn start_idle_monitor(&self, settings: &Settings) {
+        let timeout = Duration::from_secs(settings.idle_timeout_min as u64 * 60);
+        self.idle
+            .replace(Some(IdleMonitor::new(PlatformIdleSource, timeout)));
+
+        start_timer(hwnd(self.window.handle), TIMER_IDLE, TIMER_IDLE_PERIOD_MS)
+            .unwrap_or_else(|e| warn!("{}", e));
+    }
+
+    fn start_battery_monitor(&self, settings: &Settings) {
+        self.battery.replace(Some(BatteryMonitor::new(
+            PlatformPowerSource,
+            settings.battery_threshold_percent,
+        )));
+
+        /* apply the current power state right away */
+        self.on_battery_timer();
+
+        start_timer(hwnd(self.window.handle), TIMER_BATTERY, TIMER_BATTERY_PERIOD_MS)
+            .unwrap_or_else(|e| warn!("{}", e));
+    }
+
+    fn start_mqtt_client(&self, settings: &MqttSettings) {
+        let sender = self.ipc_notice.sender();
+        match MqttClient::start(settings, move || sender.notice()) {
+            Ok((client, receiver)) => {
+                self.mqtt.replace(Some(client));
+                self.mqtt_requests.replace(Some(receiver));
+            }
+            Err(e) => {
+                warn!("{}", e);
+                return;
+            }
+        }
+
+        start_timer(hwnd(self.window.handle), TIMER_MQTT, TIMER_MQTT_PERIOD_MS)
+            .unwrap_or_else(|e| warn!("{}", e));
+    }
+
+    fn start_ipc_server(&self) {
+        let sender = self.ipc_notice.sender();
+        let scope = self.core.borrow().settings().instance_scope;
+        match ipc::start_server(scope, move || sender.notice()) {
+            Ok(receiver) => {
+                self.ipc_requests.replace(Some(receiver));
+            }
+            Err(e) => warn!("{}", e),
+        }
     }
 }
 
+fn timer_id(timer: Timer) -> usize {
+    match timer {
+        Timer::Audio => TIMER_AUDIO,
+        Timer::Snooze => TIMER_SNOOZE,
+    }
+}
+
+fn notice_text(notice: &notifications::Notice) -> (String, TrayNotificationFlags) {
+    use notifications::Notice;
+
+    match notice {
+        Notice::DeviceLost(error) => (
+            format!("{}\n{}", rs!(IDS_DEVICE_LOST), error),
+            TrayNotificationFlags::WARNING_ICON,
+        ),
+        Notice::DeviceRecovered => (rs!(IDS_DEVICE_RECOVERED).to_string(), TrayNotificationFlags::INFO_ICON),
+        Notice::KeepsFailing(count) => (
+            format!("{} ({})", rs!(IDS_KEEPS_FAILING), count),
+            TrayNotificationFlags::WARNING_ICON,
+        ),
+        Notice::Paused(reason) => {
+            let reason = match reason {
+                PauseReason::User => rs!(IDS_REASON_USER),
+                PauseReason::UserIdle => rs!(IDS_REASON_USER_IDLE),
+                PauseReason::SessionLocked => rs!(IDS_REASON_SESSION_LOCKED),
+                PauseReason::OnBattery => rs!(IDS_REASON_ON_BATTERY),
+                PauseReason::Snooze => rs!(IDS_REASON_SNOOZE),
+            };
+            (
+                format!("{} ({})", rs!(IDS_PAUSED), reason),
+                TrayNotificationFlags::INFO_ICON | TrayNotificationFlags::SILENT,
+            )
+        }
+        Notice::Resumed => (
+            rs!(IDS_KEEPING_AUDIO_DEVICE_AWAKE).to_string(),
+            TrayNotificationFlags::INFO_ICON | TrayNotificationFlags::SILENT,
+        ),
+        Notice::AudioRestarted => (rs!(IDS_AUDIO_RESTARTED).to_string(), TrayNotificationFlags::WARNING_ICON),
+    }
+}
+
+fn tip_text(lines: &[TipLine]) -> String {
+    lines
+        .iter()
+        .map(|line| match line {
+            TipLine::State(State::Active) => rs!(IDS_KEEPING_AUDIO_DEVICE_AWAKE).to_string(),
+            TipLine::State(State::Paused) => rs!(IDS_PAUSED).to_string(),
+            TipLine::State(State::Suspended) => rs!(IDS_SUSPENDED).to_string(),
+            TipLine::State(State::Failing) => rs!(IDS_RETRYING).to_string(),
+            TipLine::FailedPings(count) => format!("{}: {}", rs!(IDS_FAILED_PINGS), count),
+            TipLine::Device(name) => format!("{}: {}", rs!(IDS_DEVICE), name),
+            TipLine::LastPing(time) => format!(
+                "{}: {}",
+                rs!(IDS_LAST_PING),
+                time.with_timezone(&Local).format("%H:%M:%S")
+            ),
+            TipLine::OnBattery(Some(charge)) => format!("{} ({}%)", rs!(IDS_ON_BATTERY), charge),
+            TipLine::OnBattery(None) => rs!(IDS_ON_BATTERY).to_string(),
+            TipLine::PausedOnBattery => rs!(IDS_PAUSED_ON_BATTERY).to_string(),
+            TipLine::SlowedDownOnBattery => rs!(IDS_SLOWED_DOWN_ON_BATTERY).to_string(),
+        })
+        .collect::<Vec<_>>()
+        .join("\n")
+}
+
 pub(crate) fn run_main() -> Result<(), String> {
-    native_windows_gui::init().expect("Failed to init Native Windows GUI");
+    native_windows_gui::init().map_err(|e| format!("Failed to init Native Windows GUI. {}", e))?;
+
+    let settings = Settings::load().unwrap_or_else(|e| {
+        warn!("{}", e);
+        Settings::default()
+    });
 
-    check_app_running().map_err(|e| {
-        warn_message(rs!(IDS_APP_IS_ALREADY_RUNNING));
-        e
+    /* held until the application exits */
+    let _lock = InstanceLock::acquire(settings.instance_scope).inspect_err(|e| match e {
+        LockError::AlreadyRunning(Some(pid)) => {
+            warn_message(&format!("{}\nPID: {}", rs!(IDS_APP_IS_ALREADY_RUNNING), pid))
+        }
+        LockError::AlreadyRunning(None) => warn_message(rs!(IDS_APP_IS_ALREADY_RUNNING)),
+        LockError::Failed(message) => warn_message(message),
     })?;
 
     /* do not remove `let ui`! */
-    let ui = App::build_ui(App::default()).expect("Failed to build UI");
-    ui.run();
-
-    Ok(())
+    let app = App {
+        core: RefCell::new(Core::new(settings, Settings::load)),
+        ..Default::default()
+    };
+    let ui = App::build_ui(app).map_err(|e| format!("Failed to build UI. {}", e))?;
+    ui.run()
 }
 
 fn warn_message(text: &str) {
@@ -85,26 +580,38 @@ fn warn_message(text: &str) {
 }
 
 mod app_ui {
-    use crate::audio;
+    use crate::battery::TIMER_BATTERY;
+    use crate::devices::TIMER_DEVICES;
     use crate::gui::res::RESOURCES;
     use crate::gui::res_ids::IDS_KEEPING_AUDIO_DEVICE_AWAKE;
-    use crate::gui::res_ids::{IDI_APP_ICON, IDS_EXIT};
+    use crate::gui::res_ids::{IDI_APP_ICON, IDS_DEVICES, IDS_EXIT, IDS_SETTINGS, IDS_START_AT_LOGIN};
     use crate::gui::tray_icon::{stop_blink_icon, TIMER_ICON_BLINK};
-    use crate::gui::App;
+    use crate::core::Timer as CoreTimer;
+    use crate::gui::{App, TIMER_AUDIO, TIMER_SNOOZE};
+    use crate::idle::TIMER_IDLE;
+    use crate::mqtt::TIMER_MQTT;
+    use crate::tray::MenuAction;
+    use crate::power::power_event;
+    use crate::session::session_event;
+    use crate::supervisor::catch_panic;
     use crate::{r_icon, rs};
-    use audio::TIMER_AUDIO;
+    use log::warn;
     use native_windows_gui::{
-        full_bind_event_handler, unbind_event_handler, ControlHandle, Event, EventHandler, Menu, MenuItem, MessageWindow,
-        NativeUi, NwgError, TrayNotification,
+        bind_raw_event_handler, full_bind_event_handler, unbind_event_handler, unbind_raw_event_handler, ControlHandle,
+        Event, EventHandler, Menu, MenuItem, MenuSeparator, MessageWindow, NativeUi, Notice, NwgError, RawEventHandler,
+        TrayNotification,
     };
     use std::cell::RefCell;
     use std::ops::Deref;
     use std::rc::Rc;
     use ControlHandle::Timer;
 
+    const RAW_HANDLER_ID: usize = 0x10000;
+
     pub struct AppUi {
         inner: Rc<App>,
         default_handler: RefCell<Vec<EventHandler>>,
+        raw_handler: RefCell<Vec<RawEventHandler>>,
     }
 
     impl NativeUi<AppUi> for App {
@@ -115,7 +622,7 @@ mod app_ui {
 
             TrayNotification::builder()
                 .parent(&app.window)
-                .icon(Some(&r_icon!(IDI_APP_ICON)))
+                .icon(r_icon!(IDI_APP_ICON).as_ref())
                 .tip(Some(rs!(IDS_KEEPING_AUDIO_DEVICE_AWAKE)))
                 .build(&mut app.tray)?;
 
@@ -124,16 +631,42 @@ mod app_ui {
                 .parent(&app.window)
                 .build(&mut app.tray_menu)?;
 
+            Menu::builder()
+                .text(rs!(IDS_DEVICES))
+                .parent(&app.tray_menu)
+                .build(&mut app.devices_menu)?;
+
+            MenuItem::builder()
+                .text(rs!(IDS_START_AT_LOGIN))
+                .parent(&app.tray_menu)
+                .build(&mut app.autostart_menu_item)?;
+
+            MenuItem::builder()
+                .text(rs!(IDS_SETTINGS))
+                .parent(&app.tray_menu)
+                .build(&mut app.settings_menu_item)?;
+
+            MenuSeparator::builder()
+                .parent(&app.tray_menu)
+                .build(&mut app.exit_menu_separator)?;
+
             MenuItem::builder()
                 .text(rs!(IDS_EXIT))
                 .parent(&app.tray_menu)
                 .build(&mut app.exit_menu_item)?;
 
+            Notice::builder()
+                .parent(&app.window)
+                .build(&mut app.ipc_notice)?;
+
+            app.settings_window.build()?;
+
             /* Wrap-up */
 
             let ui = AppUi {
                 inner: Rc::new(app),
                 default_handler: Default::default(),
+                raw_handler: Default::default(),
             };
 
             /* Events */
@@ -141,27 +674,52 @@ mod app_ui {
             let app_weak = Rc::downgrade(&ui.inner);
             let handle_events = move |evt, _data, handle| {
                 if let Some(app) = app_weak.upgrade() {
-                    match evt {
-                        Event::OnTimerTick => {
-                            if let Timer(_hwnd, timer_id) = handle {
-                                if timer_id as usize == TIMER_AUDIO {
-                                    app.on_timer()
-                                } else if timer_id as usize == TIMER_ICON_BLINK {
-                                    stop_blink_icon(&app.window, &app.tray);
+                    /* a panic must not unwind into the window procedure */
+                    let result = catch_panic(|| {
+                        match evt {
+                            Event::OnTimerTick => {
+                                if let Timer(_hwnd, timer_id) = handle {
+                                    if timer_id as usize == TIMER_AUDIO {
+                                        app.on_core_timer(CoreTimer::Audio);
+                                    } else if timer_id as usize == TIMER_ICON_BLINK {
+                                        stop_blink_icon(&app.window, &app.tray, app.core.borrow().icon());
+                                    } else if timer_id as usize == TIMER_IDLE {
+                                        app.on_idle_timer();
+                                    } else if timer_id as usize == TIMER_BATTERY {
+                                        app.on_battery_timer();
+                                    } else if timer_id as usize == TIMER_SNOOZE {
+                                        app.on_core_timer(CoreTimer::Snooze);
+                                    } else if timer_id as usize == TIMER_MQTT {
+                                        app.on_mqtt_timer();
+                                    } else if timer_id as usize == TIMER_DEVICES {
+                                        app.on_devices_timer();
+                                    }
                                 }
                             }
-                        }
-                        Event::OnContextMenu => {
-                            if &handle == &app.tray {
-                                app.on_show_menu();
+                            Event::OnContextMenu => {
+                                if &handle == &app.tray {
+                                    app.on_show_menu();
+                                }
                             }
-                        }
-                        Event::OnMenuItemSelected => {
-                            if &handle == &app.exit_menu_item {
-                                app.on_app_exit();
+                            Event::OnMenuItemSelected => {
+                                if &handle == &app.exit_menu_item {
+                                    app.on_menu_action(MenuAction::Exit);
+                                } else if handle == app.autostart_menu_item.handle {
+                                    app.on_menu_action(MenuAction::ToggleAutostart);
+                                } else if handle == app.settings_menu_item.handle {
+                                    app.on_menu_action(MenuAction::Settings);
+                                } else {
+                                    app.on_device_item_selected(handle);
+                                }
+                            }
+                            Event::OnNotice if handle == app.ipc_notice => {
+                                app.on_ipc_notice();
                             }
+                            _ => {}
                         }
-                        _ => {}
+                    });
+                    if let Err(message) = result {
+                        app.on_handler_panic(message);
                     }
                 }
             };
@@ -170,6 +728,55 @@ mod app_ui {
                 .borrow_mut()
                 .push(full_bind_event_handler(&ui.window.handle, handle_events));
 
+            /* the settings window is a top-level window with its own handler */
+            let app_weak = Rc::downgrade(&ui.inner);
+            let handle_settings_events = move |evt, _data, handle| {
+                if let Some(app) = app_weak.upgrade()
+                    && evt == Event::OnButtonClick
+                {
+                    let result = catch_panic(|| {
+                        if handle == app.settings_window.ok_button.handle {
+                            app.on_settings_ok();
+                        } else if handle == app.settings_window.cancel_button.handle {
+                            app.settings_window.hide();
+                        }
+                    });
+                    if let Err(message) = result {
+                        app.on_handler_panic(message);
+                    }
+                }
+            };
+
+            ui.default_handler.borrow_mut().push(full_bind_event_handler(
+                &ui.settings_window.window.handle,
+                handle_settings_events,
+            ));
+
+            /* Raw window messages not covered by NWG events */
+
+            let app_weak = Rc::downgrade(&ui.inner);
+            let handle_raw_events = move |_hwnd, msg, wparam, _lparam| {
+                if let Some(app) = app_weak.upgrade() {
+                    let result = catch_panic(|| {
+                        if let Some(event) = power_event(msg, wparam) {
+                            app.on_power_event(event);
+                        } else if let Some(event) = session_event(msg, wparam) {
+                            app.on_session_event(event);
+                        }
+                    });
+                    if let Err(message) = result {
+                        app.on_handler_panic(message);
+                    }
+                }
+                None
+            };
+
+            ui.raw_handler.borrow_mut().push(bind_raw_event_handler(
+                &ui.window.handle,
+                RAW_HANDLER_ID,
+                handle_raw_events,
+            )?);
+
             Ok(ui)
         }
     }
@@ -180,6 +787,11 @@ mod app_ui {
             for handler in handlers.drain(0..) {
                 unbind_event_handler(&handler);
             }
+
+            let mut raw_handlers = self.raw_handler.borrow_mut();
+            for handler in raw_handlers.drain(0..) {
+                unbind_raw_event_handler(&handler).unwrap_or_else(|e| warn!("{}", e));
+            }
         }
     }
 
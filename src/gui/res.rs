@@ -1,3 +1,5 @@
+use crate::util::small_icon_size;
+use log::warn;
 use native_windows_gui::{EmbedResource, Icon};
 
 #[macro_export]
@@ -18,34 +20,43 @@ thread_local! {
     pub(crate) static RESOURCES: Resources = Resources::new();
 }
 
+/// Embedded icons and strings. A missing resource is logged and left out rather than taking the
+/// application down.
 pub(crate) struct Resources {
-    embed: EmbedResource,
+    embed: Option<EmbedResource>,
 }
 
 impl Resources {
     fn new() -> Self {
         Self {
-            embed: EmbedResource::load(None).expect("Unable to load embedded resources"),
+            embed: EmbedResource::load(None)
+                .inspect_err(|e| warn!("Unable to load embedded resources. {}", e))
+                .ok(),
         }
     }
 
-    pub(crate) fn icon(&self, res_id: usize) -> Icon {
+    pub(crate) fn icon(&self, res_id: usize) -> Option<Icon> {
         let mut icon = Icon::default();
 
         Icon::builder()
-            .source_embed(Some(&self.embed))
+            .source_embed(Some(self.embed.as_ref()?))
             .source_embed_id(res_id)
             .strict(true)
-            .size(Some((16, 16)))
+            .size(Some((small_icon_size(), small_icon_size())))
             .build(&mut icon)
-            .expect("Unable to load resource icon");
+            .inspect_err(|e| warn!("Unable to load resource icon {}. {}", res_id, e))
+            .ok()?;
 
-        icon
+        Some(icon)
     }
 
     pub(crate) fn string(&self, res_id: usize) -> String {
         self.embed
-            .string(res_id as u32)
-            .expect("Unable to read resource string")
+            .as_ref()
+            .and_then(|embed| embed.string(res_id as u32))
+            .unwrap_or_else(|| {
+                warn!("Unable to read resource string {}", res_id);
+                format!("#{}", res_id)
+            })
     }
 }
@@ -1,8 +1,45 @@
 /* Autogenerated by build.rs. Do not edit. */
 
 pub const IDI_APP_ICON: usize = 1001;
-pub const IDI_APP_ICON_GRAY: usize = 1002;
 pub const IDS_APP_TITLE: usize = 1001;
 pub const IDS_EXIT: usize = 1002;
 pub const IDS_KEEPING_AUDIO_DEVICE_AWAKE: usize = 1003;
 pub const IDS_APP_IS_ALREADY_RUNNING: usize = 1004;
+pub const IDS_ON_BATTERY: usize = 1005;
+pub const IDS_PAUSED_ON_BATTERY: usize = 1006;
+pub const IDS_SLOWED_DOWN_ON_BATTERY: usize = 1007;
+pub const IDS_PAUSED: usize = 1008;
+pub const IDS_SUSPENDED: usize = 1009;
+pub const IDS_RETRYING: usize = 1010;
+pub const IDS_FAILED_PINGS: usize = 1011;
+pub const IDS_DEVICE: usize = 1012;
+pub const IDS_LAST_PING: usize = 1013;
+pub const IDS_DEVICES: usize = 1014;
+pub const IDS_DEFAULT_DEVICE: usize = 1015;
+pub const IDS_SETTINGS: usize = 1016;
+pub const IDS_PERIOD_MS: usize = 1017;
+pub const IDS_JITTER_PERCENT: usize = 1018;
+pub const IDS_SIGNAL: usize = 1019;
+pub const IDS_SIGNAL_NORMAL: usize = 1020;
+pub const IDS_SIGNAL_MIN_ENERGY: usize = 1021;
+pub const IDS_IDLE_TIMEOUT_MIN: usize = 1022;
+pub const IDS_PAUSE_WHEN_LOCKED: usize = 1023;
+pub const IDS_BATTERY_IGNORE: usize = 1024;
+pub const IDS_BATTERY_PAUSE: usize = 1025;
+pub const IDS_BATTERY_SLOW_DOWN: usize = 1026;
+pub const IDS_BATTERY_THRESHOLD_PERCENT: usize = 1027;
+pub const IDS_BATTERY_PERIOD_MS: usize = 1028;
+pub const IDS_OK: usize = 1029;
+pub const IDS_CANCEL: usize = 1030;
+pub const IDS_DEVICE_LOST: usize = 1031;
+pub const IDS_DEVICE_RECOVERED: usize = 1032;
+pub const IDS_KEEPS_FAILING: usize = 1033;
+pub const IDS_REASON_USER: usize = 1034;
+pub const IDS_REASON_USER_IDLE: usize = 1035;
+pub const IDS_REASON_SESSION_LOCKED: usize = 1036;
+pub const IDS_REASON_ON_BATTERY: usize = 1037;
+pub const IDS_REASON_SNOOZE: usize = 1038;
+pub const IDS_NOTIFICATIONS: usize = 1039;
+pub const IDS_START_AT_LOGIN: usize = 1040;
+pub const IDS_LOG_LEVEL: usize = 1041;
+pub const IDS_AUDIO_RESTARTED: usize = 1042;
@@ -0,0 +1,248 @@
+use crate::gui::res_ids::{
+    IDI_APP_ICON, IDS_BATTERY_IGNORE, IDS_BATTERY_PAUSE, IDS_BATTERY_PERIOD_MS, IDS_BATTERY_SLOW_DOWN,
+    IDS_BATTERY_THRESHOLD_PERCENT, IDS_CANCEL, IDS_DEVICES, IDS_IDLE_TIMEOUT_MIN, IDS_JITTER_PERCENT, IDS_OK,
+    IDS_LOG_LEVEL, IDS_NOTIFICATIONS, IDS_ON_BATTERY, IDS_PAUSE_WHEN_LOCKED, IDS_PERIOD_MS, IDS_SETTINGS, IDS_SIGNAL, IDS_SIGNAL_MIN_ENERGY,
+    IDS_SIGNAL_NORMAL, IDS_START_AT_LOGIN,
+};
+use crate::gui::RESOURCES;
+use crate::settings::BatteryMode;
+use crate::settings_form::SettingsForm;
+use crate::{r_icon, rs};
+use native_windows_gui::{
+    Button, CheckBox, CheckBoxState, ComboBox, GridLayout, GridLayoutItem, Label, ListBox, ListBoxFlags, NwgError,
+    TextInput, Window, WindowFlags,
+};
+
+const SIGNAL_NORMAL: usize = 0;
+const SIGNAL_MIN_ENERGY: usize = 1;
+
+/// Dialog for the settings a user is expected to change. Built hidden with the application
+/// and shown from the tray menu.
+#[derive(Default)]
+pub struct SettingsWindow {
+    pub window: Window,
+    layout: GridLayout,
+    period_label: Label,
+    period: TextInput,
+    jitter_label: Label,
+    jitter: TextInput,
+    signal_label: Label,
+    signal: ComboBox<String>,
+    devices_label: Label,
+    devices: ListBox<String>,
+    idle_label: Label,
+    idle: TextInput,
+    pause_when_locked: CheckBox,
+    battery_mode_label: Label,
+    battery_mode: ComboBox<String>,
+    battery_threshold_label: Label,
+    battery_threshold: TextInput,
+    battery_period_label: Label,
+    battery_period: TextInput,
+    notifications: CheckBox,
+    autostart: CheckBox,
+    log_level_label: Label,
+    log_level: TextInput,
+    error: Label,
+    pub ok_button: Button,
+    pub cancel_button: Button,
+}
+
+impl SettingsWindow {
+    pub fn build(&mut self) -> Result<(), NwgError> {
+        Window::builder()
+            .flags(WindowFlags::WINDOW)
+            .size((420, 585))
+            .center(true)
+            .title(rs!(IDS_SETTINGS))
+            .icon(r_icon!(IDI_APP_ICON).as_ref())
+            .build(&mut self.window)?;
+
+        let labels = [
+            (&mut self.period_label, IDS_PERIOD_MS),
+            (&mut self.jitter_label, IDS_JITTER_PERCENT),
+            (&mut self.signal_label, IDS_SIGNAL),
+            (&mut self.devices_label, IDS_DEVICES),
+            (&mut self.idle_label, IDS_IDLE_TIMEOUT_MIN),
+            (&mut self.battery_mode_label, IDS_ON_BATTERY),
+            (&mut self.battery_threshold_label, IDS_BATTERY_THRESHOLD_PERCENT),
+            (&mut self.battery_period_label, IDS_BATTERY_PERIOD_MS),
+            (&mut self.log_level_label, IDS_LOG_LEVEL),
+        ];
+        for (label, text) in labels {
+            Label::builder().text(rs!(text)).parent(&self.window).build(label)?;
+        }
+
+        for input in [
+            &mut self.period,
+            &mut self.jitter,
+            &mut self.idle,
+            &mut self.battery_threshold,
+            &mut self.battery_period,
+            &mut self.log_level,
+        ] {
+            TextInput::builder().parent(&self.window).build(input)?;
+        }
+
+        ComboBox::builder()
+            .collection(vec![rs!(IDS_SIGNAL_NORMAL).to_string(), rs!(IDS_SIGNAL_MIN_ENERGY).to_string()])
+            .parent(&self.window)
+            .build(&mut self.signal)?;
+
+        /* in the order of SettingsForm::BATTERY_MODES */
+        ComboBox::builder()
+            .collection(vec![
+                rs!(IDS_BATTERY_IGNORE).to_string(),
+                rs!(IDS_BATTERY_PAUSE).to_string(),
+                rs!(IDS_BATTERY_SLOW_DOWN).to_string(),
+            ])
+            .parent(&self.window)
+            .build(&mut self.battery_mode)?;
+
+        ListBox::builder()
+            .flags(ListBoxFlags::VISIBLE | ListBoxFlags::TAB_STOP | ListBoxFlags::MULTI_SELECT)
+            .parent(&self.window)
+            .build(&mut self.devices)?;
+
+        CheckBox::builder()
+            .text(rs!(IDS_PAUSE_WHEN_LOCKED))
+            .parent(&self.window)
+            .build(&mut self.pause_when_locked)?;
+
+        CheckBox::builder()
+            .text(rs!(IDS_NOTIFICATIONS))
+            .parent(&self.window)
+            .build(&mut self.notifications)?;
+
+        CheckBox::builder()
+            .text(rs!(IDS_START_AT_LOGIN))
+            .parent(&self.window)
+            .build(&mut self.autostart)?;
+
+        Label::builder().text("").parent(&self.window).build(&mut self.error)?;
+
+        Button::builder()
+            .text(rs!(IDS_OK))
+            .parent(&self.window)
+            .build(&mut self.ok_button)?;
+
+        Button::builder()
+            .text(rs!(IDS_CANCEL))
+            .parent(&self.window)
+            .build(&mut self.cancel_button)?;
+
+        /* the device list takes three rows */
+        GridLayout::builder()
+            .parent(&self.window)
+            .spacing(2)
+            .max_column(Some(2))
+            .max_row(Some(16))
+            .child(0, 0, &self.period_label)
+            .child(1, 0, &self.period)
+            .child(0, 1, &self.jitter_label)
+            .child(1, 1, &self.jitter)
+            .child(0, 2, &self.signal_label)
+            .child(1, 2, &self.signal)
+            .child(0, 3, &self.devices_label)
+            .child_item(GridLayoutItem::new(&self.devices, 1, 3, 1, 3))
+            .child(0, 6, &self.idle_label)
+            .child(1, 6, &self.idle)
+            .child_item(GridLayoutItem::new(&self.pause_when_locked, 0, 7, 2, 1))
+            .child(0, 8, &self.battery_mode_label)
+            .child(1, 8, &self.battery_mode)
+            .child(0, 9, &self.battery_threshold_label)
+            .child(1, 9, &self.battery_threshold)
+            .child(0, 10, &self.battery_period_label)
+            .child(1, 10, &self.battery_period)
+            .child_item(GridLayoutItem::new(&self.notifications, 0, 11, 2, 1))
+            .child_item(GridLayoutItem::new(&self.autostart, 0, 12, 2, 1))
+            .child(0, 13, &self.log_level_label)
+            .child(1, 13, &self.log_level)
+            .child_item(GridLayoutItem::new(&self.error, 0, 14, 2, 1))
+            .child(0, 15, &self.ok_button)
+            .child(1, 15, &self.cancel_button)
+            .build(&self.layout)
+    }
+
+    /// Fills the window with the form and brings it up.
+    pub fn show(&self, form: &SettingsForm) {
+        self.period.set_text(&form.period_ms);
+        self.jitter.set_text(&form.jitter_percent);
+        self.signal.set_selection(Some(if form.min_energy {
+            SIGNAL_MIN_ENERGY
+        } else {
+            SIGNAL_NORMAL
+        }));
+
+        self.devices
+            .set_collection(form.devices.iter().map(|(name, _)| name.clone()).collect());
+        for (i, (_, selected)) in form.devices.iter().enumerate() {
+            if *selected {
+                self.devices.multi_add_selection(i);
+            }
+        }
+
+        self.idle.set_text(&form.idle_timeout_min);
+        self.pause_when_locked.set_check_state(check_state(form.pause_when_locked));
+        self.battery_mode.set_selection(
+            SettingsForm::BATTERY_MODES
+                .iter()
+                .position(|mode| *mode == form.battery_mode),
+        );
+        self.battery_threshold.set_text(&form.battery_threshold_percent);
+        self.battery_period.set_text(&form.battery_period_ms);
+        self.notifications.set_check_state(check_state(form.notifications));
+        self.autostart.set_check_state(check_state(form.autostart));
+        self.log_level.set_text(&form.log_level);
+        self.error.set_text("");
+
+        self.window.set_visible(true);
+        self.window.set_focus();
+    }
+
+    pub fn form(&self) -> SettingsForm {
+        let devices = self
+            .devices
+            .collection()
+            .iter()
+            .enumerate()
+            .map(|(i, name)| (name.clone(), self.devices.selected(i)))
+            .collect();
+
+        SettingsForm {
+            period_ms: self.period.text(),
+            jitter_percent: self.jitter.text(),
+            min_energy: self.signal.selection() == Some(SIGNAL_MIN_ENERGY),
+            devices,
+            idle_timeout_min: self.idle.text(),
+            pause_when_locked: self.pause_when_locked.check_state() == CheckBoxState::Checked,
+            battery_mode: self
+                .battery_mode
+                .selection()
+                .and_then(|i| SettingsForm::BATTERY_MODES.get(i).copied())
+                .unwrap_or(BatteryMode::Ignore),
+            battery_threshold_percent: self.battery_threshold.text(),
+            battery_period_ms: self.battery_period.text(),
+            notifications: self.notifications.check_state() == CheckBoxState::Checked,
+            autostart: self.autostart.check_state() == CheckBoxState::Checked,
+            log_level: self.log_level.text(),
+        }
+    }
+
+    /// Shows why the form was not applied. The window stays open.
+    pub fn show_error(&self, error: &str) {
+        self.error.set_text(error);
+    }
+
+    pub fn hide(&self) {
+        self.window.set_visible(false);
+    }
+}
+
+fn check_state(checked: bool) -> CheckBoxState {
+    if checked {
+        CheckBoxState::Checked
+    } else {
+        CheckBoxState::Unchecked
+    }
+}
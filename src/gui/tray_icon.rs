@@ -1,41 +1,105 @@
-use crate::gui::res_ids::{IDI_APP_ICON, IDI_APP_ICON_GRAY};
+use crate::gui::res_ids::IDI_APP_ICON;
 use crate::gui::RESOURCES;
 use crate::r_icon;
-use crate::util::{hwnd, start_timer, stop_timer};
-use log::{trace};
-use native_windows_gui::{MessageWindow, TrayNotification};
+use crate::tray::render::render;
+use crate::tray::{Tray, TrayIcon, ICON_BLINK_PERIOD_MS};
+use crate::util::{hwnd, small_icon_size, start_timer, stop_timer};
+use log::{trace, warn};
+use native_windows_gui::{Icon, MessageWindow, TrayNotification};
+use std::cell::RefCell;
+use std::collections::HashMap;
+use windows::Win32::UI::WindowsAndMessaging::{CreateIcon, DestroyIcon, HICON};
 
 pub const TIMER_ICON_BLINK: usize = 411;
-const TIMER_ICON_BLINK_PERIOD_MS: u32 = 500;
 
-pub fn start_blink_icon(window: &MessageWindow, tray: &TrayNotification) {
-    set_busy_icon(tray, true);
+thread_local! {
+    /* rendered at the current tray icon size, and again when the DPI changes it */
+    static STATUS_ICONS: RefCell<Option<StatusIcons>> = const { RefCell::new(None) };
+}
+
+struct StatusIcons {
+    size: u32,
+    icons: HashMap<TrayIcon, Icon>,
+}
+
+impl StatusIcons {
+    fn new(size: u32) -> Self {
+        let icons = TrayIcon::ALL
+            .into_iter()
+            .filter_map(|icon| match create_icon(icon, size) {
+                Ok(created) => Some((icon, created)),
+                Err(e) => {
+                    warn!("{}", e);
+                    None
+                }
+            })
+            .collect();
+
+        Self { size, icons }
+    }
+}
+
+impl Drop for StatusIcons {
+    fn drop(&mut self) {
+        for icon in self.icons.values() {
+            unsafe { DestroyIcon(HICON(icon.handle as _)) }.unwrap_or_default();
+        }
+    }
+}
+
+impl Tray for TrayNotification {
+    fn show_icon(&self, icon: TrayIcon) {
+        STATUS_ICONS.with_borrow_mut(|icons| {
+            let size = small_icon_size();
+            if icons.as_ref().is_none_or(|icons| icons.size != size) {
+                trace!("Rendering tray icons at {} px", size);
+                *icons = Some(StatusIcons::new(size));
+            }
+
+            match icons.as_ref().and_then(|icons| icons.icons.get(&icon)) {
+                Some(icon) => self.set_icon(icon),
+                None => {
+                    if let Some(icon) = r_icon!(IDI_APP_ICON) {
+                        self.set_icon(&icon);
+                    }
+                }
+            }
+        });
+    }
 
+    fn show_tip(&self, tip: &str) {
+        self.set_tip(tip);
+    }
+}
+
+fn create_icon(icon: TrayIcon, size: u32) -> Result<Icon, String> {
+    let color = render(icon, size).to_bgra();
+    /* the alpha channel of a 32-bit color bitmap takes precedence over the mask.
+    mask rows are word aligned */
+    let mask = vec![0u8; (size.div_ceil(16) * 2 * size) as usize];
+
+    let handle = unsafe {
+        CreateIcon(None, size as i32, size as i32, 1, 32, mask.as_ptr(), color.as_ptr())
+    }
+    .map_err(|e| format!("Failed to create tray icon. {}", e.message()))?;
+
+    let mut icon = Icon::default();
+    icon.handle = handle.0 as _;
+    Ok(icon)
+}
+
+pub fn start_blink_icon(window: &MessageWindow, tray: &impl Tray) {
     trace!("Starting icon blink");
-    
-    if start_timer(
-        hwnd(window.handle),
-        TIMER_ICON_BLINK,
-        TIMER_ICON_BLINK_PERIOD_MS,
-    )
-    .is_err()
-    {
-        set_busy_icon(tray, false);
+
+    if start_timer(hwnd(window.handle), TIMER_ICON_BLINK, ICON_BLINK_PERIOD_MS).is_ok() {
+        tray.show_icon(TrayIcon::Busy);
     }
 }
 
-pub fn stop_blink_icon(window: &MessageWindow, tray: &TrayNotification) {
+/// Restores the status icon shown before the blink.
+pub fn stop_blink_icon(window: &MessageWindow, tray: &impl Tray, icon: TrayIcon) {
     trace!("Stopping icon blink ");
-    
-    set_busy_icon(tray, false);
-    stop_timer(hwnd(window.handle), TIMER_ICON_BLINK);
-}
 
-fn set_busy_icon(tray: &TrayNotification, busy: bool) {
-    let icon_res = if busy {
-        IDI_APP_ICON_GRAY
-    } else {
-        IDI_APP_ICON
-    };
-    tray.set_icon(&r_icon!(icon_res));
+    tray.show_icon(icon);
+    stop_timer(hwnd(window.handle), TIMER_ICON_BLINK);
 }
@@ -0,0 +1,711 @@
+use crate::audio::{waveform_duration_ms, AudioOutput, PlatformAudioOutput, PlatformDeviceSource};
+use crate::battery::{BatteryMonitor, PlatformPowerSource, TIMER_BATTERY_PERIOD_MS};
+use crate::core::{Core, Effect, Event, Timer};
+#[cfg(target_os = "linux")]
+use crate::core::TipLine;
+#[cfg(target_os = "linux")]
+use crate::dbus::DbusService;
+use crate::devices::{DeviceMonitor, TIMER_DEVICES_PERIOD_MS};
+use crate::hooks::Hooks;
+use crate::idle::{IdleMonitor, PlatformIdleSource, TIMER_IDLE_PERIOD_MS};
+use crate::instance::InstanceLock;
+use crate::ipc::Request;
+use crate::mqtt::{MqttClient, TIMER_MQTT_PERIOD_MS};
+#[cfg(target_os = "linux")]
+use crate::logind;
+#[cfg(target_os = "linux")]
+use crate::power::{watch_sleep, PowerEvent, SleepInhibitor};
+#[cfg(target_os = "linux")]
+use crate::session::{watch_lock, SessionEvent};
+use crate::settings::{BatteryMode, Settings};
+use crate::supervisor::catch_panic;
+#[cfg(target_os = "linux")]
+use crate::settings::settings_path;
+#[cfg(target_os = "linux")]
+use crate::notifications::DesktopNotifier;
+#[cfg(target_os = "linux")]
+use crate::status::State;
+#[cfg(target_os = "linux")]
+use crate::systemd::SystemdNotifier;
+#[cfg(target_os = "linux")]
+use crate::tray::sni::{tip_text, SniTray};
+#[cfg(target_os = "linux")]
+use crate::tray::{MenuAction, Tray};
+use crate::{autostart, ipc, logging, metrics};
+use log::{debug, warn};
+use std::collections::HashMap;
+use std::sync::mpsc::{channel, Receiver, Sender};
+use std::time::{Duration, Instant};
+
+/* nothing is scheduled only while paused with no event sources */
+const MAX_WAIT: Duration = Duration::from_secs(60);
+
+/// Deadlines the loop waits for. Core timers are one-shot, the rest are polls.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+enum Tick {
+    Core(Timer),
+    Idle,
+    Battery,
+    Devices,
+    Publish,
+    #[cfg(target_os = "linux")]
+    Watchdog,
+}
+
+/// Front-end without a window. Runs the core on the main thread and sleeps on a channel that
+/// every background source wakes up.
+struct Runner {
+    core: Core,
+    output: Option<PlatformAudioOutput>,
+    hooks: Hooks,
+    deadlines: HashMap<Tick, Instant>,
+    wake: Sender<()>,
+    idle: Option<IdleMonitor<PlatformIdleSource>>,
+    battery: Option<BatteryMonitor<PlatformPowerSource>>,
+    devices: Option<DeviceMonitor<PlatformDeviceSource>>,
+    ipc_requests: Option<Receiver<Request>>,
+    mqtt: Option<MqttClient>,
+    mqtt_requests: Option<Receiver<Request>>,
+    /* from the service manager */
+    control_requests: Option<Receiver<Request>>,
+    #[cfg(target_os = "linux")]
+    dbus: Option<(DbusService, Receiver<Request>)>,
+    #[cfg(target_os = "linux")]
+    tray: Option<(SniTray, Receiver<MenuAction>)>,
+    #[cfg(target_os = "linux")]
+    notifier: Option<DesktopNotifier>,
+    #[cfg(target_os = "linux")]
+    systemd: Option<SystemdNotifier>,
+    #[cfg(target_os = "linux")]
+    power_events: Option<Receiver<PowerEvent>>,
+    #[cfg(target_os = "linux")]
+    sleep_inhibitor: Option<SleepInhibitor>,
+    #[cfg(target_os = "linux")]
+    session_events: Option<Receiver<SessionEvent>>,
+    /* set once the loop has to end, with the error to exit with if it aborts */
+    exit: Option<Result<(), String>>,
+}
+
+impl Runner {
+    fn new(settings: Settings, wake: Sender<()>) -> Self {
+        Self {
+            core: Core::new(settings, Settings::load),
+            output: None,
+            hooks: Hooks::default(),
+            deadlines: HashMap::new(),
+            wake,
+            idle: None,
+            battery: None,
+            devices: None,
+            ipc_requests: None,
+            mqtt: None,
+            mqtt_requests: None,
+            control_requests: None,
+            #[cfg(target_os = "linux")]
+            dbus: None,
+            #[cfg(target_os = "linux")]
+            tray: None,
+            #[cfg(target_os = "linux")]
+            notifier: None,
+            #[cfg(target_os = "linux")]
+            systemd: SystemdNotifier::from_env(),
+            #[cfg(target_os = "linux")]
+            power_events: None,
+            #[cfg(target_os = "linux")]
+            sleep_inhibitor: None,
+            #[cfg(target_os = "linux")]
+            session_events: None,
+            exit: None,
+        }
+    }
+
+    fn run(&mut self, wake: Receiver<()>) -> Result<(), String> {
+        self.start_sources();
+        let effects = self.core.start();
+        self.perform(effects);
+        self.check_autostart();
+        self.start_ipc_server();
+        self.schedule(Tick::Publish, TIMER_MQTT_PERIOD_MS);
+        #[cfg(target_os = "linux")]
+        self.start_watchdog();
+
+        if let Some(port) = self.core.settings().metrics_port {
+            metrics::start_server(port).unwrap_or_else(|e| warn!("{}", e));
+        }
+
+        debug!("Application started");
+
+        while self.exit.is_none() {
+            let now = Instant::now();
+            let timeout = self
+                .deadlines
+                .values()
+                .min()
+                .map(|deadline| deadline.saturating_duration_since(now))
+                .unwrap_or(MAX_WAIT);
+
+            /* the message only wakes the loop up. the sources are drained below */
+            wake.recv_timeout(timeout).unwrap_or_default();
+
+            /* a panic may have lost the audio tick. a second one while recovering ends the process
+             * with an error, and the service manager restarts it */
+            let result = catch_panic(|| {
+                self.on_ticks();
+                self.on_requests();
+                #[cfg(target_os = "linux")]
+                self.on_menu_actions();
+                #[cfg(target_os = "linux")]
+                self.on_logind_events();
+            });
+            if let Err(message) = result {
+                self.on_audio_panic(message);
+            }
+        }
+
+        /* a hook of the stopped event would not even start if the process exited now */
+        self.hooks.wait(Instant::now() + self.hooks.timeout());
+
+        self.exit.take().unwrap_or(Ok(()))
+    }
+
+    fn dispatch(&mut self, event: Event) {
+        let effects = self.core.handle(event);
+        self.perform(effects);
+    }
+
+    fn perform(&mut self, effects: Vec<Effect>) {
+        for effect in effects {
+            match effect {
+                Effect::Play => {
+                    let result = match self.output.as_mut() {
+                        Some(output) => catch_panic(|| output.play()),
+                        None => Ok(Err("No audio output".to_string())),
+                    };
+                    let result = match result {
+                        Ok(result) => result,
+                        Err(message) => {
+                            self.on_audio_panic(message);
+                            continue;
+                        }
+                    };
+                    #[cfg(target_os = "linux")]
+                    if let (Some(systemd), Ok(())) = (&self.systemd, &result) {
+                        systemd.watchdog();
+                    }
+                    self.dispatch(Event::Played(result));
+                }
+                Effect::StartTimer(timer, delay_ms) => {
+                    #[cfg(target_os = "linux")]
+                    if let (Some(systemd), Timer::Audio) = (self.systemd.as_mut(), timer) {
+                        systemd.expect_ping_within(Duration::from_millis(delay_ms as u64));
+                    }
+                    self.schedule(Tick::Core(timer), delay_ms);
+                }
+                Effect::StopTimer(timer) => {
+                    self.deadlines.remove(&Tick::Core(timer));
+                }
+                Effect::CloseDevice => {
+                    if let Some(Err(message)) = self.output.as_mut().map(|output| catch_panic(|| output.close())) {
+                        self.on_audio_panic(message);
+                    }
+                }
+                Effect::ReopenDevice => {
+                    if let Some(Err(message)) = self.output.as_mut().map(|output| catch_panic(|| output.reopen())) {
+                        self.on_audio_panic(message);
+                    }
+                }
+                #[cfg(target_os = "linux")]
+                Effect::Blink => {
+                    if let Some((tray, _)) = &self.tray {
+                        tray.blink();
+                    }
+                }
+                #[cfg(target_os = "linux")]
+                Effect::UpdateIcon(icon) => {
+                    if let Some((tray, _)) = &self.tray {
+                        tray.show_icon(icon);
+                    }
+                }
+                #[cfg(target_os = "linux")]
+                Effect::UpdateTip(lines) => {
+                    let text = tip_text(&lines);
+                    if let Some((tray, _)) = &self.tray {
+                        tray.show_tip(&text);
+                    }
+                    if let Some(systemd) = &self.systemd {
+                        systemd.status(&text);
+                    }
+                }
+                #[cfg(target_os = "linux")]
+                Effect::UpdateDevices(items) => {
+                    if let Some((tray, _)) = &self.tray {
+                        tray.show_devices(items);
+                    }
+                }
+                #[cfg(target_os = "linux")]
+                Effect::UpdateAutostart(enabled) => {
+                    if let Some((tray, _)) = &self.tray {
+                        tray.show_autostart(enabled);
+                    }
+                }
+                #[cfg(target_os = "linux")]
+                Effect::ShowSettings => open_settings_file(self.core.settings()).unwrap_or_else(|e| warn!("{}", e)),
+                #[cfg(target_os = "linux")]
+                Effect::Notify(notice) => {
+                    if let Some(notifier) = &self.notifier {
+                        notifier.show(&notice).unwrap_or_else(|e| warn!("{}", e));
+                    }
+                }
+                #[cfg(not(target_os = "linux"))]
+                Effect::Blink
+                | Effect::UpdateIcon(_)
+                | Effect::UpdateTip(_)
+                | Effect::UpdateDevices(_)
+                | Effect::UpdateAutostart(_)
+                | Effect::ShowSettings
+                | Effect::Notify(_) => {}
+                Effect::RunHooks(payload) => self.hooks.fire(payload),
+                Effect::SelectDevices(devices) => self.open_output(&devices),
+                Effect::SaveSettings(settings) => settings.save().unwrap_or_else(|e| warn!("{}", e)),
+                Effect::SetAutostart(enabled) => {
+                    autostart::set_enabled(enabled).unwrap_or_else(|e| warn!("{}", e));
+                    self.check_autostart();
+                }
+                Effect::Reconfigure => {
+                    logging::reconfigure(&self.core.settings().log);
+                    self.stop_sources();
+                    self.start_sources();
+                }
+                Effect::RestartAudio => {
+                    let devices = self.core.settings().devices.clone();
+                    self.open_output(&devices);
+                }
+                Effect::Exit => self.shut_down(Ok(())),
+                Effect::Abort(e) => self.shut_down(Err(e)),
+            }
+        }
+
+        #[cfg(target_os = "linux")]
+        self.publish_dbus();
+    }
+
+    fn shut_down(&mut self, result: Result<(), String>) {
+        #[cfg(target_os = "linux")]
+        if let Some(systemd) = &self.systemd {
+            systemd.stopping();
+        }
+        self.stop_sources();
+        self.exit = Some(result);
+    }
+
+    /// Drops the output a panic has left half done and lets the core decide on a restart.
+    fn on_audio_panic(&mut self, message: String) {
+        /* its panic is logged by the hook, nothing more to do with it */
+        catch_panic(|| self.output = None).unwrap_or_default();
+        self.dispatch(Event::AudioPanicked(message));
+    }
+
+    fn schedule(&mut self, tick: Tick, delay_ms: u32) {
+        let deadline = Instant::now() + Duration::from_millis(delay_ms as u64);
+        self.deadlines.insert(tick, deadline);
+    }
+
+    fn on_ticks(&mut self) {
+        let now = Instant::now();
+        let due: Vec<Tick> = self
+            .deadlines
+            .iter()
+            .filter(|(_, deadline)| **deadline <= now)
+            .map(|(tick, _)| *tick)
+            .collect();
+
+        for tick in due {
+            self.deadlines.remove(&tick);
+
+            match tick {
+                Tick::Core(timer) => self.dispatch(Event::Timer(timer)),
+                Tick::Idle => self.on_idle_poll(),
+                Tick::Battery => self.on_battery_poll(),
+                Tick::Devices => self.on_devices_poll(),
+                Tick::Publish => {
+                    self.publish();
+                    self.schedule(Tick::Publish, TIMER_MQTT_PERIOD_MS);
+                }
+                #[cfg(target_os = "linux")]
+                Tick::Watchdog => self.on_watchdog_tick(),
+            }
+        }
+    }
+
+    fn on_idle_poll(&mut self) {
+        let Some(monitor) = self.idle.as_mut() else {
+            return;
+        };
+        let change = monitor.poll();
+        self.schedule(Tick::Idle, TIMER_IDLE_PERIOD_MS);
+
+        if let Some(state) = change {
+            self.dispatch(Event::Idle(state));
+        }
+    }
+
+    fn on_battery_poll(&mut self) {
+        let Some(monitor) = self.battery.as_mut() else {
+            return;
+        };
+        let change = monitor.poll().map(|status| Event::Battery {
+            status,
+            saving: monitor.is_saving(),
+        });
+        self.schedule(Tick::Battery, TIMER_BATTERY_PERIOD_MS);
+
+        if let Some(event) = change {
+            self.dispatch(event);
+        }
+    }
+
+    fn on_devices_poll(&mut self) {
+        let Some(monitor) = self.devices.as_mut() else {
+            return;
+        };
+        let change = monitor.poll();
+        self.schedule(Tick::Devices, TIMER_DEVICES_PERIOD_MS);
+
+        if let Some(devices) = change {
+            self.dispatch(Event::DevicesConnected(devices));
+        }
+    }
+
+    fn on_requests(&mut self) {
+        let receivers = [&self.ipc_requests, &self.mqtt_requests, &self.control_requests]
+            .into_iter()
+            .flatten();
+        #[cfg(target_os = "linux")]
+        let receivers = receivers.chain(self.dbus.as_ref().map(|(_, receiver)| receiver));
+
+        let requests: Vec<Request> = receivers
+            .flat_map(|receiver| receiver.try_iter().collect::<Vec<_>>())
+            .collect();
+
+        if !requests.is_empty() {
+            /* the command line may have changed it */
+            self.check_autostart();
+        }
+        for request in requests {
+            let (response, effects) = self.core.execute(request.command);
+            /* a client reading a property right after its call sees the change */
+            #[cfg(target_os = "linux")]
+            self.publish_dbus();
+            request.reply(response);
+            self.perform(effects);
+        }
+    }
+
+    #[cfg(target_os = "linux")]
+    fn on_menu_actions(&mut self) {
+        let actions: Vec<MenuAction> = self
+            .tray
+            .as_ref()
+            .map(|(_, receiver)| receiver.try_iter().collect())
+            .unwrap_or_default();
+
+        for action in actions {
+            self.dispatch(Event::Menu(action));
+        }
+    }
+
+    #[cfg(target_os = "linux")]
+    fn on_logind_events(&mut self) {
+        let events: Vec<PowerEvent> = self
+            .power_events
+            .as_ref()
+            .map(|receiver| receiver.try_iter().collect())
+            .unwrap_or_default();
+
+        for event in events {
+            /* the device is closed once the suspend is dispatched, so the system may go to sleep */
+            match event {
+                PowerEvent::Suspend => {
+                    self.dispatch(Event::Power(event));
+                    if let Some(inhibitor) = &mut self.sleep_inhibitor {
+                        inhibitor.release();
+                    }
+                }
+                PowerEvent::Resume => {
+                    if let Some(inhibitor) = &mut self.sleep_inhibitor {
+                        inhibitor.take();
+                    }
+                    self.dispatch(Event::Power(event));
+                }
+            }
+        }
+
+        let events: Vec<SessionEvent> = self
+            .session_events
+            .as_ref()
+            .map(|receiver| receiver.try_iter().collect())
+            .unwrap_or_default();
+
+        /* read always, not to act on a stale lock once the setting is turned on */
+        if self.core.settings().pause_when_locked {
+            for event in events {
+                self.dispatch(Event::Session(event));
+            }
+        }
+    }
+
+    fn check_autostart(&mut self) {
+        match autostart::is_enabled() {
+            Ok(enabled) => self.dispatch(Event::Autostart(enabled)),
+            Err(e) => warn!("{}", e),
+        }
+    }
+
+    #[cfg(target_os = "linux")]
+    fn start_watchdog(&mut self) {
+        let Some(systemd) = &self.systemd else {
+            return;
+        };
+        systemd.ready();
+        if let Some(interval) = systemd.watchdog_interval() {
+            self.schedule(Tick::Watchdog, interval.as_millis() as u32);
+        }
+    }
+
+    /// Pings tell the watchdog that keep-alive works. Without pings to wait for, it is told
+    /// directly.
+    #[cfg(target_os = "linux")]
+    fn on_watchdog_tick(&mut self) {
+        let Some(systemd) = &self.systemd else {
+            return;
+        };
+        if matches!(self.core.state(), State::Paused | State::Suspended) {
+            systemd.watchdog();
+        }
+        if let Some(interval) = systemd.watchdog_interval() {
+            self.schedule(Tick::Watchdog, interval.as_millis() as u32);
+        }
+    }
+
+    fn publish(&mut self) {
+        let status = self.core.status();
+        if let Some(mqtt) = self.mqtt.as_mut() {
+            mqtt.publish(&status);
+        }
+    }
+
+    /// Updates the D-Bus properties as soon as the status changes. Only changed properties are
+    /// signalled, so this is cheap to call after every event.
+    #[cfg(target_os = "linux")]
+    fn publish_dbus(&self) {
+        if let Some((dbus, _)) = &self.dbus {
+            dbus.publish(&self.core.status());
+        }
+    }
+
+    /// Creates the audio output and event sources from the current settings.
+    fn start_sources(&mut self) {
+        let settings = self.core.settings().clone();
+
+        self.open_output(&settings.devices);
+
+        self.devices = Some(DeviceMonitor::new(PlatformDeviceSource));
+        self.on_devices_poll();
+
+        self.hooks = Hooks::new(&settings.hooks);
+
+        if settings.idle_timeout_min > 0 {
+            let timeout = Duration::from_secs(settings.idle_timeout_min as u64 * 60);
+            self.idle = Some(IdleMonitor::new(Default::default(), timeout));
+            self.schedule(Tick::Idle, TIMER_IDLE_PERIOD_MS);
+        }
+
+        if settings.battery_mode != BatteryMode::Ignore {
+            self.battery = Some(BatteryMonitor::new(
+                PlatformPowerSource,
+                settings.battery_threshold_percent,
+            ));
+            /* apply the current power state right away */
+            self.on_battery_poll();
+        }
+
+        if let Some(mqtt) = &settings.mqtt {
+            match MqttClient::start(mqtt, self.waker()) {
+                Ok((client, receiver)) => {
+                    self.mqtt = Some(client);
+                    self.mqtt_requests = Some(receiver);
+                }
+                Err(e) => warn!("{}", e),
+            }
+        }
+    }
+
+    fn stop_sources(&mut self) {
+        self.deadlines.remove(&Tick::Idle);
+        self.deadlines.remove(&Tick::Battery);
+        self.deadlines.remove(&Tick::Devices);
+        if let Some(mqtt) = self.mqtt.take() {
+            mqtt.stop();
+        }
+        self.mqtt_requests = None;
+        self.idle = None;
+        self.battery = None;
+        self.devices = None;
+        self.output = None;
+    }
+
+    /// Replaces the audio output with one playing on the given devices.
+    fn open_output(&mut self, devices: &[String]) {
+        /* the devices of the old output are released first */
+        self.output = None;
+
+        let duration_ms = waveform_duration_ms(self.core.settings());
+        let opened = catch_panic(|| {
+            let mut output = PlatformAudioOutput::new(duration_ms, devices);
+            /* the first ping retries. failures are logged by device */
+            output.open().unwrap_or_default();
+            let names = output.device_names();
+            (output, names)
+        });
+        match opened {
+            Ok((output, names)) => {
+                self.output = Some(output);
+                self.dispatch(Event::DevicesChanged(names));
+            }
+            Err(message) => self.on_audio_panic(message),
+        }
+    }
+
+    fn start_ipc_server(&mut self) {
+        let scope = self.core.settings().instance_scope;
+        match ipc::start_server(scope, self.waker()) {
+            Ok(receiver) => self.ipc_requests = Some(receiver),
+            Err(e) => warn!("{}", e),
+        }
+    }
+
+    /// Watches logind for suspend, resume and locks of the session. Unlike the other sources it
+    /// does not depend on the settings and runs for the lifetime of the application.
+    #[cfg(target_os = "linux")]
+    fn start_logind(&mut self) {
+        let connection = match logind::system_bus() {
+            Ok(connection) => connection,
+            Err(e) => {
+                warn!("{}", e);
+                return;
+            }
+        };
+
+        match watch_sleep(&connection, self.waker()) {
+            Ok(receiver) => {
+                self.power_events = Some(receiver);
+                self.sleep_inhibitor = Some(SleepInhibitor::new(&connection));
+            }
+            Err(e) => warn!("{}", e),
+        }
+
+        let watched = logind::session_path(&connection)
+            .and_then(|path| watch_lock(&connection, path.as_str(), self.waker()));
+        match watched {
+            Ok(receiver) => self.session_events = Some(receiver),
+            /* a system service has no session to lock */
+            Err(e) if self.core.settings().pause_when_locked => warn!("{}", e),
+            Err(e) => debug!("{}", e),
+        }
+    }
+
+    #[cfg(target_os = "linux")]
+    fn start_dbus_service(&mut self) {
+        match DbusService::start(self.waker()) {
+            Ok(service) => self.dbus = Some(service),
+            Err(e) => warn!("{}", e),
+        }
+    }
+
+    #[cfg(target_os = "linux")]
+    fn start_tray(&mut self) {
+        let tip = tip_text(&[TipLine::State(State::Active)]);
+        match SniTray::start(&tip, self.waker()) {
+            Ok(tray) => self.tray = Some(tray),
+            Err(e) => warn!("{}", e),
+        }
+
+        /* notifications go with the tray, a service has nobody to show them to */
+        match DesktopNotifier::new() {
+            Ok(notifier) => self.notifier = Some(notifier),
+            Err(e) => warn!("{}", e),
+        }
+    }
+
+    fn waker(&self) -> impl Fn() + Send + Sync + 'static {
+        let wake = self.wake.clone();
+        move || wake.send(()).unwrap_or_default()
+    }
+}
+
+/// Opens the settings file in the default editor, writing the current settings first if there
+/// is no file yet. Changes apply on the reload command.
+#[cfg(target_os = "linux")]
+fn open_settings_file(settings: &Settings) -> Result<(), String> {
+    let path = settings_path()?;
+    if !path.exists() {
+        settings.save()?;
+    }
+
+    let mut child = std::process::Command::new("xdg-open")
+        .arg(&path)
+        .spawn()
+        .map_err(|e| format!("Failed to open {}. {}", path.display(), e))?;
+    /* reaped in the background, the editor may outlive it */
+    std::thread::spawn(move || child.wait());
+
+    Ok(())
+}
+
+/// Runs the application without a window. On Linux `tray` adds a StatusNotifierItem.
+pub(crate) fn run_main(tray: bool) -> Result<(), String> {
+    run(tray, |_| None)
+}
+
+/// Runs the application as a service. `connect` gets the function that wakes the application up
+/// and returns the receiver of service control requests. It is called once the application is
+/// about to run.
+#[cfg(windows)]
+pub(crate) fn run_service<F>(connect: F) -> Result<(), String>
+where
+    F: FnOnce(Box<dyn Fn() + Send + Sync>) -> Receiver<Request>,
+{
+    run(false, |wake| Some(connect(wake)))
+}
+
+fn run<F>(tray: bool, connect: F) -> Result<(), String>
+where
+    F: FnOnce(Box<dyn Fn() + Send + Sync>) -> Option<Receiver<Request>>,
+{
+    let settings = Settings::load().unwrap_or_else(|e| {
+        warn!("{}", e);
+        Settings::default()
+    });
+
+    /* held until the application exits */
+    let _lock = InstanceLock::acquire(settings.instance_scope).map_err(|e| e.to_string())?;
+
+    let (sender, receiver) = channel();
+    let mut runner = Runner::new(settings, sender);
+
+    #[cfg(target_os = "linux")]
+    {
+        runner.start_logind();
+        runner.start_dbus_service();
+        if tray {
+            runner.start_tray();
+        }
+    }
+    #[cfg(not(target_os = "linux"))]
+    if tray {
+        warn!("The headless front-end has no tray icon");
+    }
+
+    runner.control_requests = connect(Box::new(runner.waker()));
+    runner.run(receiver)
+}
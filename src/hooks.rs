@@ -0,0 +1,406 @@
+use crate::audio::PauseReason;
+use chrono::{DateTime, Utc};
+use log::{debug, warn};
+use serde::{Deserialize, Serialize};
+use std::io::Write;
+use std::process::{Child, Command, Stdio};
+use std::thread;
+use std::thread::JoinHandle;
+use std::time::{Duration, Instant};
+
+const ENV_PREFIX: &str = "KEEP_AUDIO_AWAKE";
+const DEFAULT_TIMEOUT_SECS: u32 = 10;
+const POLL_INTERVAL: Duration = Duration::from_millis(100);
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum HookEvent {
+    Started,
+    Stopped,
+    Paused,
+    Resumed,
+    /// The first failure after the device worked.
+    DeviceLost,
+    /// The first successful ping after failures.
+    DeviceRecovered,
+    /// Every failure to open the device or write to it.
+    PlayFailed,
+}
+
+impl HookEvent {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            HookEvent::Started => "started",
+            HookEvent::Stopped => "stopped",
+            HookEvent::Paused => "paused",
+            HookEvent::Resumed => "resumed",
+            HookEvent::DeviceLost => "device_lost",
+            HookEvent::DeviceRecovered => "device_recovered",
+            HookEvent::PlayFailed => "play_failed",
+        }
+    }
+}
+
+/// Shell command run on an event.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Hook {
+    pub event: HookEvent,
+    pub command: String,
+    /// The command is killed if it runs longer.
+    #[serde(default = "default_timeout_secs")]
+    pub timeout_secs: u32,
+}
+
+fn default_timeout_secs() -> u32 {
+    DEFAULT_TIMEOUT_SECS
+}
+
+/// Event details passed to hooks as JSON on stdin and as environment variables.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct Payload {
+    pub event: HookEvent,
+    pub time: DateTime<Utc>,
+    pub reason: Option<PauseReason>,
+    pub error: Option<String>,
+}
+
+impl Payload {
+    pub fn new(event: HookEvent) -> Self {
+        Self {
+            event,
+            time: Utc::now(),
+            reason: None,
+            error: None,
+        }
+    }
+
+    pub fn with_reason(self, reason: PauseReason) -> Self {
+        Self {
+            reason: Some(reason),
+            ..self
+        }
+    }
+
+    pub fn with_error(self, error: &str) -> Self {
+        Self {
+            error: Some(error.to_string()),
+            ..self
+        }
+    }
+
+    fn to_env(&self) -> Vec<(String, String)> {
+        let mut env = vec![
+            (format!("{}_EVENT", ENV_PREFIX), self.event.as_str().to_string()),
+            (format!("{}_TIME", ENV_PREFIX), self.time.to_rfc3339()),
+        ];
+        if let Some(reason) = self.reason {
+            /* the same spelling as in the JSON */
+            let reason = serde_json::to_value(reason)
+                .ok()
+                .and_then(|v| v.as_str().map(str::to_string))
+                .unwrap_or_default();
+            env.push((format!("{}_REASON", ENV_PREFIX), reason));
+        }
+        if let Some(error) = &self.error {
+            env.push((format!("{}_ERROR", ENV_PREFIX), error.clone()));
+        }
+        env
+    }
+}
+
+/// Runs configured hooks in background threads so the caller is never blocked.
+#[derive(Default)]
+pub struct Hooks {
+    hooks: Vec<Hook>,
+    /* threads of hooks that may still run, waited for on exit */
+    running: Vec<JoinHandle<()>>,
+}
+
+impl Hooks {
+    pub fn new(hooks: &[Hook]) -> Self {
+        Self {
+            hooks: hooks.to_vec(),
+            running: Vec::new(),
+        }
+    }
+
+    pub fn fire(&mut self, payload: Payload) {
+        self.running.retain(|thread| !thread.is_finished());
+
+        for hook in self.hooks.iter().filter(|h| h.event == payload.event) {
+            let hook = hook.clone();
+            let payload = payload.clone();
+
+            thread::Builder::new()
+                .name("hook".to_string())
+                .spawn(move || run(&hook, &payload).unwrap_or_else(|e| warn!("{}", e)))
+                .map(|thread| self.running.push(thread))
+                .unwrap_or_else(|e| warn!("Failed to start hook thread. {}", e));
+        }
+    }
+
+    /// Time after which every running hook has finished or been killed.
+    pub fn timeout(&self) -> Duration {
+        let timeout_secs = self.hooks.iter().map(|h| h.timeout_secs).max().unwrap_or(0);
+        /* killing a hook that timed out takes up to a poll more */
+        Duration::from_secs(timeout_secs as u64) + 2 * POLL_INTERVAL
+    }
+
+    /// Waits for the running hooks, such as the one of the stopped event before the process exits.
+    /// Gives up on the hooks still running at the deadline.
+    pub fn wait(&mut self, deadline: Instant) {
+        while self.running.iter().any(|thread| !thread.is_finished()) {
+            if Instant::now() >= deadline {
+                warn!("Hooks still running on exit");
+                break;
+            }
+            thread::sleep(POLL_INTERVAL / 10);
+        }
+        self.running.clear();
+    }
+}
+
+fn run(hook: &Hook, payload: &Payload) -> Result<(), String> {
+    debug!("Running {} hook: {}", payload.event.as_str(), hook.command);
+
+    let mut child = shell_command(&hook.command)
+        .envs(payload.to_env())
+        .stdin(Stdio::piped())
+        .stdout(Stdio::null())
+        .stderr(Stdio::null())
+        .spawn()
+        .map_err(|e| format!("Failed to run hook `{}`. {}", hook.command, e))?;
+    let group = ChildGroup::attach(&child);
+
+    if let Some(mut stdin) = child.stdin.take() {
+        let json = serde_json::to_string(payload).unwrap_or_default();
+        /* the hook is free to ignore its input */
+        stdin.write_all(json.as_bytes()).unwrap_or_default();
+    }
+
+    let status = wait_timeout(&mut child, &group, Duration::from_secs(hook.timeout_secs as u64))
+        .map_err(|e| format!("Hook `{}` {}", hook.command, e))?;
+
+    if status.success() {
+        debug!("Hook `{}` finished", hook.command);
+        Ok(())
+    } else {
+        Err(format!("Hook `{}` failed with {}", hook.command, status))
+    }
+}
+
+fn wait_timeout(child: &mut Child, group: &ChildGroup, timeout: Duration) -> Result<std::process::ExitStatus, String> {
+    let started = Instant::now();
+    loop {
+        if let Some(status) = child.try_wait().map_err(|e| format!("failed. {}", e))? {
+            return Ok(status);
+        }
+        if started.elapsed() >= timeout {
+            group.kill();
+            child.kill().unwrap_or_default();
+            child.wait().unwrap_or_default();
+            Err(format!("timed out after {} s", timeout.as_secs()))?
+        }
+        thread::sleep(POLL_INTERVAL);
+    }
+}
+
+#[cfg(windows)]
+use windows_hooks::ChildGroup;
+
+#[cfg(unix)]
+use unix_hooks::ChildGroup;
+
+#[cfg(windows)]
+mod windows_hooks {
+    use log::warn;
+    use std::os::windows::io::AsRawHandle;
+    use std::process::Child;
+    use windows::core::PCWSTR;
+    use windows::Win32::Foundation::{CloseHandle, HANDLE};
+    use windows::Win32::System::JobObjects::{AssignProcessToJobObject, CreateJobObjectW, TerminateJobObject};
+
+    /// Job object holding the hook and the processes it starts, so that a timeout kills them all.
+    /// Processes that outlive a finished hook are left running.
+    pub struct ChildGroup(Option<HANDLE>);
+
+    impl ChildGroup {
+        pub fn attach(child: &Child) -> Self {
+            let job = unsafe { CreateJobObjectW(None, PCWSTR::null()) }.and_then(|job| {
+                unsafe { AssignProcessToJobObject(job, HANDLE(child.as_raw_handle())) }
+                    .map(|()| job)
+                    .inspect_err(|_| unsafe { CloseHandle(job) }.unwrap_or_default())
+            });
+
+            match job {
+                Ok(job) => Self(Some(job)),
+                Err(e) => {
+                    warn!("Failed to create a job object for the hook. {}", e.message());
+                    Self(None)
+                }
+            }
+        }
+
+        pub fn kill(&self) {
+            if let Some(job) = self.0 {
+                unsafe { TerminateJobObject(job, 1) }.unwrap_or_else(|e| warn!("{}", e.message()));
+            }
+        }
+    }
+
+    impl Drop for ChildGroup {
+        fn drop(&mut self) {
+            if let Some(job) = self.0 {
+                unsafe { CloseHandle(job) }.unwrap_or_default();
+            }
+        }
+    }
+}
+
+#[cfg(unix)]
+mod unix_hooks {
+    use std::process::Child;
+
+    /// Process group the hook leads, as started by `shell_command`, so that a timeout kills the
+    /// processes the shell started as well.
+    pub struct ChildGroup(libc::pid_t);
+
+    impl ChildGroup {
+        pub fn attach(child: &Child) -> Self {
+            Self(child.id() as libc::pid_t)
+        }
+
+        pub fn kill(&self) {
+            /* the group stays valid until the leader is waited for */
+            unsafe { libc::kill(-self.0, libc::SIGKILL) };
+        }
+    }
+}
+
+#[cfg(windows)]
+fn shell_command(command: &str) -> Command {
+    use std::os::windows::process::CommandExt;
+    use windows::Win32::System::Threading::CREATE_NO_WINDOW;
+
+    let mut shell = Command::new("cmd");
+    /* cmd does its own parsing of the command line */
+    shell.arg("/C").raw_arg(command).creation_flags(CREATE_NO_WINDOW.0);
+    shell
+}
+
+#[cfg(unix)]
+fn shell_command(command: &str) -> Command {
+    use std::os::unix::process::CommandExt;
+
+    let mut shell = Command::new("sh");
+    /* in a group of its own, see ChildGroup */
+    shell.arg("-c").arg(command).process_group(0);
+    shell
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::audio::PauseReason;
+    use crate::hooks::{HookEvent, Payload};
+    use chrono::DateTime;
+    #[cfg(unix)]
+    use {
+        crate::hooks::{run, Hook, Hooks},
+        std::fs,
+        std::time::{Duration, Instant},
+    };
+
+    #[test]
+    fn test_payload() {
+        let payload = Payload {
+            time: DateTime::from_timestamp(1746093600, 0).unwrap(),
+            ..Payload::new(HookEvent::Paused)
+        }
+        .with_reason(PauseReason::SessionLocked);
+
+        assert_eq!(
+            r#"{"event":"paused","time":"2025-05-01T10:00:00Z","reason":"session_locked","error":null}"#,
+            serde_json::to_string(&payload).unwrap()
+        );
+        assert_eq!(
+            vec![
+                ("KEEP_AUDIO_AWAKE_EVENT".to_string(), "paused".to_string()),
+                ("KEEP_AUDIO_AWAKE_TIME".to_string(), "2025-05-01T10:00:00+00:00".to_string()),
+                ("KEEP_AUDIO_AWAKE_REASON".to_string(), "session_locked".to_string()),
+            ],
+            payload.to_env()
+        );
+    }
+
+    #[test]
+    #[cfg(unix)]
+    fn test_run_timeout() {
+        let hook = Hook {
+            event: HookEvent::Started,
+            command: "sleep 5".to_string(),
+            timeout_secs: 1,
+        };
+
+        let started = Instant::now();
+        let result = run(&hook, &Payload::new(HookEvent::Started));
+        assert!(result.is_err_and(|e| e.contains("timed out")));
+        assert!(started.elapsed() < Duration::from_secs(3));
+    }
+
+    #[test]
+    #[cfg(target_os = "linux")]
+    fn test_run_timeout_kills_group() {
+        let path = std::env::temp_dir().join(format!("keep-audio-awake-test-{}.pid", std::process::id()));
+        let hook = Hook {
+            event: HookEvent::Started,
+            command: format!("sleep 30 & echo $! > '{}'; sleep 30", path.display()),
+            timeout_secs: 1,
+        };
+
+        let result = run(&hook, &Payload::new(HookEvent::Started));
+        assert!(result.is_err_and(|e| e.contains("timed out")));
+
+        let pid = fs::read_to_string(&path).unwrap();
+        fs::remove_file(&path).unwrap();
+        /* the killed process is gone or a zombie until its new parent reaps it */
+        let stat = fs::read_to_string(format!("/proc/{}/stat", pid.trim())).unwrap_or_default();
+        let state = stat.rsplit(") ").next().and_then(|rest| rest.chars().next());
+        assert!(matches!(state, None | Some('Z')), "{}", stat);
+    }
+
+    #[test]
+    #[cfg(unix)]
+    fn test_payload_on_stdin() {
+        let path = std::env::temp_dir().join(format!("keep-audio-awake-test-{}.json", std::process::id()));
+        let hook = Hook {
+            event: HookEvent::DeviceLost,
+            command: format!("cat > '{}'", path.display()),
+            timeout_secs: 5,
+        };
+        let payload = Payload::new(HookEvent::DeviceLost).with_error("Device is gone");
+
+        run(&hook, &payload).unwrap();
+        let json = fs::read_to_string(&path).unwrap();
+        fs::remove_file(&path).unwrap();
+        assert_eq!(serde_json::to_string(&payload).unwrap(), json);
+    }
+
+    #[test]
+    #[cfg(unix)]
+    fn test_fire_does_not_block() {
+        let mut hooks = Hooks::new(&[Hook {
+            event: HookEvent::Stopped,
+            command: "sleep 1".to_string(),
+            timeout_secs: 5,
+        }]);
+
+        let started = Instant::now();
+        hooks.fire(Payload::new(HookEvent::Stopped));
+        hooks.fire(Payload::new(HookEvent::Paused));
+        assert!(started.elapsed() < Duration::from_millis(500));
+
+        hooks.wait(Instant::now() + hooks.timeout());
+        assert!(started.elapsed() >= Duration::from_secs(1));
+        assert!(hooks.running.is_empty());
+    }
+}
@@ -0,0 +1,195 @@
+use log::warn;
+use std::time::Duration;
+
+#[cfg(windows)]
+pub const TIMER_IDLE: usize = 200;
+pub const TIMER_IDLE_PERIOD_MS: u32 = 1000;
+
+/// Source of the time elapsed since the last user input.
+pub trait IdleSource {
+    fn idle_time(&self) -> Result<Duration, String>;
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum IdleState {
+    Active,
+    Idle,
+}
+
+/// Tracks user presence and reports transitions between active and idle states.
+pub struct IdleMonitor<S: IdleSource> {
+    source: S,
+    timeout: Duration,
+    state: IdleState,
+}
+
+impl<S: IdleSource> IdleMonitor<S> {
+    pub fn new(source: S, timeout: Duration) -> Self {
+        Self {
+            source,
+            timeout,
+            state: IdleState::Active,
+        }
+    }
+
+    /// Returns the new state if it has changed since the previous poll.
+    pub fn poll(&mut self) -> Option<IdleState> {
+        let idle_time = self
+            .source
+            .idle_time()
+            .inspect_err(|e| warn!("{}", e))
+            .ok()?;
+
+        let state = if idle_time >= self.timeout {
+            IdleState::Idle
+        } else {
+            IdleState::Active
+        };
+
+        if state == self.state {
+            None
+        } else {
+            self.state = state;
+            Some(state)
+        }
+    }
+}
+
+#[cfg(windows)]
+pub use windows_idle::InputIdleSource as PlatformIdleSource;
+
+#[cfg(target_os = "linux")]
+pub use linux_idle::LogindIdleSource as PlatformIdleSource;
+
+#[cfg(windows)]
+mod windows_idle {
+    use crate::idle::IdleSource;
+    use std::time::Duration;
+    use windows::Win32::System::SystemInformation::GetTickCount;
+    use windows::Win32::UI::Input::KeyboardAndMouse::{GetLastInputInfo, LASTINPUTINFO};
+
+    /// Reads the last keyboard or mouse input time of the current session.
+    #[derive(Default)]
+    pub struct InputIdleSource;
+
+    impl IdleSource for InputIdleSource {
+        fn idle_time(&self) -> Result<Duration, String> {
+            let mut info = LASTINPUTINFO {
+                cbSize: size_of::<LASTINPUTINFO>() as u32,
+                dwTime: 0,
+            };
+
+            unsafe {
+                if !GetLastInputInfo(&mut info).as_bool() {
+                    Err("Failed to get last input info")?
+                }
+
+                /* both values are 32-bit tick counts and wrap around together */
+                Ok(Duration::from_millis(
+                    GetTickCount().wrapping_sub(info.dwTime) as u64
+                ))
+            }
+        }
+    }
+}
+
+#[cfg(target_os = "linux")]
+mod linux_idle {
+    use crate::idle::IdleSource;
+    use crate::logind;
+    use std::cell::RefCell;
+    use std::time::{Duration, SystemTime, UNIX_EPOCH};
+    use zbus::blocking::Proxy;
+
+    /// Reads the idle hint that the desktop environment reports to logind. The X11 screensaver
+    /// extension is not used: it sees no input under Wayland, and GNOME and KDE set the hint on
+    /// X11 as well.
+    #[derive(Default)]
+    pub struct LogindIdleSource {
+        /* connected on the first poll and kept, the properties are cached from change signals */
+        session: RefCell<Option<Proxy<'static>>>,
+    }
+
+    impl IdleSource for LogindIdleSource {
+        fn idle_time(&self) -> Result<Duration, String> {
+            let mut session = self.session.borrow_mut();
+            let session = match &mut *session {
+                Some(session) => session,
+                none => none.insert(logind::session_proxy(&logind::system_bus()?)?),
+            };
+
+            let read_error = |e: zbus::Error| format!("Failed to read the idle hint. {}", e);
+            let idle = session.get_property::<bool>("IdleHint").map_err(read_error)?;
+            let since_us = session.get_property::<u64>("IdleSinceHint").map_err(read_error)?;
+
+            let now = SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .map_err(|e| e.to_string())?;
+            Ok(idle_time(idle, since_us, now))
+        }
+    }
+
+    /// `since_us` is the wall clock time in microseconds when the session became idle.
+    pub(super) fn idle_time(idle: bool, since_us: u64, now: Duration) -> Duration {
+        if !idle || since_us == 0 {
+            return Duration::ZERO;
+        }
+
+        now.saturating_sub(Duration::from_micros(since_us))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::idle::{IdleMonitor, IdleSource, IdleState};
+    use std::cell::Cell;
+    use std::rc::Rc;
+    use std::time::Duration;
+
+    #[derive(Clone, Default)]
+    struct FakeIdleSource(Rc<Cell<Option<Duration>>>);
+
+    impl IdleSource for FakeIdleSource {
+        fn idle_time(&self) -> Result<Duration, String> {
+            self.0.get().ok_or("Not available".to_string())
+        }
+    }
+
+    #[test]
+    fn test_idle_transitions() {
+        let source = FakeIdleSource::default();
+        let mut monitor = IdleMonitor::new(source.clone(), Duration::from_secs(60));
+
+        source.0.set(Some(Duration::from_secs(10)));
+        assert_eq!(None, monitor.poll());
+
+        source.0.set(Some(Duration::from_secs(60)));
+        assert_eq!(Some(IdleState::Idle), monitor.poll());
+        assert_eq!(None, monitor.poll());
+
+        source.0.set(Some(Duration::ZERO));
+        assert_eq!(Some(IdleState::Active), monitor.poll());
+    }
+
+    #[test]
+    fn test_idle_source_error() {
+        let source = FakeIdleSource::default();
+        let mut monitor = IdleMonitor::new(source.clone(), Duration::from_secs(60));
+
+        assert_eq!(None, monitor.poll());
+    }
+
+    #[cfg(target_os = "linux")]
+    #[test]
+    fn test_idle_hint() {
+        use crate::idle::linux_idle::idle_time;
+
+        let now = Duration::from_secs(1_700_000_600);
+        assert_eq!(Duration::from_secs(600), idle_time(true, 1_700_000_000_000_000, now));
+        assert_eq!(Duration::ZERO, idle_time(false, 1_700_000_000_000_000, now));
+        /* idle without a time */
+        assert_eq!(Duration::ZERO, idle_time(true, 0, now));
+        /* the clock went back */
+        assert_eq!(Duration::ZERO, idle_time(true, 1_700_000_900_000_000, now));
+    }
+}
@@ -0,0 +1,262 @@
+use log::{debug, warn};
+use serde::{Deserialize, Serialize};
+use std::fmt::{Display, Formatter};
+use std::fs;
+use std::path::Path;
+
+const APP_ID: &str = "keep-audio-awake";
+#[cfg(windows)]
+const LOCK_ID: &str = "8e22f9ab-0f7f-4f01-8dc2-6047b74a2a99";
+
+/// Which instances exclude each other.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize, Default)]
+#[serde(rename_all = "snake_case")]
+pub enum InstanceScope {
+    /// One instance per user.
+    User,
+    /// One instance per machine.
+    #[default]
+    Machine,
+}
+
+impl InstanceScope {
+    /// Makes the name unique within the scope.
+    pub fn qualify(&self, name: &str) -> String {
+        match self {
+            InstanceScope::User => format!("{}-{}", name, user_name()),
+            InstanceScope::Machine => name.to_string(),
+        }
+    }
+}
+
+#[derive(Debug, PartialEq)]
+pub enum LockError {
+    /// Another instance owns the lock. Its PID if known.
+    AlreadyRunning(Option<u32>),
+    Failed(String),
+}
+
+impl Display for LockError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            LockError::AlreadyRunning(Some(pid)) => write!(f, "Already running (PID {}).", pid),
+            LockError::AlreadyRunning(None) => write!(f, "Already running."),
+            LockError::Failed(message) => write!(f, "{}", message),
+        }
+    }
+}
+
+impl From<LockError> for String {
+    fn from(e: LockError) -> Self {
+        e.to_string()
+    }
+}
+
+fn user_name() -> String {
+    std::env::var("USERNAME")
+        .or_else(|_| std::env::var("USER"))
+        .unwrap_or("default".to_string())
+}
+
+fn read_pid(path: &Path) -> Option<u32> {
+    fs::read_to_string(path).ok()?.trim().parse().ok()
+}
+
+fn write_pid(path: &Path) {
+    if let Some(pid) = read_pid(path) {
+        debug!("Recovered a stale lock of PID {}", pid);
+    }
+
+    fs::write(path, std::process::id().to_string())
+        .unwrap_or_else(|e| warn!("Failed to write {}. {}", path.display(), e));
+}
+
+#[cfg(windows)]
+pub use windows_instance::InstanceLock;
+
+#[cfg(unix)]
+pub use unix_instance::InstanceLock;
+
+#[cfg(windows)]
+mod windows_instance {
+    use crate::instance::{read_pid, write_pid, InstanceScope, LockError, APP_ID, LOCK_ID};
+    use log::warn;
+    use std::fs;
+    use std::path::PathBuf;
+    use windows::core::HSTRING;
+    use windows::Win32::Foundation::{
+        CloseHandle, GetLastError, ERROR_ACCESS_DENIED, ERROR_ALREADY_EXISTS, HANDLE,
+    };
+    use windows::Win32::Storage::FileSystem::SYNCHRONIZE;
+    use windows::Win32::System::Threading::CreateMutexExW;
+
+    /// Named mutex held for the lifetime of the application. The system releases it
+    /// when the process exits, so a crashed instance never leaves a stale lock.
+    pub struct InstanceLock {
+        handle: HANDLE,
+        pid_file: PathBuf,
+    }
+
+    impl InstanceLock {
+        pub fn acquire(scope: InstanceScope) -> Result<Self, LockError> {
+            let name = format!("Global\\{}", scope.qualify(LOCK_ID));
+            let pid_file = pid_file(scope);
+
+            let handle = unsafe { CreateMutexExW(None, &HSTRING::from(name), 0, SYNCHRONIZE.0) }
+                .map_err(|e| {
+                    /* the mutex is owned by an instance of another user */
+                    if e.code() == ERROR_ACCESS_DENIED.to_hresult() {
+                        LockError::AlreadyRunning(read_pid(&pid_file))
+                    } else {
+                        LockError::Failed(e.message())
+                    }
+                })?;
+
+            if unsafe { GetLastError() } == ERROR_ALREADY_EXISTS {
+                unsafe { CloseHandle(handle) }.unwrap_or_default();
+                return Err(LockError::AlreadyRunning(read_pid(&pid_file)));
+            }
+
+            write_pid(&pid_file);
+
+            Ok(Self { handle, pid_file })
+        }
+    }
+
+    impl Drop for InstanceLock {
+        fn drop(&mut self) {
+            fs::remove_file(&self.pid_file).unwrap_or_default();
+            unsafe { CloseHandle(self.handle) }.unwrap_or_else(|e| warn!("{}", e.message()));
+        }
+    }
+
+    fn pid_file(scope: InstanceScope) -> PathBuf {
+        let dir = match scope {
+            InstanceScope::User => std::env::temp_dir(),
+            InstanceScope::Machine => std::env::var_os("ProgramData")
+                .map(PathBuf::from)
+                .unwrap_or_else(std::env::temp_dir),
+        };
+
+        dir.join(format!("{}.pid", scope.qualify(APP_ID)))
+    }
+}
+
+#[cfg(unix)]
+mod unix_instance {
+    use crate::instance::{read_pid, write_pid, InstanceScope, LockError, APP_ID};
+    use std::fs::{File, OpenOptions, Permissions, TryLockError};
+    use std::io;
+    use std::io::ErrorKind;
+    use std::os::unix::fs::PermissionsExt;
+    use std::path::{Path, PathBuf};
+
+    /// Lock file held with `flock`. The kernel drops the lock when the process exits,
+    /// so a file left by a crashed instance is simply reused.
+    pub struct InstanceLock {
+        file: File,
+    }
+
+    impl InstanceLock {
+        pub fn acquire(scope: InstanceScope) -> Result<Self, LockError> {
+            Self::acquire_at(&lock_path(scope), scope == InstanceScope::Machine)
+        }
+
+        /// `shared` makes a new lock file writable by all users.
+        pub(super) fn acquire_at(path: &Path, shared: bool) -> Result<Self, LockError> {
+            let (file, writable) = open(path, shared).map_err(|e| match e.kind() {
+                /* the file of an instance of another user that is not readable */
+                ErrorKind::PermissionDenied => LockError::AlreadyRunning(read_pid(path)),
+                _ => LockError::Failed(format!("Failed to open {}. {}", path.display(), e)),
+            })?;
+
+            match file.try_lock() {
+                Ok(()) => {}
+                Err(TryLockError::WouldBlock) => {
+                    return Err(LockError::AlreadyRunning(read_pid(path)));
+                }
+                Err(TryLockError::Error(e)) => {
+                    return Err(LockError::Failed(format!("Failed to lock {}. {}", path.display(), e)));
+                }
+            }
+
+            if writable {
+                write_pid(path);
+            }
+
+            Ok(Self { file })
+        }
+    }
+
+    impl Drop for InstanceLock {
+        fn drop(&mut self) {
+            /* the file stays, as removing it would race with an instance that has just opened it */
+            self.file.set_len(0).unwrap_or_default();
+        }
+    }
+
+    /// Opens the lock file for writing if possible. `flock` needs only a readable file, so the
+    /// file of another user is opened read-only. Tells whether the file is writable.
+    fn open(path: &Path, shared: bool) -> io::Result<(File, bool)> {
+        /* O_CREAT is refused on files of other users in sticky directories such as /tmp */
+        match OpenOptions::new().read(true).write(true).open(path) {
+            Ok(file) => Ok((file, true)),
+            Err(e) if e.kind() == ErrorKind::NotFound => {
+                let file = OpenOptions::new()
+                    .read(true)
+                    .write(true)
+                    .create(true)
+                    .truncate(false)
+                    .open(path)?;
+                /* the mode given to open is masked by the umask */
+                if shared {
+                    file.set_permissions(Permissions::from_mode(0o666))?;
+                }
+                Ok((file, true))
+            }
+            Err(e) if e.kind() == ErrorKind::PermissionDenied => {
+                OpenOptions::new().read(true).open(path).map(|file| (file, false))
+            }
+            Err(e) => Err(e),
+        }
+    }
+
+    fn lock_path(scope: InstanceScope) -> PathBuf {
+        let dir = match scope {
+            InstanceScope::User => std::env::var_os("XDG_RUNTIME_DIR")
+                .map(PathBuf::from)
+                .unwrap_or_else(std::env::temp_dir),
+            InstanceScope::Machine => std::env::temp_dir(),
+        };
+
+        dir.join(format!("{}.lock", scope.qualify(APP_ID)))
+    }
+}
+
+#[cfg(all(test, unix))]
+mod tests {
+    use crate::instance::{InstanceLock, LockError};
+    use std::fs;
+    use std::os::unix::fs::PermissionsExt;
+
+    #[test]
+    fn test_acquire() {
+        let path = std::env::temp_dir().join(format!("keep-audio-awake-test-{}.lock", std::process::id()));
+        fs::remove_file(&path).unwrap_or_default();
+
+        let lock = InstanceLock::acquire_at(&path, true).unwrap();
+        assert_eq!(0o666, fs::metadata(&path).unwrap().permissions().mode() & 0o777);
+        assert_eq!(
+            Some(LockError::AlreadyRunning(Some(std::process::id()))),
+            InstanceLock::acquire_at(&path, true).err()
+        );
+
+        drop(lock);
+        assert_eq!("", fs::read_to_string(&path).unwrap());
+        let lock = InstanceLock::acquire_at(&path, true);
+        assert!(lock.is_ok());
+
+        drop(lock);
+        fs::remove_file(&path).unwrap();
+    }
+}
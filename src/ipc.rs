@@ -0,0 +1,593 @@
+use crate::instance::InstanceScope;
+use log::{debug, warn};
+use std::fmt::{Display, Formatter};
+use std::io::{BufRead, BufReader, Read, Write};
+use std::str::FromStr;
+use std::sync::mpsc::{channel, Receiver, Sender};
+use std::sync::Arc;
+use std::thread;
+use std::time::Duration;
+
+const ENDPOINT_NAME: &str = "keep-audio-awake";
+const RESPONSE_TIMEOUT: Duration = Duration::from_secs(5);
+const ACCEPT_RETRY_DELAY: Duration = Duration::from_secs(1);
+
+/// Command sent to the running instance. One command per connection, terminated by a newline.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Command {
+    Pause,
+    Resume,
+    /// Pause for the given number of minutes.
+    Snooze(u32),
+    /// Change the keep-alive period in milliseconds.
+    Period(u32),
+    Status,
+    Stop,
+    Reload,
+}
+
+impl Display for Command {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Command::Pause => write!(f, "pause"),
+            Command::Resume => write!(f, "resume"),
+            Command::Snooze(minutes) => write!(f, "snooze {}", minutes),
+            Command::Period(period_ms) => write!(f, "period {}", period_ms),
+            Command::Status => write!(f, "status"),
+            Command::Stop => write!(f, "stop"),
+            Command::Reload => write!(f, "reload"),
+        }
+    }
+}
+
+impl FromStr for Command {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (name, arg) = s.split_once(' ').unwrap_or((s, ""));
+        let number = || {
+            arg.trim()
+                .parse::<u32>()
+                .map_err(|_| format!("Command {} expects a number", name))
+        };
+
+        match name {
+            "pause" => Ok(Command::Pause),
+            "resume" => Ok(Command::Resume),
+            "snooze" => Ok(Command::Snooze(number()?)),
+            "period" => Ok(Command::Period(number()?)),
+            "status" => Ok(Command::Status),
+            "stop" => Ok(Command::Stop),
+            "reload" => Ok(Command::Reload),
+            _ => Err(format!("Unknown command: {}", s)),
+        }
+    }
+}
+
+/// Response line: `ok [<payload>]` or `error <message>`.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Response {
+    Ok(String),
+    Error(String),
+}
+
+impl Response {
+    fn encode(&self) -> String {
+        let (status, text) = match self {
+            Response::Ok(text) => ("ok", text),
+            Response::Error(text) => ("error", text),
+        };
+
+        /* the protocol is line-based */
+        let text = text.replace('\n', " ");
+        if text.is_empty() {
+            status.to_string()
+        } else {
+            format!("{} {}", status, text)
+        }
+    }
+
+    fn decode(line: &str) -> Self {
+        let (status, text) = line.split_once(' ').unwrap_or((line, ""));
+        match status {
+            "ok" => Response::Ok(text.to_string()),
+            "error" => Response::Error(text.to_string()),
+            _ => Response::Error(format!("Invalid response: {}", line)),
+        }
+    }
+}
+
+/// Command received from another process that awaits a response from the GUI thread.
+pub struct Request {
+    pub command: Command,
+    reply: Sender<Response>,
+}
+
+impl Request {
+    /// Returns the request and the receiver of its response.
+    pub fn new(command: Command) -> (Self, Receiver<Response>) {
+        let (reply, response) = channel();
+        (Self { command, reply }, response)
+    }
+
+    pub fn reply(self, response: Response) {
+        if self.reply.send(response).is_err() {
+            warn!("IPC client is gone");
+        }
+    }
+}
+
+/// Serves commands on the local endpoint of the scope in a background thread.
+/// Requests are queued to the returned receiver, and `wake` is called after each one.
+pub fn start_server<F>(scope: InstanceScope, wake: F) -> Result<Receiver<Request>, String>
+where
+    F: Fn() + Send + Sync + 'static,
+{
+    let (sender, receiver) = channel();
+    let wake = Arc::new(wake);
+
+    thread::Builder::new()
+        .name("ipc".to_string())
+        .spawn(move || {
+            let listener = match Listener::bind(scope) {
+                Ok(listener) => listener,
+                Err(e) => {
+                    warn!("{}", e);
+                    return;
+                }
+            };
+
+            debug!("IPC server started");
+
+            loop {
+                match listener.accept() {
+                    Ok(stream) => serve(stream, sender.clone(), wake.clone()),
+                    Err(e) => {
+                        warn!("{}", e);
+                        thread::sleep(ACCEPT_RETRY_DELAY);
+                    }
+                }
+            }
+        })
+        .map_err(|e| format!("Failed to start IPC server. {}", e))?;
+
+    Ok(receiver)
+}
+
+/// Sends a command to the running instance and returns its response.
+pub fn send(scope: InstanceScope, command: Command) -> Result<Response, String> {
+    let mut stream = connect(scope)?;
+    writeln!(stream, "{}", command)
+        .and_then(|_| stream.flush())
+        .map_err(|e| format!("Failed to send command. {}", e))?;
+
+    let mut line = String::new();
+    BufReader::new(stream)
+        .read_line(&mut line)
+        .map_err(|e| format!("Failed to read response. {}", e))?;
+
+    Ok(Response::decode(line.trim_end()))
+}
+
+/// Handles the connection in a thread of its own, so that a client that sends nothing does not
+/// hold up the commands of others.
+fn serve<S, F>(stream: S, requests: Sender<Request>, wake: Arc<F>)
+where
+    S: Read + Write + Send + 'static,
+    F: Fn() + Send + Sync + 'static,
+{
+    thread::Builder::new()
+        .name("ipc-connection".to_string())
+        .spawn(move || handle_connection(stream, &requests, wake.as_ref()))
+        .map(|_| ())
+        .unwrap_or_else(|e| warn!("Failed to start IPC connection thread. {}", e));
+}
+
+fn handle_connection<S: Read + Write>(stream: S, requests: &Sender<Request>, wake: &dyn Fn()) {
+    let mut reader = BufReader::new(stream);
+    let mut line = String::new();
+    if let Err(e) = reader.read_line(&mut line) {
+        warn!("Failed to read IPC request. {}", e);
+        return;
+    }
+
+    let response = match line.trim().parse::<Command>() {
+        Ok(command) => {
+            debug!("IPC command: {:?}", command);
+
+            let (request, response) = Request::new(command);
+            if requests.send(request).is_err() {
+                return;
+            }
+            wake();
+
+            response
+                .recv_timeout(RESPONSE_TIMEOUT)
+                .unwrap_or(Response::Error("No response from the application".to_string()))
+        }
+        Err(e) => Response::Error(e),
+    };
+
+    let mut stream = reader.into_inner();
+    writeln!(stream, "{}", response.encode())
+        .and_then(|_| stream.flush())
+        .unwrap_or_else(|e| warn!("Failed to write IPC response. {}", e));
+}
+
+#[cfg(windows)]
+use windows_ipc::{connect, Listener};
+
+#[cfg(unix)]
+use unix_ipc::{connect, Listener};
+
+#[cfg(target_os = "linux")]
+pub use unix_ipc::socket_path;
+
+#[cfg(windows)]
+mod windows_ipc {
+    use crate::instance::InstanceScope;
+    use crate::ipc::ENDPOINT_NAME;
+    use log::warn;
+    use std::cell::Cell;
+    use std::fs::{File, OpenOptions};
+    use std::io::{ErrorKind, Read, Write};
+    use std::os::windows::fs::OpenOptionsExt;
+    use std::os::windows::io::FromRawHandle;
+    use std::thread;
+    use std::time::Duration;
+    use windows::core::{HSTRING, PWSTR};
+    use windows::Win32::Foundation::{
+        CloseHandle, LocalFree, ERROR_ACCESS_DENIED, ERROR_PIPE_CONNECTED, HANDLE, HLOCAL,
+    };
+    use windows::Win32::Security::Authorization::{
+        ConvertSidToStringSidW, ConvertStringSecurityDescriptorToSecurityDescriptorW, SDDL_REVISION_1,
+    };
+    use windows::Win32::Security::{
+        GetTokenInformation, TokenUser, PSECURITY_DESCRIPTOR, SECURITY_ATTRIBUTES, TOKEN_QUERY, TOKEN_USER,
+    };
+    use windows::Win32::Storage::FileSystem::{
+        FILE_FLAG_FIRST_PIPE_INSTANCE, FILE_GENERIC_READ, FILE_WRITE_DATA, PIPE_ACCESS_DUPLEX,
+    };
+    use windows::Win32::System::Pipes::{
+        ConnectNamedPipe, CreateNamedPipeW, PIPE_READMODE_BYTE, PIPE_REJECT_REMOTE_CLIENTS,
+        PIPE_TYPE_BYTE, PIPE_UNLIMITED_INSTANCES, PIPE_WAIT,
+    };
+    use windows::Win32::System::Threading::{GetCurrentProcess, OpenProcessToken};
+
+    const PIPE_BUFFER_SIZE: u32 = 4096;
+    const CONNECT_ATTEMPTS: u32 = 10;
+    const CONNECT_RETRY_DELAY: Duration = Duration::from_millis(100);
+    /* what clients need: read, write and flush. not FILE_CREATE_PIPE_INSTANCE, so they can't serve the pipe */
+    const CLIENT_ACCESS: u32 = FILE_GENERIC_READ.0 | FILE_WRITE_DATA.0;
+
+    /// Pipe end that waits for the other side to read everything on flush.
+    pub struct PipeStream(File);
+
+    impl Read for PipeStream {
+        fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+            self.0.read(buf)
+        }
+    }
+
+    impl Write for PipeStream {
+        fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+            self.0.write(buf)
+        }
+
+        fn flush(&mut self) -> std::io::Result<()> {
+            /* FlushFileBuffers */
+            self.0.sync_all()
+        }
+    }
+
+    /// Security descriptor allocated by the system.
+    struct SecurityDescriptor(PSECURITY_DESCRIPTOR);
+
+    impl Drop for SecurityDescriptor {
+        fn drop(&mut self) {
+            unsafe { LocalFree(Some(HLOCAL(self.0 .0))) };
+        }
+    }
+
+    pub struct Listener {
+        pipe_name: String,
+        security: SecurityDescriptor,
+        /* the instance the next client connects to. there is always one while the server runs */
+        pending: Cell<Option<HANDLE>>,
+    }
+
+    impl Listener {
+        /// Creates the first pipe instance. Fails if another process has taken the name.
+        pub fn bind(scope: InstanceScope) -> Result<Self, String> {
+            let listener = Self {
+                pipe_name: pipe_name(scope),
+                security: security_descriptor(scope)?,
+                pending: Cell::new(None),
+            };
+            let handle = listener.create_instance(true)?;
+            listener.pending.set(Some(handle));
+
+            Ok(listener)
+        }
+
+        /// Waits for a client to connect to the pending pipe instance.
+        pub fn accept(&self) -> Result<PipeStream, String> {
+            let handle = match self.pending.take() {
+                Some(handle) => handle,
+                None => self.create_instance(false)?,
+            };
+
+            /* the file owns the handle from now on */
+            let stream = PipeStream(unsafe { File::from_raw_handle(handle.0) });
+            let connected = unsafe { ConnectNamedPipe(handle, None) };
+
+            /* clients connect to the next instance while this one is served */
+            self.pending.set(self.create_instance(false).map_err(|e| warn!("{}", e)).ok());
+
+            if let Err(e) = connected
+                && e.code() != ERROR_PIPE_CONNECTED.to_hresult()
+            {
+                Err(format!("Failed to connect pipe. {}", e.message()))?
+            }
+
+            Ok(stream)
+        }
+
+        fn create_instance(&self, first: bool) -> Result<HANDLE, String> {
+            let security = SECURITY_ATTRIBUTES {
+                nLength: size_of::<SECURITY_ATTRIBUTES>() as u32,
+                lpSecurityDescriptor: self.security.0 .0,
+                bInheritHandle: false.into(),
+            };
+            let mut open_mode = PIPE_ACCESS_DUPLEX;
+            if first {
+                open_mode |= FILE_FLAG_FIRST_PIPE_INSTANCE;
+            }
+
+            let handle = unsafe {
+                CreateNamedPipeW(
+                    &HSTRING::from(&self.pipe_name),
+                    open_mode,
+                    PIPE_TYPE_BYTE | PIPE_READMODE_BYTE | PIPE_WAIT | PIPE_REJECT_REMOTE_CLIENTS,
+                    PIPE_UNLIMITED_INSTANCES,
+                    PIPE_BUFFER_SIZE,
+                    PIPE_BUFFER_SIZE,
+                    0,
+                    Some(&security),
+                )
+            };
+            if handle.is_invalid() {
+                let e = windows::core::Error::from_win32();
+                if first && e.code() == ERROR_ACCESS_DENIED.to_hresult() {
+                    Err(format!("Pipe {} is taken by another process", self.pipe_name))?
+                }
+                Err(format!("Failed to create pipe {}. {}", self.pipe_name, e.message()))?
+            }
+
+            Ok(handle)
+        }
+    }
+
+    impl Drop for Listener {
+        fn drop(&mut self) {
+            if let Some(handle) = self.pending.take() {
+                unsafe { CloseHandle(handle) }.unwrap_or_default();
+            }
+        }
+    }
+
+    /// Gives full access to the current user and the system. In the machine scope other
+    /// users can send commands too, but only as clients.
+    fn security_descriptor(scope: InstanceScope) -> Result<SecurityDescriptor, String> {
+        let user = user_sid()?;
+        let mut sddl = format!("D:P(A;;GA;;;SY)(A;;GA;;;{})", user);
+        if scope == InstanceScope::Machine {
+            sddl.push_str(&format!("(A;;{:#x};;;AU)", CLIENT_ACCESS));
+        }
+
+        let mut descriptor = PSECURITY_DESCRIPTOR::default();
+        unsafe {
+            ConvertStringSecurityDescriptorToSecurityDescriptorW(
+                &HSTRING::from(sddl),
+                SDDL_REVISION_1,
+                &mut descriptor,
+                None,
+            )
+        }
+        .map_err(|e| format!("Failed to create pipe security descriptor. {}", e.message()))?;
+
+        Ok(SecurityDescriptor(descriptor))
+    }
+
+    /// SID of the user the process runs as, in the string form.
+    fn user_sid() -> Result<String, String> {
+        let error = |e: windows::core::Error| format!("Failed to get the user SID. {}", e.message());
+
+        let mut token = HANDLE::default();
+        unsafe { OpenProcessToken(GetCurrentProcess(), TOKEN_QUERY, &mut token) }.map_err(error)?;
+
+        /* TOKEN_USER followed by the SID it points to. usize keeps it aligned */
+        let mut buffer = [0usize; 64];
+        let mut length = 0;
+        let result = unsafe {
+            GetTokenInformation(
+                token,
+                TokenUser,
+                Some(buffer.as_mut_ptr().cast()),
+                size_of_val(&buffer) as u32,
+                &mut length,
+            )
+        };
+        unsafe { CloseHandle(token) }.unwrap_or_default();
+        result.map_err(error)?;
+
+        let user = unsafe { &*buffer.as_ptr().cast::<TOKEN_USER>() };
+        let mut sid = PWSTR::null();
+        unsafe { ConvertSidToStringSidW(user.User.Sid, &mut sid) }.map_err(error)?;
+        let text = unsafe { sid.to_string() };
+        unsafe { LocalFree(Some(HLOCAL(sid.0.cast()))) };
+
+        text.map_err(|e| format!("Failed to get the user SID. {}", e))
+    }
+
+    pub fn connect(scope: InstanceScope) -> Result<PipeStream, String> {
+        let pipe_name = pipe_name(scope);
+        let mut attempt = 1;
+        loop {
+            match OpenOptions::new().access_mode(CLIENT_ACCESS).open(&pipe_name) {
+                Ok(file) => return Ok(PipeStream(file)),
+                Err(e) if e.kind() == ErrorKind::NotFound => {
+                    Err("Application is not running".to_string())?
+                }
+                /* all pipe instances are busy */
+                Err(_) if attempt < CONNECT_ATTEMPTS => {
+                    attempt += 1;
+                    thread::sleep(CONNECT_RETRY_DELAY);
+                }
+                Err(e) => Err(format!("Failed to connect to the application. {}", e))?,
+            }
+        }
+    }
+
+    fn pipe_name(scope: InstanceScope) -> String {
+        format!(r"\\.\pipe\{}", scope.qualify(ENDPOINT_NAME))
+    }
+}
+
+#[cfg(unix)]
+mod unix_ipc {
+    use crate::instance::InstanceScope;
+    use crate::ipc::ENDPOINT_NAME;
+    use std::fs::Permissions;
+    use std::io::ErrorKind;
+    use std::os::unix::fs::PermissionsExt;
+    use std::os::unix::net::{UnixListener, UnixStream};
+    use std::path::PathBuf;
+    use std::{env, fs};
+
+    pub struct Listener(UnixListener);
+
+    impl Listener {
+        pub fn bind(scope: InstanceScope) -> Result<Self, String> {
+            #[cfg(target_os = "linux")]
+            if let Some(listener) = crate::systemd::activated_listener() {
+                return Ok(Self(listener));
+            }
+
+            let path = socket_path(scope);
+            /* a socket file left by a crashed instance. only one instance runs at a time */
+            if path.exists() {
+                fs::remove_file(&path)
+                    .map_err(|e| format!("Failed to remove {}. {}", path.display(), e))?;
+            }
+
+            let listener = UnixListener::bind(&path)
+                .map_err(|e| format!("Failed to bind {}. {}", path.display(), e))?;
+
+            /* any user may send commands to the instance of the machine, as the pipe on Windows */
+            if scope == InstanceScope::Machine {
+                fs::set_permissions(&path, Permissions::from_mode(0o666))
+                    .map_err(|e| format!("Failed to set permissions of {}. {}", path.display(), e))?;
+            }
+
+            Ok(Self(listener))
+        }
+
+        pub fn accept(&self) -> Result<UnixStream, String> {
+            self.0
+                .accept()
+                .map(|(stream, _)| stream)
+                .map_err(|e| format!("Failed to accept connection. {}", e))
+        }
+    }
+
+    pub fn connect(scope: InstanceScope) -> Result<UnixStream, String> {
+        UnixStream::connect(socket_path(scope)).map_err(|e| match e.kind() {
+            ErrorKind::NotFound | ErrorKind::ConnectionRefused => {
+                "Application is not running".to_string()
+            }
+            _ => format!("Failed to connect to the application. {}", e),
+        })
+    }
+
+    pub fn socket_path(scope: InstanceScope) -> PathBuf {
+        let dir = match scope {
+            InstanceScope::User => env::var_os("XDG_RUNTIME_DIR")
+                .map(PathBuf::from)
+                .unwrap_or_else(env::temp_dir),
+            InstanceScope::Machine => env::temp_dir(),
+        };
+
+        dir.join(format!("{}.sock", scope.qualify(ENDPOINT_NAME)))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::ipc::{Command, Response};
+    #[cfg(unix)]
+    use {
+        crate::ipc::serve,
+        std::io::{BufRead, BufReader, Write},
+        std::os::unix::net::UnixStream,
+        std::sync::mpsc::channel,
+        std::sync::Arc,
+        std::time::Duration,
+    };
+
+    #[test]
+    fn test_parse_command() {
+        for command in [
+            Command::Pause,
+            Command::Resume,
+            Command::Snooze(15),
+            Command::Period(10000),
+            Command::Status,
+            Command::Stop,
+            Command::Reload,
+        ] {
+            assert_eq!(Ok(command), command.to_string().parse());
+        }
+        assert!("sleep".parse::<Command>().is_err());
+        assert!("snooze".parse::<Command>().is_err());
+        assert!("period fast".parse::<Command>().is_err());
+    }
+
+    #[test]
+    fn test_response_encoding() {
+        for response in [
+            Response::Ok(String::new()),
+            Response::Ok("running".to_string()),
+            Response::Error("Unknown command: sleep".to_string()),
+        ] {
+            assert_eq!(response, Response::decode(&response.encode()));
+        }
+        assert_eq!(
+            Response::Ok("a b".to_string()),
+            Response::decode(&Response::Ok("a\nb".to_string()).encode())
+        );
+    }
+
+    #[test]
+    #[cfg(unix)]
+    fn test_silent_client_does_not_block() {
+        let (requests, receiver) = channel();
+        let wake = Arc::new(|| {});
+
+        /* connected and never sends anything */
+        let (silent, _silent_client) = UnixStream::pair().unwrap();
+        serve(silent, requests.clone(), wake.clone());
+
+        let (stream, mut client) = UnixStream::pair().unwrap();
+        serve(stream, requests, wake);
+        writeln!(client, "{}", Command::Status).unwrap();
+
+        let request = receiver.recv_timeout(Duration::from_secs(5)).unwrap();
+        assert_eq!(Command::Status, request.command);
+        request.reply(Response::Ok("running".to_string()));
+
+        let mut line = String::new();
+        BufReader::new(client).read_line(&mut line).unwrap();
+        assert_eq!("ok running", line.trim_end());
+    }
+}
@@ -0,0 +1,676 @@
+use crate::settings::state_dir;
+use flexi_logger::writers::LogWriter;
+use flexi_logger::{
+    colored_detailed_format, detailed_format, Cleanup, Criterion, DeferredNow, Duplicate, FileSpec, FormatFunction,
+    LogSpecification, Logger, LoggerHandle, Naming,
+};
+use log::kv::{self, Key, Value, VisitSource, VisitValue};
+use log::{debug, warn, LevelFilter, Record};
+use serde::{Deserialize, Serialize};
+use serde_json::Map;
+use std::io::{self, Write};
+use std::path::PathBuf;
+use std::sync::OnceLock;
+
+const LOG_ARG_PREFIX: &str = "--log-";
+/* the environment variable takes precedence over the settings file */
+const LOG_ENV: &str = "RUST_LOG";
+
+static LOGGER: OnceLock<Logging> = OnceLock::new();
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize, Default)]
+#[serde(rename_all = "snake_case")]
+pub enum LogFormat {
+    /// One line per record with the fields after the message.
+    #[default]
+    Text,
+    /// One JSON object per line.
+    Json,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct LogSettings {
+    /// Level or flexi_logger specification, e.g. `info` or `warn,keep_audio_awake=debug`.
+    pub level: String,
+    /// Directory of the log files. The platform state directory if unset.
+    pub directory: Option<PathBuf>,
+    /// A new file is started when the current one reaches this size.
+    pub rotate_size_mb: u64,
+    /// Number of rotated files to keep.
+    pub keep_files: usize,
+    /// Format of the files and of the standard output.
+    pub format: LogFormat,
+    /// Colour the standard output in the text format.
+    pub color: bool,
+    /// Duplicate the log to the standard output.
+    pub stdout: bool,
+    /// Write the log files.
+    pub file: bool,
+    /// Send the log to journald on Linux or to the Event Log on Windows.
+    pub system: bool,
+}
+
+impl Default for LogSettings {
+    fn default() -> Self {
+        Self {
+            level: "info".to_string(),
+            directory: None,
+            rotate_size_mb: 10,
+            keep_files: 7,
+            format: LogFormat::Text,
+            color: true,
+            stdout: true,
+            file: true,
+            system: false,
+        }
+    }
+}
+
+impl LogSettings {
+    pub fn validate(&self) -> Result<(), String> {
+        parse_spec(&self.level)?;
+        if self.rotate_size_mb == 0 {
+            Err("Log rotation size must not be zero")?
+        }
+        if self.keep_files == 0 {
+            Err("Number of log files to keep must not be zero")?
+        }
+        Ok(())
+    }
+}
+
+/// Log settings given on the command line as `--log-<key>=<value>`. They take precedence over
+/// the settings file.
+#[derive(Debug, Default, PartialEq)]
+pub struct LogArgs {
+    level: Option<String>,
+    directory: Option<PathBuf>,
+    rotate_size_mb: Option<u64>,
+    keep_files: Option<usize>,
+    format: Option<LogFormat>,
+    color: Option<bool>,
+    stdout: Option<bool>,
+    file: Option<bool>,
+    system: Option<bool>,
+}
+
+impl LogArgs {
+    /// Takes the log options out of `args`.
+    pub fn parse(args: &mut Vec<String>) -> Result<Self, String> {
+        let mut log_args = Self::default();
+        let mut error = None;
+
+        args.retain(|arg| {
+            let Some(option) = arg.strip_prefix(LOG_ARG_PREFIX) else {
+                return true;
+            };
+            if let Err(e) = log_args.set(option) {
+                error.get_or_insert(e);
+            }
+            false
+        });
+
+        match error {
+            Some(e) => Err(e),
+            None => Ok(log_args),
+        }
+    }
+
+    fn set(&mut self, option: &str) -> Result<(), String> {
+        let (key, value) = option
+            .split_once('=')
+            .ok_or(format!("Option {}{} expects a value", LOG_ARG_PREFIX, option))?;
+        let invalid = || format!("Invalid value of {}{}: {}", LOG_ARG_PREFIX, key, value);
+
+        match key {
+            "level" => self.level = Some(value.to_string()),
+            "dir" => self.directory = Some(PathBuf::from(value)),
+            "rotate-size-mb" => self.rotate_size_mb = Some(value.parse().map_err(|_| invalid())?),
+            "keep-files" => self.keep_files = Some(value.parse().map_err(|_| invalid())?),
+            "format" => {
+                self.format = Some(match value {
+                    "text" => LogFormat::Text,
+                    "json" => LogFormat::Json,
+                    _ => Err(invalid())?,
+                })
+            }
+            "color" => self.color = Some(value.parse().map_err(|_| invalid())?),
+            "stdout" => self.stdout = Some(value.parse().map_err(|_| invalid())?),
+            "file" => self.file = Some(value.parse().map_err(|_| invalid())?),
+            "system" => self.system = Some(value.parse().map_err(|_| invalid())?),
+            _ => Err(format!("Unknown option {}{}", LOG_ARG_PREFIX, key))?,
+        }
+        Ok(())
+    }
+
+    pub fn apply(&self, settings: &LogSettings) -> LogSettings {
+        LogSettings {
+            level: self.level.clone().unwrap_or(settings.level.clone()),
+            directory: self.directory.clone().or(settings.directory.clone()),
+            rotate_size_mb: self.rotate_size_mb.unwrap_or(settings.rotate_size_mb),
+            keep_files: self.keep_files.unwrap_or(settings.keep_files),
+            format: self.format.unwrap_or(settings.format),
+            color: self.color.unwrap_or(settings.color),
+            stdout: self.stdout.unwrap_or(settings.stdout),
+            file: self.file.unwrap_or(settings.file),
+            system: self.system.unwrap_or(settings.system),
+        }
+    }
+}
+
+struct Logging {
+    handle: LoggerHandle,
+    /* set on the command line or in the environment, settings do not change it */
+    fixed_level: bool,
+}
+
+/// Starts logging to the enabled outputs.
+pub fn setup(settings: &LogSettings, args: &LogArgs) -> Result<(), String> {
+    let env_level = std::env::var(LOG_ENV).ok().filter(|level| !level.is_empty());
+    let fixed_level = args.level.is_some() || env_level.is_some();
+    let settings = args.apply(settings);
+    let level = match (&args.level, env_level) {
+        (None, Some(level)) => level,
+        _ => settings.level.clone(),
+    };
+    let spec = parse_spec(&level)?;
+
+    /* the logger is not there yet to report a failure */
+    let (system_log, system_log_error) = match settings.system.then(SystemLogWriter::new) {
+        Some(Ok(writer)) => (Some(Box::new(writer) as Box<dyn LogWriter>), None),
+        Some(Err(e)) => (None, Some(e)),
+        None => (None, None),
+    };
+
+    let directory = settings.directory.clone().unwrap_or_else(default_directory);
+    let file_spec = FileSpec::default().directory(&directory);
+    let (file_format, stdout_format): (FormatFunction, FormatFunction) = match settings.format {
+        LogFormat::Text if settings.color => (text_format, colored_text_format),
+        LogFormat::Text => (text_format, text_format),
+        LogFormat::Json => (json_format, json_format),
+    };
+    let logger = Logger::with(spec);
+    let logger = match (settings.file, system_log) {
+        (true, Some(writer)) => logger.log_to_file_and_writer(file_spec, writer),
+        (true, None) => logger.log_to_file(file_spec),
+        (false, Some(writer)) => logger.log_to_writer(writer),
+        (false, None) => logger.do_not_log(),
+    };
+    let handle = logger
+        .set_palette("1;3;4;2;7".into())
+        .format_for_files(file_format)
+        .format_for_stdout(stdout_format)
+        .rotate(
+            Criterion::Size(settings.rotate_size_mb * 1_000_000),
+            Naming::Timestamps,
+            Cleanup::KeepLogFiles(settings.keep_files),
+        )
+        .duplicate_to_stdout(if settings.stdout { Duplicate::All } else { Duplicate::None })
+        .start()
+        .map_err(|e| format!("Failed to initialize logger. {}", e))?;
+
+    if settings.file {
+        debug!("Logging to {}", directory.display());
+    }
+    if let Some(e) = system_log_error {
+        warn!("{}", e);
+    }
+    LOGGER.get_or_init(|| Logging { handle, fixed_level });
+
+    Ok(())
+}
+
+/// Applies the level of reloaded settings. The other settings take a restart.
+pub fn reconfigure(settings: &LogSettings) {
+    let Some(logging) = LOGGER.get() else {
+        return;
+    };
+    if logging.fixed_level {
+        debug!("Log level is set on the command line or in {}", LOG_ENV);
+        return;
+    }
+    match parse_spec(&settings.level) {
+        Ok(spec) => logging.handle.set_new_spec(spec),
+        Err(e) => warn!("{}", e),
+    }
+}
+
+/// Parses a level or a flexi_logger specification.
+fn parse_spec(level: &str) -> Result<LogSpecification, String> {
+    /* a bare word that is not a level is taken for a module logged at trace, which turns
+     * everything else off. that is rather a typo than intended */
+    let unknown = level
+        .split(',')
+        .map(|directive| directive.split('/').next().unwrap_or_default().trim())
+        .filter(|directive| !directive.is_empty() && !directive.contains('='))
+        .find(|directive| directive.parse::<LevelFilter>().is_err());
+    if let Some(word) = unknown {
+        Err(format!("Invalid log level {}. Unknown level {}", level, word))?
+    }
+
+    LogSpecification::parse(level).map_err(|e| format!("Invalid log level {}. {}", level, e))
+}
+
+/// `detailed_format` followed by the fields of the record.
+fn text_format(w: &mut dyn Write, now: &mut DeferredNow, record: &Record) -> io::Result<()> {
+    detailed_format(w, now, record)?;
+    write_fields(w, record)
+}
+
+fn colored_text_format(w: &mut dyn Write, now: &mut DeferredNow, record: &Record) -> io::Result<()> {
+    colored_detailed_format(w, now, record)?;
+    write_fields(w, record)
+}
+
+fn write_fields(w: &mut dyn Write, record: &Record) -> io::Result<()> {
+    let mut fields = JsonFields::default();
+    record.key_values().visit(&mut fields).ok();
+    for (key, value) in fields.0 {
+        match value {
+            serde_json::Value::Null => {}
+            serde_json::Value::String(text) if !text.contains(char::is_whitespace) => write!(w, " {}={}", key, text)?,
+            value => write!(w, " {}={}", key, value)?,
+        }
+    }
+    Ok(())
+}
+
+#[derive(Serialize)]
+struct JsonRecord<'a> {
+    timestamp: String,
+    level: &'a str,
+    module: &'a str,
+    /// The `event` field of the record, telling what happened without parsing the message.
+    event: serde_json::Value,
+    message: String,
+    #[serde(flatten)]
+    fields: Map<String, serde_json::Value>,
+}
+
+/// One JSON object per record with the fields next to the message.
+fn json_format(w: &mut dyn Write, now: &mut DeferredNow, record: &Record) -> io::Result<()> {
+    let mut fields = JsonFields::default();
+    record.key_values().visit(&mut fields).ok();
+    let mut fields: Map<_, _> = fields.0.into_iter().collect();
+
+    let record = JsonRecord {
+        timestamp: now.format_rfc3339(),
+        level: record.level().as_str(),
+        module: record.module_path().unwrap_or(record.target()),
+        event: fields.remove("event").unwrap_or_default(),
+        message: record.args().to_string(),
+        fields,
+    };
+    serde_json::to_writer(w, &record).map_err(io::Error::other)
+}
+
+/// Fields of a record in the order they were given.
+#[derive(Default)]
+struct JsonFields(Vec<(String, serde_json::Value)>);
+
+impl<'kvs> VisitSource<'kvs> for JsonFields {
+    fn visit_pair(&mut self, key: Key<'kvs>, value: Value<'kvs>) -> Result<(), kv::Error> {
+        self.0.push((key.to_string(), json_value(&value)));
+        Ok(())
+    }
+}
+
+/// Numbers and booleans stay what they are, `None` is null, anything else is its text.
+fn json_value(value: &Value) -> serde_json::Value {
+    struct Visitor(serde_json::Value);
+
+    impl VisitValue<'_> for Visitor {
+        fn visit_any(&mut self, value: Value) -> Result<(), kv::Error> {
+            self.0 = value.to_string().into();
+            Ok(())
+        }
+
+        fn visit_null(&mut self) -> Result<(), kv::Error> {
+            self.0 = serde_json::Value::Null;
+            Ok(())
+        }
+
+        fn visit_u64(&mut self, value: u64) -> Result<(), kv::Error> {
+            self.0 = value.into();
+            Ok(())
+        }
+
+        fn visit_i64(&mut self, value: i64) -> Result<(), kv::Error> {
+            self.0 = value.into();
+            Ok(())
+        }
+
+        fn visit_f64(&mut self, value: f64) -> Result<(), kv::Error> {
+            self.0 = value.into();
+            Ok(())
+        }
+
+        fn visit_bool(&mut self, value: bool) -> Result<(), kv::Error> {
+            self.0 = value.into();
+            Ok(())
+        }
+    }
+
+    let mut visitor = Visitor(serde_json::Value::Null);
+    value.visit(&mut visitor).ok();
+    visitor.0
+}
+
+fn default_directory() -> PathBuf {
+    /* a service may run without the profile variables */
+    state_dir().unwrap_or_else(|_| std::env::temp_dir().join("keep-audio-awake"))
+}
+
+#[cfg(windows)]
+use windows_logging::EventLogWriter as SystemLogWriter;
+
+#[cfg(target_os = "linux")]
+use linux_logging::JournalWriter as SystemLogWriter;
+
+#[cfg(windows)]
+mod windows_logging {
+    use crate::logging::json_value;
+    use flexi_logger::writers::LogWriter;
+    use flexi_logger::DeferredNow;
+    use log::kv::{self, Key, Value, VisitSource};
+    use log::{Level, Record};
+    use std::io;
+    use windows::core::{HSTRING, PCWSTR};
+    use windows::Win32::Foundation::HANDLE;
+    use windows::Win32::System::EventLog::{
+        DeregisterEventSource, RegisterEventSourceW, ReportEventW, EVENTLOG_ERROR_TYPE, EVENTLOG_INFORMATION_TYPE,
+        EVENTLOG_WARNING_TYPE,
+    };
+
+    const EVENT_SOURCE: &str = "KeepAudioAwake";
+
+    /// Reports to the Application log. Debug and trace records are left out.
+    pub struct EventLogWriter {
+        handle: HANDLE,
+    }
+
+    /* the event source handle may be used from any thread */
+    unsafe impl Send for EventLogWriter {}
+    unsafe impl Sync for EventLogWriter {}
+
+    impl EventLogWriter {
+        pub fn new() -> Result<Self, String> {
+            let handle = unsafe { RegisterEventSourceW(None, &HSTRING::from(EVENT_SOURCE)) }
+                .map_err(|e| format!("Failed to open the Event Log. {}", e))?;
+            Ok(Self { handle })
+        }
+    }
+
+    impl LogWriter for EventLogWriter {
+        fn write(&self, _now: &mut DeferredNow, record: &Record) -> io::Result<()> {
+            let event_type = match record.level() {
+                Level::Error => EVENTLOG_ERROR_TYPE,
+                Level::Warn => EVENTLOG_WARNING_TYPE,
+                Level::Info => EVENTLOG_INFORMATION_TYPE,
+                Level::Debug | Level::Trace => return Ok(()),
+            };
+
+            /* fields are appended as lines of the description */
+            let mut text = record.args().to_string();
+            record.key_values().visit(&mut Fields(&mut text)).ok();
+
+            let text = HSTRING::from(text);
+            unsafe { ReportEventW(self.handle, event_type, 0, 0, None, 0, Some(&[PCWSTR(text.as_ptr())]), None) }
+                .map_err(io::Error::other)
+        }
+
+        fn flush(&self) -> io::Result<()> {
+            Ok(())
+        }
+
+        fn max_log_level(&self) -> log::LevelFilter {
+            log::LevelFilter::Info
+        }
+    }
+
+    impl Drop for EventLogWriter {
+        fn drop(&mut self) {
+            unsafe { DeregisterEventSource(self.handle) }.unwrap_or_default();
+        }
+    }
+
+    struct Fields<'a>(&'a mut String);
+
+    impl<'kvs> VisitSource<'kvs> for Fields<'_> {
+        fn visit_pair(&mut self, key: Key<'kvs>, value: Value<'kvs>) -> Result<(), kv::Error> {
+            if !json_value(&value).is_null() {
+                self.0.push_str(&format!("\r\n{}: {}", key, value));
+            }
+            Ok(())
+        }
+    }
+}
+
+#[cfg(target_os = "linux")]
+mod linux_logging {
+    use crate::logging::json_value;
+    use flexi_logger::writers::LogWriter;
+    use flexi_logger::DeferredNow;
+    use log::kv::{self, Key, Value, VisitSource};
+    use log::{Level, Record};
+    use std::io;
+    use std::os::unix::net::UnixDatagram;
+
+    const JOURNAL_SOCKET: &str = "/run/systemd/journal/socket";
+    const SYSLOG_IDENTIFIER: &str = "keep-audio-awake";
+
+    /// Sends records to journald over its native protocol. Key-value pairs of a record become
+    /// journal fields, `state` becomes `STATE`.
+    pub struct JournalWriter {
+        socket: UnixDatagram,
+    }
+
+    impl JournalWriter {
+        pub fn new() -> Result<Self, String> {
+            let socket =
+                UnixDatagram::unbound().map_err(|e| format!("Failed to create journal socket. {}", e))?;
+            socket
+                .connect(JOURNAL_SOCKET)
+                .map_err(|e| format!("Failed to connect to journald. {}", e))?;
+            Ok(Self { socket })
+        }
+    }
+
+    impl LogWriter for JournalWriter {
+        fn write(&self, _now: &mut DeferredNow, record: &Record) -> io::Result<()> {
+            self.socket.send(&journal_entry(record)).map(|_| ())
+        }
+
+        fn flush(&self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    pub(super) fn journal_entry(record: &Record) -> Vec<u8> {
+        let mut entry = Vec::new();
+        add_field(&mut entry, "MESSAGE", &record.args().to_string());
+        add_field(&mut entry, "PRIORITY", priority(record.level()));
+        add_field(&mut entry, "SYSLOG_IDENTIFIER", SYSLOG_IDENTIFIER);
+        add_field(&mut entry, "TARGET", record.target());
+        if let Some(file) = record.file() {
+            add_field(&mut entry, "CODE_FILE", file);
+        }
+        if let Some(line) = record.line() {
+            add_field(&mut entry, "CODE_LINE", &line.to_string());
+        }
+        record.key_values().visit(&mut Fields(&mut entry)).ok();
+        entry
+    }
+
+    /// Syslog priority of the level.
+    fn priority(level: Level) -> &'static str {
+        match level {
+            Level::Error => "3",
+            Level::Warn => "4",
+            Level::Info => "6",
+            Level::Debug | Level::Trace => "7",
+        }
+    }
+
+    fn add_field(entry: &mut Vec<u8>, name: &str, value: &str) {
+        entry.extend_from_slice(name.as_bytes());
+        /* a value with line breaks is sent with its length instead of a separator */
+        if value.contains('\n') {
+            entry.push(b'\n');
+            entry.extend_from_slice(&(value.len() as u64).to_le_bytes());
+        } else {
+            entry.push(b'=');
+        }
+        entry.extend_from_slice(value.as_bytes());
+        entry.push(b'\n');
+    }
+
+    /// Journal field names are upper case letters, digits and underscores. Names starting with
+    /// an underscore are reserved for journald.
+    fn field_name(key: &str) -> String {
+        let name: String = key
+            .chars()
+            .map(|c| if c.is_ascii_alphanumeric() { c.to_ascii_uppercase() } else { '_' })
+            .collect();
+        match name.trim_start_matches('_') {
+            name if name.starts_with(|c: char| c.is_ascii_digit()) => format!("F_{}", name),
+            name => name.to_string(),
+        }
+    }
+
+    struct Fields<'a>(&'a mut Vec<u8>);
+
+    impl<'kvs> VisitSource<'kvs> for Fields<'_> {
+        fn visit_pair(&mut self, key: Key<'kvs>, value: Value<'kvs>) -> Result<(), kv::Error> {
+            if !json_value(&value).is_null() {
+                add_field(self.0, &field_name(key.as_str()), &value.to_string());
+            }
+            Ok(())
+        }
+    }
+
+    #[cfg(test)]
+    mod tests {
+        use crate::logging::linux_logging::{field_name, journal_entry};
+        use log::{Level, Record};
+
+        #[test]
+        fn test_journal_entry() {
+            let fields = [("state", "failing"), ("device", "hw:0")];
+            let record = Record::builder()
+                .args(format_args!("Pings\nkeep failing"))
+                .level(Level::Warn)
+                .target("keep_audio_awake::core")
+                .key_values(&fields)
+                .build();
+
+            let entry = journal_entry(&record);
+            let text = String::from_utf8_lossy(&entry);
+            assert!(entry.starts_with(b"MESSAGE\n\x12\0\0\0\0\0\0\0Pings\nkeep failing\n"));
+            assert!(text.contains("\nPRIORITY=4\n"));
+            assert!(text.contains("\nTARGET=keep_audio_awake::core\n"));
+            assert!(text.ends_with("\nSTATE=failing\nDEVICE=hw:0\n"));
+        }
+
+        #[test]
+        fn test_field_name() {
+            assert_eq!("ERROR_CODE", field_name("error_code"));
+            assert_eq!("PERIOD_MS", field_name("period.ms"));
+            assert_eq!("F_2ND", field_name("_2nd"));
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::logging::{json_format, text_format, LogArgs, LogFormat, LogSettings};
+    use flexi_logger::{DeferredNow, FormatFunction};
+    use log::kv::ToValue;
+    use log::{Level, Record};
+    use std::path::PathBuf;
+
+    fn format(format: FormatFunction) -> String {
+        let fields = [
+            ("event", "audio_error".to_value()),
+            ("device", "Speakers (USB Audio)".to_value()),
+            ("device_id", None::<u32>.to_value()),
+            ("code", 11.to_value()),
+        ];
+        let record = Record::builder()
+            .args(format_args!("Error playing waveform"))
+            .level(Level::Warn)
+            .module_path(Some("keep_audio_awake::audio"))
+            .key_values(&fields)
+            .build();
+
+        let mut buffer = Vec::new();
+        format(&mut buffer, &mut DeferredNow::new(), &record).unwrap();
+        String::from_utf8(buffer).unwrap()
+    }
+
+    #[test]
+    fn test_text_format() {
+        assert!(format(text_format)
+            .ends_with("Error playing waveform event=audio_error device=\"Speakers (USB Audio)\" code=11"));
+    }
+
+    #[test]
+    fn test_json_format() {
+        let line = format(json_format);
+        let record: serde_json::Value = serde_json::from_str(&line).unwrap();
+        assert_eq!("WARN", record["level"]);
+        assert_eq!("keep_audio_awake::audio", record["module"]);
+        assert_eq!("audio_error", record["event"]);
+        assert_eq!("Error playing waveform", record["message"]);
+        assert_eq!("Speakers (USB Audio)", record["device"]);
+        assert!(record["device_id"].is_null());
+        assert_eq!(11, record["code"]);
+        assert!(record["timestamp"].is_string());
+    }
+
+    #[test]
+    fn test_parse_args() {
+        let mut args = vec![
+            "--log-level=debug".to_string(),
+            "status".to_string(),
+            "--log-dir=/var/log/keep audio awake".to_string(),
+            "--log-stdout=false".to_string(),
+        ];
+        let log_args = LogArgs::parse(&mut args).unwrap();
+        assert_eq!(vec!["status"], args);
+
+        let settings = log_args.apply(&LogSettings::default());
+        assert_eq!("debug", settings.level);
+        assert_eq!(Some(PathBuf::from("/var/log/keep audio awake")), settings.directory);
+        assert!(!settings.stdout);
+        assert_eq!(LogSettings::default().keep_files, settings.keep_files);
+
+        let mut args = vec!["--log-format=json".to_string()];
+        assert_eq!(LogFormat::Json, LogArgs::parse(&mut args).unwrap().apply(&settings).format);
+    }
+
+    #[test]
+    fn test_parse_invalid_args() {
+        for arg in ["--log-level", "--log-keep-files=many", "--log-colour=false", "--log-format=xml"] {
+            assert!(LogArgs::parse(&mut vec![arg.to_string()]).is_err(), "{}", arg);
+        }
+    }
+
+    #[test]
+    fn test_validate() {
+        assert!(LogSettings::default().validate().is_ok());
+
+        let settings = LogSettings {
+            level: "warn,keep_audio_awake=debug".to_string(),
+            ..LogSettings::default()
+        };
+        assert!(settings.validate().is_ok());
+
+        let settings = LogSettings {
+            level: "вуигп".to_string(),
+            ..LogSettings::default()
+        };
+        assert!(settings.validate().is_err());
+    }
+}
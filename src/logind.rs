@@ -0,0 +1,82 @@
+use log::debug;
+use std::sync::mpsc::{channel, Receiver};
+use std::thread;
+use zbus::blocking::{Connection, MessageIterator, Proxy};
+use zbus::message::Type;
+use zbus::zvariant::OwnedObjectPath;
+use zbus::{MatchRule, Message};
+
+const DESTINATION: &str = "org.freedesktop.login1";
+pub const MANAGER_PATH: &str = "/org/freedesktop/login1";
+pub const MANAGER_INTERFACE: &str = "org.freedesktop.login1.Manager";
+pub const SESSION_INTERFACE: &str = "org.freedesktop.login1.Session";
+/* the session of the caller, or the graphical session of the user for a service */
+const AUTO_SESSION_PATH: &str = "/org/freedesktop/login1/session/auto";
+
+pub fn system_bus() -> Result<Connection, String> {
+    Connection::system().map_err(|e| format!("Failed to connect to the system bus. {}", e))
+}
+
+/// Object path of the login session the application belongs to.
+pub fn session_path(connection: &Connection) -> Result<OwnedObjectPath, String> {
+    let id = match std::env::var("XDG_SESSION_ID") {
+        Ok(id) => id,
+        Err(_) => Proxy::new(connection, DESTINATION, AUTO_SESSION_PATH, SESSION_INTERFACE)
+            .and_then(|session| session.get_property::<String>("Id"))
+            .map_err(|e| format!("Failed to find the login session. {}", e))?,
+    };
+
+    manager_proxy(connection)
+        .and_then(|manager| manager.call("GetSession", &(id.as_str(),)))
+        .map_err(|e| format!("Failed to find login session {}. {}", id, e))
+}
+
+/// Proxy to the `Manager` interface of logind.
+pub fn manager_proxy(connection: &Connection) -> zbus::Result<Proxy<'static>> {
+    Proxy::new(connection, DESTINATION, MANAGER_PATH, MANAGER_INTERFACE)
+}
+
+/// Proxy to the `Session` interface of the login session the application belongs to.
+pub fn session_proxy(connection: &Connection) -> Result<Proxy<'static>, String> {
+    let path = session_path(connection)?;
+    Proxy::new(connection, DESTINATION, path, SESSION_INTERFACE)
+        .map_err(|e| format!("Failed to open the login session. {}", e))
+}
+
+/// Forwards the signals of a logind object that `map` turns into events, from a background
+/// thread. `map` gets the signal name and the message. `wake` is called after each event. The
+/// thread exits on the first signal after the receiver is dropped.
+pub fn watch<T, M, W>(connection: &Connection, path: &str, map: M, wake: W) -> Result<Receiver<T>, String>
+where
+    T: Send + 'static,
+    M: Fn(&str, &Message) -> Option<T> + Send + 'static,
+    W: Fn() + Send + 'static,
+{
+    let messages = MatchRule::builder()
+        .msg_type(Type::Signal)
+        .sender(DESTINATION)
+        .and_then(|builder| builder.path(path))
+        .map(|builder| builder.build().to_owned())
+        .and_then(|rule| MessageIterator::for_match_rule(rule, connection, None))
+        .map_err(|e| format!("Failed to subscribe to signals of {}. {}", path, e))?;
+
+    let (sender, receiver) = channel();
+    thread::Builder::new()
+        .name("logind".to_string())
+        .spawn(move || {
+            for message in messages.flatten() {
+                let header = message.header();
+                let Some(event) = header.member().and_then(|member| map(member.as_str(), &message)) else {
+                    continue;
+                };
+                if sender.send(event).is_err() {
+                    break;
+                }
+                wake();
+            }
+            debug!("Stopped watching logind signals");
+        })
+        .map_err(|e| format!("Failed to watch logind signals. {}", e))?;
+
+    Ok(receiver)
+}
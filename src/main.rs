@@ -1,36 +1,157 @@
 #![cfg_attr(not(feature = "console"), windows_subsystem = "windows")] /* hides console window */
-use flexi_logger::colored_detailed_format;
-use log::error;
+use crate::ipc::{Command, Response};
+use crate::logging::{LogArgs, LogSettings};
+use crate::settings::Settings;
+
+/// Runs without a window or a tray icon.
+const HEADLESS_FLAG: &str = "--headless";
+/// `autostart [on|off]` is carried out by the command line itself, the application does not
+/// have to be running.
+const AUTOSTART_COMMAND: &str = "autostart";
+/// `service install|uninstall|start|stop` manages the Windows service running the headless
+/// front-end. The service manager starts it with `service run`.
+#[cfg(windows)]
+const SERVICE_COMMAND: &str = "service";
+/// Writes systemd user units for the headless front-end.
+#[cfg(target_os = "linux")]
+const INSTALL_SERVICE_COMMAND: &str = "install-service";
 
 mod audio;
+mod autostart;
+mod battery;
+mod core;
+#[cfg(target_os = "linux")]
+mod dbus;
+mod devices;
+#[cfg(windows)]
 mod gui;
+mod headless;
+mod hooks;
+mod idle;
+mod instance;
+mod ipc;
+mod logging;
+#[cfg(target_os = "linux")]
+mod logind;
+mod metrics;
+mod mqtt;
+mod notifications;
+mod power;
+mod scheduler;
+mod session;
+#[cfg(any(windows, test))]
+mod service;
+mod settings;
+#[cfg(any(windows, test))]
+mod settings_form;
+mod status;
+mod supervisor;
+#[cfg(target_os = "linux")]
+mod systemd;
+mod tray;
+#[cfg(windows)]
 mod util;
 
-fn setup_logger() {
-    use flexi_logger::{Cleanup, Criterion, Duplicate, FileSpec, Logger, Naming};
-
-    Logger::try_with_env_or_str("вуигп")
-        .expect("Failed to create logger")
-        .log_to_file(FileSpec::default().directory(".log"))
-        .set_palette("1;3;4;2;7".into())
-        .format(colored_detailed_format)
-        .rotate(
-            Criterion::Size(10_000_000),
-            Naming::Timestamps,
-            Cleanup::KeepLogFiles(7),
-        )
-        .duplicate_to_stdout(Duplicate::All)
-        .start()
-        .expect("Failed to initialize logger.");
+fn setup_logger(settings: &LogSettings, args: &LogArgs) -> Result<(), String> {
+    logging::setup(settings, args)?;
+    supervisor::install_panic_hook();
 
-    std::panic::set_hook(Box::new(|panic_info| {
-        error!("{}", panic_info);
-    }));
+    Ok(())
 }
 
 fn main() -> Result<(), String> {
-    setup_logger();
-    gui::run_main()?;
+    let mut args = std::env::args().skip(1).collect::<Vec<_>>();
+    if let Some(path) = settings::take_config_arg(&mut args)? {
+        settings::set_config_path(path);
+    }
+    let log_args = LogArgs::parse(&mut args)?;
+    setup_logger(&Settings::load().unwrap_or_default().log, &log_args)?;
+
+    let command = args.join(" ");
+    let (name, arg) = command.split_once(' ').unwrap_or((&command, ""));
+    match name {
+        "" => run_app(false)?,
+        HEADLESS_FLAG if arg.is_empty() => run_app(true)?,
+        AUTOSTART_COMMAND => run_autostart(arg.trim())?,
+        #[cfg(target_os = "linux")]
+        INSTALL_SERVICE_COMMAND if arg.is_empty() => run_install_service()?,
+        #[cfg(windows)]
+        SERVICE_COMMAND => run_service_command(arg.trim())?,
+        _ => run_command(&command)?,
+    }
+
+    Ok(())
+}
+
+#[cfg(windows)]
+fn run_app(headless: bool) -> Result<(), String> {
+    if headless {
+        headless::run_main(false)
+    } else {
+        gui::run_main()
+    }
+}
+
+#[cfg(target_os = "linux")]
+fn run_app(headless: bool) -> Result<(), String> {
+    headless::run_main(!headless)
+}
+
+/// Turns starting at login on or off, or prints whether it is on.
+fn run_autostart(arg: &str) -> Result<(), String> {
+    #[cfg(windows)]
+    util::attach_console();
+
+    match arg {
+        "on" => autostart::set_enabled(true),
+        "off" => autostart::set_enabled(false),
+        "" => autostart::is_enabled().map(|enabled| println!("{}", if enabled { "on" } else { "off" })),
+        _ => Err(format!("Command {} expects on or off", AUTOSTART_COMMAND)),
+    }
+}
+
+#[cfg(windows)]
+fn run_service_command(arg: &str) -> Result<(), String> {
+    if arg == service::RUN_ARG {
+        return service::run_dispatcher();
+    }
 
+    util::attach_console();
+    match arg {
+        "install" => service::install().map(|config| println!("The service reads {}", config.display())),
+        "uninstall" => service::uninstall(),
+        "start" => service::start(),
+        "stop" => service::stop(),
+        _ => Err(format!("Command {} expects install, uninstall, start or stop", SERVICE_COMMAND)),
+    }
+}
+
+#[cfg(target_os = "linux")]
+fn run_install_service() -> Result<(), String> {
+    let settings = Settings::load().unwrap_or_default();
+    for path in systemd::install_service(&settings)? {
+        println!("Written {}", path.display());
+    }
+    println!("Enable with:");
+    println!("systemctl --user daemon-reload");
+    println!("systemctl --user enable --now keep-audio-awake.socket keep-audio-awake.service");
     Ok(())
 }
+
+/// Forwards the command to the running instance.
+fn run_command(command: &str) -> Result<(), String> {
+    let command = command.parse::<Command>()?;
+    #[cfg(windows)]
+    util::attach_console();
+
+    let scope = Settings::load().unwrap_or_default().instance_scope;
+    match ipc::send(scope, command)? {
+        Response::Ok(text) => {
+            if !text.is_empty() {
+                println!("{}", text);
+            }
+            Ok(())
+        }
+        Response::Error(text) => Err(text),
+    }
+}
@@ -0,0 +1,242 @@
+use crate::status::State;
+use log::{debug, trace, warn};
+use serde::Serialize;
+use std::fmt::Write as _;
+use std::io::{BufRead, BufReader, Write};
+use std::net::{Ipv4Addr, TcpListener, TcpStream};
+use std::sync::atomic::{AtomicBool, AtomicU64, AtomicU8, Ordering};
+use std::thread;
+use std::time::Duration;
+
+const PREFIX: &str = "keep_audio_awake";
+const LATENCY_BUCKETS_MS: [u64; 8] = [5, 10, 25, 50, 100, 250, 1000, 5000];
+const STATES: [State; 4] = [State::Active, State::Paused, State::Suspended, State::Failing];
+/* connections are served one at a time, so a silent client must not hold up the others */
+const CONNECTION_TIMEOUT: Duration = Duration::from_secs(5);
+
+pub static METRICS: Metrics = Metrics::new();
+
+/// Counter values at a point in time.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize)]
+pub struct Counters {
+    pub plays: u64,
+    pub write_failures: u64,
+    pub resets: u64,
+    pub reopen_attempts: u64,
+}
+
+/// Keep-alive counters exported in the Prometheus text format.
+pub struct Metrics {
+    enabled: AtomicBool,
+    plays: AtomicU64,
+    write_failures: AtomicU64,
+    resets: AtomicU64,
+    reopen_attempts: AtomicU64,
+    latency_buckets: [AtomicU64; LATENCY_BUCKETS_MS.len()],
+    latency_count: AtomicU64,
+    latency_sum_us: AtomicU64,
+    state: AtomicU8,
+}
+
+impl Metrics {
+    const fn new() -> Self {
+        Self {
+            enabled: AtomicBool::new(false),
+            plays: AtomicU64::new(0),
+            write_failures: AtomicU64::new(0),
+            resets: AtomicU64::new(0),
+            reopen_attempts: AtomicU64::new(0),
+            latency_buckets: [const { AtomicU64::new(0) }; LATENCY_BUCKETS_MS.len()],
+            latency_count: AtomicU64::new(0),
+            latency_sum_us: AtomicU64::new(0),
+            state: AtomicU8::new(State::Active as u8),
+        }
+    }
+
+    /// Whether anyone collects the metrics, so that costly measurements can be skipped.
+    pub fn is_enabled(&self) -> bool {
+        self.enabled.load(Ordering::Relaxed)
+    }
+
+    pub fn count_play(&self) {
+        self.plays.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn count_write_failure(&self) {
+        self.write_failures.fetch_add(1, Ordering::Relaxed);
+    }
+
+    #[cfg(windows)]
+    pub fn count_reset(&self) {
+        self.resets.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn count_reopen_attempt(&self) {
+        self.reopen_attempts.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn observe_latency(&self, latency: Duration) {
+        let ms = latency.as_millis() as u64;
+        for (bucket, le) in self.latency_buckets.iter().zip(LATENCY_BUCKETS_MS) {
+            if ms <= le {
+                bucket.fetch_add(1, Ordering::Relaxed);
+            }
+        }
+        self.latency_count.fetch_add(1, Ordering::Relaxed);
+        self.latency_sum_us
+            .fetch_add(latency.as_micros() as u64, Ordering::Relaxed);
+    }
+
+    pub fn set_state(&self, state: State) {
+        self.state.store(state as u8, Ordering::Relaxed);
+    }
+
+    pub fn counters(&self) -> Counters {
+        Counters {
+            plays: self.plays.load(Ordering::Relaxed),
+            write_failures: self.write_failures.load(Ordering::Relaxed),
+            resets: self.resets.load(Ordering::Relaxed),
+            reopen_attempts: self.reopen_attempts.load(Ordering::Relaxed),
+        }
+    }
+
+    fn render(&self) -> String {
+        let mut out = String::new();
+
+        for (name, help, counter) in [
+            ("plays_total", "Successful keep-alive pings.", &self.plays),
+            ("write_failures_total", "Failed waveform writes.", &self.write_failures),
+            ("resets_total", "Device resets after failed writes.", &self.resets),
+            ("reopen_attempts_total", "Attempts to reopen the device.", &self.reopen_attempts),
+        ] {
+            let _ = writeln!(out, "# HELP {PREFIX}_{name} {help}");
+            let _ = writeln!(out, "# TYPE {PREFIX}_{name} counter");
+            let _ = writeln!(out, "{PREFIX}_{name} {}", counter.load(Ordering::Relaxed));
+        }
+
+        let name = format!("{PREFIX}_play_latency_seconds");
+        let count = self.latency_count.load(Ordering::Relaxed);
+        let _ = writeln!(out, "# HELP {name} Time until the audio device completes a buffer.");
+        let _ = writeln!(out, "# TYPE {name} histogram");
+        for (bucket, le) in self.latency_buckets.iter().zip(LATENCY_BUCKETS_MS) {
+            let _ = writeln!(
+                out,
+                "{name}_bucket{{le=\"{}\"}} {}",
+                le as f64 / 1000.0,
+                bucket.load(Ordering::Relaxed)
+            );
+        }
+        let _ = writeln!(out, "{name}_bucket{{le=\"+Inf\"}} {count}");
+        let _ = writeln!(
+            out,
+            "{name}_sum {}",
+            self.latency_sum_us.load(Ordering::Relaxed) as f64 / 1_000_000.0
+        );
+        let _ = writeln!(out, "{name}_count {count}");
+
+        let name = format!("{PREFIX}_state");
+        let current = self.state.load(Ordering::Relaxed);
+        let _ = writeln!(out, "# HELP {name} Current keep-alive state.");
+        let _ = writeln!(out, "# TYPE {name} gauge");
+        for state in STATES {
+            let _ = writeln!(
+                out,
+                "{name}{{state=\"{}\"}} {}",
+                state.as_str(),
+                (state as u8 == current) as u8
+            );
+        }
+
+        out
+    }
+}
+
+/// Serves the metrics on `127.0.0.1:<port>` in a background thread.
+pub fn start_server(port: u16) -> Result<(), String> {
+    let listener = TcpListener::bind((Ipv4Addr::LOCALHOST, port))
+        .map_err(|e| format!("Failed to bind metrics port {}. {}", port, e))?;
+
+    thread::Builder::new()
+        .name("metrics".to_string())
+        .spawn(move || {
+            for stream in listener.incoming() {
+                match stream {
+                    Ok(stream) => handle_connection(stream),
+                    Err(e) => warn!("Failed to accept metrics connection. {}", e),
+                }
+            }
+        })
+        .map_err(|e| format!("Failed to start metrics server. {}", e))?;
+
+    METRICS.enabled.store(true, Ordering::Relaxed);
+    debug!("Metrics served on port {}", port);
+
+    Ok(())
+}
+
+fn handle_connection(mut stream: TcpStream) {
+    if let Err(e) = stream
+        .set_read_timeout(Some(CONNECTION_TIMEOUT))
+        .and_then(|()| stream.set_write_timeout(Some(CONNECTION_TIMEOUT)))
+    {
+        warn!("Failed to set metrics connection timeout. {}", e);
+        return;
+    }
+
+    let mut reader = BufReader::new(&stream);
+    let mut request_line = String::new();
+    if reader.read_line(&mut request_line).is_err() {
+        return;
+    }
+
+    /* skip the headers */
+    let mut line = String::new();
+    while reader.read_line(&mut line).is_ok_and(|n| n > 0 && line != "\r\n") {
+        line.clear();
+    }
+
+    trace!("Metrics request: {}", request_line.trim_end());
+
+    let response = match request_line.split_whitespace().nth(1) {
+        Some("/metrics") => {
+            let body = METRICS.render();
+            format!(
+                "HTTP/1.1 200 OK\r\nContent-Type: text/plain; version=0.0.4\r\n\
+                 Content-Length: {}\r\nConnection: close\r\n\r\n{}",
+                body.len(),
+                body
+            )
+        }
+        _ => "HTTP/1.1 404 Not Found\r\nContent-Length: 0\r\nConnection: close\r\n\r\n".to_string(),
+    };
+
+    stream
+        .write_all(response.as_bytes())
+        .unwrap_or_else(|e| warn!("Failed to write metrics response. {}", e));
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::metrics::Metrics;
+    use crate::status::State;
+    use std::time::Duration;
+
+    #[test]
+    fn test_render() {
+        let metrics = Metrics::new();
+        metrics.count_play();
+        metrics.count_play();
+        metrics.observe_latency(Duration::from_millis(20));
+        metrics.set_state(State::Paused);
+
+        let text = metrics.render();
+        assert!(text.contains("keep_audio_awake_plays_total 2\n"));
+        assert!(text.contains("keep_audio_awake_write_failures_total 0\n"));
+        assert!(text.contains("keep_audio_awake_play_latency_seconds_bucket{le=\"0.01\"} 0\n"));
+        assert!(text.contains("keep_audio_awake_play_latency_seconds_bucket{le=\"0.025\"} 1\n"));
+        assert!(text.contains("keep_audio_awake_play_latency_seconds_bucket{le=\"+Inf\"} 1\n"));
+        assert!(text.contains("keep_audio_awake_play_latency_seconds_count 1\n"));
+        assert!(text.contains("keep_audio_awake_state{state=\"paused\"} 1\n"));
+        assert!(text.contains("keep_audio_awake_state{state=\"active\"} 0\n"));
+    }
+}
@@ -0,0 +1,556 @@
+use crate::ipc::{Command, Request, Response};
+use crate::metrics::METRICS;
+use crate::status::{State, Status};
+use log::{debug, warn};
+use rumqttc::{Client, Connection, Event, LastWill, MqttOptions, Outgoing, Packet, QoS};
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+use std::sync::mpsc::{channel, Receiver, RecvTimeoutError, Sender, TryRecvError};
+use std::thread;
+use std::time::{Duration, Instant};
+
+#[cfg(windows)]
+pub const TIMER_MQTT: usize = 500;
+pub const TIMER_MQTT_PERIOD_MS: u32 = 1000;
+
+const CLIENT_NAME: &str = "keep-audio-awake";
+const KEEP_ALIVE: Duration = Duration::from_secs(30);
+const RECONNECT_DELAY: Duration = Duration::from_secs(5);
+const RESPONSE_TIMEOUT: Duration = Duration::from_secs(5);
+const REQUEST_CAPACITY: usize = 64;
+const ONLINE: &str = "online";
+const OFFLINE: &str = "offline";
+/* what the Home Assistant snooze button sends */
+const SNOOZE_BUTTON_MIN: u32 = 15;
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct MqttSettings {
+    pub host: String,
+    pub port: u16,
+    pub username: Option<String>,
+    pub password: Option<String>,
+    /// Topics are `<topic_prefix>/<host name>/...`.
+    pub topic_prefix: String,
+    /// Home Assistant discovery prefix. Empty disables discovery.
+    pub discovery_prefix: String,
+    /// State is republished this often even if it has not changed.
+    pub publish_interval_secs: u32,
+}
+
+impl Default for MqttSettings {
+    fn default() -> Self {
+        Self {
+            host: "localhost".to_string(),
+            port: 1883,
+            username: None,
+            password: None,
+            topic_prefix: CLIENT_NAME.to_string(),
+            discovery_prefix: "homeassistant".to_string(),
+            publish_interval_secs: 60,
+        }
+    }
+}
+
+impl MqttSettings {
+    pub fn validate(&self) -> Result<(), String> {
+        if self.host.is_empty() {
+            Err("MQTT host must not be empty")?
+        }
+        if self.port == 0 {
+            Err("MQTT port must not be zero")?
+        }
+        if self.topic_prefix.is_empty() {
+            Err("MQTT topic prefix must not be empty")?
+        }
+        if self.publish_interval_secs == 0 {
+            Err("MQTT publish interval must not be zero")?
+        }
+        Ok(())
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+struct Topics {
+    node_id: String,
+    state: String,
+    metrics: String,
+    availability: String,
+    command: String,
+}
+
+impl Topics {
+    fn new(prefix: &str, node_id: &str) -> Self {
+        let base = format!("{}/{}", prefix, node_id);
+        Self {
+            node_id: node_id.to_string(),
+            state: format!("{}/state", base),
+            metrics: format!("{}/metrics", base),
+            availability: format!("{}/availability", base),
+            command: format!("{}/command", base),
+        }
+    }
+}
+
+/// Publishes the keep-alive state to a broker and forwards commands from it.
+pub struct MqttClient {
+    client: Client,
+    /* tells the background thread to exit, as does dropping it */
+    stop: Sender<()>,
+    topics: Topics,
+    interval: Duration,
+    last_published: Option<(State, u32, Instant)>,
+}
+
+impl MqttClient {
+    /// Connects in a background thread. Commands from the broker are queued to the returned
+    /// receiver, and `wake` is called after each one.
+    pub fn start<F>(settings: &MqttSettings, wake: F) -> Result<(Self, Receiver<Request>), String>
+    where
+        F: Fn() + Send + 'static,
+    {
+        let node_id = node_id();
+        let topics = Topics::new(&settings.topic_prefix, &node_id);
+
+        let mut options = MqttOptions::new(
+            format!("{}-{}", CLIENT_NAME, node_id),
+            &settings.host,
+            settings.port,
+        );
+        options.set_keep_alive(KEEP_ALIVE);
+        options.set_last_will(LastWill::new(
+            &topics.availability,
+            OFFLINE,
+            QoS::AtLeastOnce,
+            true,
+        ));
+        if let Some(username) = &settings.username {
+            options.set_credentials(username, settings.password.as_deref().unwrap_or_default());
+        }
+
+        let (client, connection) = Client::new(options, REQUEST_CAPACITY);
+        let (sender, receiver) = channel();
+        let (stop, stop_receiver) = channel();
+        let discovery = if settings.discovery_prefix.is_empty() {
+            Vec::new()
+        } else {
+            discovery(&settings.discovery_prefix, &topics)
+        };
+
+        let thread_client = client.clone();
+        let thread_topics = topics.clone();
+        thread::Builder::new()
+            .name("mqtt".to_string())
+            .spawn(move || {
+                let context = Context {
+                    client: thread_client,
+                    topics: thread_topics,
+                    discovery,
+                    requests: sender,
+                    wake,
+                };
+                run(connection, &context, &stop_receiver)
+            })
+            .map_err(|e| format!("Failed to start MQTT client. {}", e))?;
+
+        debug!("MQTT client started for {}:{}", settings.host, settings.port);
+
+        Ok((
+            Self {
+                client,
+                stop,
+                topics,
+                interval: Duration::from_secs(settings.publish_interval_secs as u64),
+                last_published: None,
+            },
+            receiver,
+        ))
+    }
+
+    /// Publishes the status if it has changed or the publish interval has elapsed.
+    pub fn publish(&mut self, status: &Status) {
+        let due = match self.last_published {
+            Some((state, period_ms, published)) => {
+                state != status.state
+                    || period_ms != status.period_ms
+                    || published.elapsed() >= self.interval
+            }
+            None => true,
+        };
+        if !due {
+            return;
+        }
+
+        let counters = serde_json::to_string(&METRICS.counters()).unwrap_or_default();
+        self.try_publish(&self.topics.state, status.to_json());
+        self.try_publish(&self.topics.metrics, counters);
+        self.last_published = Some((status.state, status.period_ms, Instant::now()));
+    }
+
+    /// Marks the application offline and disconnects. The background thread exits once the
+    /// disconnect is sent, or right away if there is no connection.
+    pub fn stop(&self) {
+        self.try_publish(&self.topics.availability, OFFLINE.to_string());
+        self.client
+            .try_disconnect()
+            .unwrap_or_else(|e| warn!("Failed to disconnect from MQTT broker. {}", e));
+        self.stop.send(()).unwrap_or_default();
+    }
+
+    fn try_publish(&self, topic: &str, payload: String) {
+        self.client
+            .try_publish(topic, QoS::AtLeastOnce, true, payload)
+            .unwrap_or_else(|e| warn!("Failed to publish to {}. {}", topic, e));
+    }
+}
+
+/// What the background thread works with besides the connection.
+struct Context<F> {
+    client: Client,
+    topics: Topics,
+    discovery: Vec<(String, String)>,
+    requests: Sender<Request>,
+    wake: F,
+}
+
+fn run<F: Fn()>(mut connection: Connection, context: &Context<F>, stop: &Receiver<()>) {
+    let mut stopping = false;
+
+    for event in connection.iter() {
+        /* the connection reconnects on its own, so the loop only ends here */
+        stopping |= !matches!(stop.try_recv(), Err(TryRecvError::Empty));
+
+        match event {
+            Ok(Event::Incoming(Packet::ConnAck(_))) if !stopping => {
+                debug!("Connected to MQTT broker");
+                set_up_topics(context);
+            }
+            Ok(Event::Incoming(Packet::Publish(publish))) if publish.topic == context.topics.command => {
+                let text = String::from_utf8_lossy(&publish.payload);
+                match parse_command(text.trim()) {
+                    Ok(command) => {
+                        if !forward(command, &context.requests, &context.wake) {
+                            break;
+                        }
+                    }
+                    Err(e) => warn!("{}", e),
+                }
+            }
+            Ok(Event::Outgoing(Outgoing::Disconnect)) => break,
+            Ok(_) => {}
+            Err(_) if stopping => break,
+            Err(e) => {
+                warn!("MQTT connection error. {}", e);
+                /* woken up early to stop */
+                if stop.recv_timeout(RECONNECT_DELAY) != Err(RecvTimeoutError::Timeout) {
+                    break;
+                }
+            }
+        }
+    }
+
+    debug!("MQTT client stopped");
+}
+
+/// The session is not persistent, so everything is renewed on each connect.
+fn set_up_topics<F>(context: &Context<F>) {
+    let client = &context.client;
+    let topics = &context.topics;
+    let result = client
+        .try_subscribe(&topics.command, QoS::AtLeastOnce)
+        .and_then(|_| {
+            context.discovery.iter().try_for_each(|(topic, payload)| {
+                client.try_publish(topic, QoS::AtLeastOnce, true, payload.clone())
+            })
+        })
+        .and_then(|_| client.try_publish(&topics.availability, QoS::AtLeastOnce, true, ONLINE));
+    result.unwrap_or_else(|e| warn!("Failed to set up MQTT topics. {}", e));
+}
+
+/// Returns false if the application no longer takes requests.
+fn forward(command: Command, requests: &Sender<Request>, wake: &dyn Fn()) -> bool {
+    debug!("MQTT command: {:?}", command);
+
+    let (request, response) = Request::new(command);
+    if requests.send(request).is_err() {
+        return false;
+    }
+    wake();
+
+    match response.recv_timeout(RESPONSE_TIMEOUT) {
+        Ok(Response::Ok(_)) => {}
+        Ok(Response::Error(e)) => warn!("MQTT command {} failed. {}", command, e),
+        Err(_) => warn!("No response to MQTT command {}", command),
+    }
+    true
+}
+
+/// Only commands that change the keep-alive are accepted from the broker.
+fn parse_command(text: &str) -> Result<Command, String> {
+    match text.parse::<Command>()? {
+        command @ (Command::Pause | Command::Resume | Command::Snooze(_) | Command::Period(_)) => {
+            Ok(command)
+        }
+        command => Err(format!("Command {} is not accepted over MQTT", command)),
+    }
+}
+
+/// Home Assistant discovery topics and their configs.
+fn discovery(prefix: &str, topics: &Topics) -> Vec<(String, String)> {
+    let node_id = &topics.node_id;
+    let device = json!({
+        "identifiers": [format!("{}_{}", CLIENT_NAME, node_id)],
+        "name": format!("Keep Audio Awake ({})", node_id),
+        "model": CLIENT_NAME,
+        "sw_version": env!("CARGO_PKG_VERSION"),
+    });
+
+    let entities = [
+        (
+            "sensor",
+            "state",
+            json!({
+                "name": "State",
+                "state_topic": topics.state,
+                "value_template": "{{ value_json.state }}",
+                "json_attributes_topic": topics.state,
+                "icon": "mdi:speaker",
+            }),
+        ),
+        (
+            "sensor",
+            "plays",
+            json!({
+                "name": "Keep-alive pings",
+                "state_topic": topics.metrics,
+                "value_template": "{{ value_json.plays }}",
+                "state_class": "total_increasing",
+                "entity_category": "diagnostic",
+            }),
+        ),
+        (
+            "sensor",
+            "write_failures",
+            json!({
+                "name": "Write failures",
+                "state_topic": topics.metrics,
+                "value_template": "{{ value_json.write_failures }}",
+                "state_class": "total_increasing",
+                "entity_category": "diagnostic",
+            }),
+        ),
+        (
+            "switch",
+            "keep_awake",
+            json!({
+                "name": "Keep awake",
+                "state_topic": topics.state,
+                "value_template": "{{ 'OFF' if value_json.state == 'paused' else 'ON' }}",
+                "command_topic": topics.command,
+                "payload_on": Command::Resume.to_string(),
+                "payload_off": Command::Pause.to_string(),
+                "icon": "mdi:speaker-play",
+            }),
+        ),
+        (
+            "button",
+            "snooze",
+            json!({
+                "name": format!("Snooze {} min", SNOOZE_BUTTON_MIN),
+                "command_topic": topics.command,
+                "payload_press": Command::Snooze(SNOOZE_BUTTON_MIN).to_string(),
+                "icon": "mdi:sleep",
+            }),
+        ),
+        (
+            "number",
+            "period",
+            json!({
+                "name": "Period",
+                "state_topic": topics.state,
+                "value_template": "{{ value_json.period_ms }}",
+                "command_topic": topics.command,
+                "command_template": "period {{ value | int }}",
+                "min": 1000,
+                "max": 3_600_000,
+                "step": 1000,
+                "mode": "box",
+                "unit_of_measurement": "ms",
+                "entity_category": "config",
+            }),
+        ),
+    ];
+
+    entities
+        .into_iter()
+        .map(|(component, object_id, mut config)| {
+            config["unique_id"] = json!(format!("{}_{}_{}", CLIENT_NAME, node_id, object_id));
+            config["availability_topic"] = json!(topics.availability);
+            config["device"] = device.clone();
+
+            (
+                format!("{}/{}/{}_{}/{}/config", prefix, component, CLIENT_NAME, node_id, object_id),
+                config.to_string(),
+            )
+        })
+        .collect()
+}
+
+/// Host name usable as a topic level and an entity id.
+fn node_id() -> String {
+    let host = std::env::var("COMPUTERNAME")
+        .or_else(|_| std::env::var("HOSTNAME"))
+        .or_else(|_| std::fs::read_to_string("/etc/hostname"))
+        .unwrap_or_default();
+
+    sanitize(host.trim())
+}
+
+fn sanitize(name: &str) -> String {
+    let name: String = name
+        .chars()
+        .map(|c| if c.is_ascii_alphanumeric() { c.to_ascii_lowercase() } else { '_' })
+        .collect();
+
+    if name.is_empty() {
+        "default".to_string()
+    } else {
+        name
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::ipc::Command;
+    use crate::mqtt::{discovery, parse_command, sanitize, MqttClient, MqttSettings, Topics};
+    use rumqttc::{Client, Event, MqttOptions, Packet, QoS};
+    use std::net::{TcpListener, TcpStream};
+    use std::process::{Child, Command as Process};
+    use std::sync::mpsc::{channel, RecvTimeoutError};
+    use std::thread;
+    use std::time::{Duration, Instant};
+
+    /// Mosquitto on a free local port, killed on drop.
+    struct Broker {
+        process: Child,
+        port: u16,
+    }
+
+    impl Broker {
+        /// None if Mosquitto is not installed.
+        fn start() -> Option<Self> {
+            let port = free_port();
+            let process = Process::new("mosquitto")
+                .args(["-p", &port.to_string()])
+                .spawn()
+                .inspect_err(|e| eprintln!("Skipped, failed to run mosquitto. {}", e))
+                .ok()?;
+            let broker = Self { process, port };
+
+            let started = Instant::now();
+            while TcpStream::connect(("127.0.0.1", port)).is_err() {
+                assert!(started.elapsed() < Duration::from_secs(5), "Mosquitto did not start");
+                thread::sleep(Duration::from_millis(50));
+            }
+            Some(broker)
+        }
+    }
+
+    impl Drop for Broker {
+        fn drop(&mut self) {
+            self.process.kill().unwrap_or_default();
+            self.process.wait().map(|_| ()).unwrap_or_default();
+        }
+    }
+
+    fn free_port() -> u16 {
+        TcpListener::bind("127.0.0.1:0").unwrap().local_addr().unwrap().port()
+    }
+
+    fn settings(port: u16) -> MqttSettings {
+        MqttSettings {
+            host: "127.0.0.1".to_string(),
+            port,
+            topic_prefix: format!("test-{}", port),
+            discovery_prefix: String::new(),
+            ..MqttSettings::default()
+        }
+    }
+
+    #[test]
+    fn test_topics() {
+        let topics = Topics::new("keep-audio-awake", &sanitize("HTPC-Living.Room"));
+        assert_eq!("keep-audio-awake/htpc_living_room/state", topics.state);
+        assert_eq!("keep-audio-awake/htpc_living_room/command", topics.command);
+        assert_eq!("default", sanitize(""));
+    }
+
+    #[test]
+    fn test_parse_command() {
+        assert_eq!(Ok(Command::Snooze(15)), parse_command("snooze 15"));
+        assert_eq!(Ok(Command::Period(10000)), parse_command("period 10000"));
+        assert!(parse_command("stop").is_err());
+        assert!(parse_command("sleep").is_err());
+    }
+
+    #[test]
+    fn test_discovery() {
+        let topics = Topics::new("keep-audio-awake", "htpc");
+        let configs = discovery("homeassistant", &topics);
+
+        let (topic, payload) = configs
+            .iter()
+            .find(|(topic, _)| topic.starts_with("homeassistant/switch/"))
+            .unwrap();
+        assert_eq!("homeassistant/switch/keep-audio-awake_htpc/keep_awake/config", topic);
+
+        let config: serde_json::Value = serde_json::from_str(payload).unwrap();
+        assert_eq!("keep-audio-awake_htpc_keep_awake", config["unique_id"]);
+        assert_eq!("keep-audio-awake/htpc/command", config["command_topic"]);
+        assert_eq!("keep-audio-awake/htpc/availability", config["availability_topic"]);
+        assert_eq!("pause", config["payload_off"]);
+    }
+
+    #[test]
+    fn test_stop_without_broker() {
+        let (client, requests) = MqttClient::start(&settings(free_port()), || {}).unwrap();
+        thread::sleep(Duration::from_millis(100));
+        client.stop();
+
+        /* the thread drops its sender when it exits */
+        assert_eq!(Err(RecvTimeoutError::Disconnected), requests.recv_timeout(Duration::from_secs(5)).map(|_| ()));
+    }
+
+    #[test]
+    fn test_stop_with_broker() {
+        let Some(broker) = Broker::start() else {
+            return;
+        };
+        let settings = settings(broker.port);
+
+        let (observer, mut connection) = Client::new(MqttOptions::new("observer", "127.0.0.1", broker.port), 10);
+        observer
+            .subscribe(format!("{}/+/availability", settings.topic_prefix), QoS::AtLeastOnce)
+            .unwrap();
+        let (messages, availability) = channel();
+        thread::spawn(move || {
+            for event in connection.iter() {
+                if let Ok(Event::Incoming(Packet::Publish(publish))) = event
+                    && messages.send(String::from_utf8_lossy(&publish.payload).to_string()).is_err()
+                {
+                    break;
+                }
+            }
+        });
+
+        let (client, requests) = MqttClient::start(&settings, || {}).unwrap();
+        let timeout = Duration::from_secs(5);
+        assert_eq!(Ok("online".to_string()), availability.recv_timeout(timeout));
+
+        client.stop();
+        assert_eq!(Ok("offline".to_string()), availability.recv_timeout(timeout));
+        assert_eq!(Err(RecvTimeoutError::Disconnected), requests.recv_timeout(timeout).map(|_| ()));
+        /* a client still running would reconnect and come back online */
+        assert_eq!(Err(RecvTimeoutError::Timeout), availability.recv_timeout(Duration::from_secs(2)));
+    }
+}
@@ -0,0 +1,166 @@
+use crate::audio::PauseReason;
+use std::collections::{HashMap, VecDeque};
+use std::mem::{discriminant, Discriminant};
+use std::time::{Duration, Instant};
+
+/* the same kind of notice is shown at most once in this time */
+const NOTICE_INTERVAL: Duration = Duration::from_secs(5 * 60);
+/* and no more than BURST_SIZE notices of any kind in BURST_WINDOW */
+const BURST_WINDOW: Duration = Duration::from_secs(60);
+const BURST_SIZE: usize = 3;
+
+/// Event worth telling the user about. Front-ends render it with their own strings.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Notice {
+    DeviceLost(String),
+    DeviceRecovered,
+    /// Pings have failed this many times in a row.
+    KeepsFailing(u32),
+    Paused(PauseReason),
+    Resumed,
+    /// The audio output crashed and has been restarted.
+    AudioRestarted,
+}
+
+/// Keeps a flapping device or a pause toggling every minute from flooding the desktop.
+#[derive(Default)]
+pub struct RateLimiter {
+    last: HashMap<Discriminant<Notice>, Instant>,
+    recent: VecDeque<Instant>,
+}
+
+impl RateLimiter {
+    /// Tells whether the notice may be shown at `now` and counts it if so.
+    pub fn allow(&mut self, notice: &Notice, now: Instant) -> bool {
+        while let Some(shown) = self.recent.front()
+            && now.duration_since(*shown) >= BURST_WINDOW
+        {
+            self.recent.pop_front();
+        }
+        if self.recent.len() >= BURST_SIZE {
+            return false;
+        }
+
+        let kind = discriminant(notice);
+        if let Some(shown) = self.last.get(&kind)
+            && now.duration_since(*shown) < NOTICE_INTERVAL
+        {
+            return false;
+        }
+
+        self.last.insert(kind, now);
+        self.recent.push_back(now);
+        true
+    }
+}
+
+#[cfg(target_os = "linux")]
+pub use linux_notifications::DesktopNotifier;
+
+#[cfg(target_os = "linux")]
+mod linux_notifications {
+    use crate::audio::PauseReason;
+    use crate::notifications::Notice;
+    use log::debug;
+    use std::collections::HashMap;
+    use zbus::blocking::Connection;
+    use zbus::zvariant::Value;
+
+    /* the same strings as in the Windows resources */
+    const APP_TITLE: &str = "KeepAuAwake";
+    const DEVICE_LOST: &str = "Lost the audio device";
+    const DEVICE_RECOVERED: &str = "The audio device is back";
+    const KEEPS_FAILING: &str = "Pings keep failing";
+    const AUDIO_RESTARTED: &str = "Audio output crashed and was restarted";
+    const PAUSED: &str = "Paused";
+    const KEEPING_AUDIO_DEVICE_AWAKE: &str = "Keeping audio device awake";
+    const REASON_USER: &str = "by the user";
+    const REASON_USER_IDLE: &str = "no user input";
+    const REASON_SESSION_LOCKED: &str = "session locked";
+    const REASON_ON_BATTERY: &str = "on battery";
+    const REASON_SNOOZE: &str = "snoozed";
+
+    const ICON: &str = "audio-speakers";
+    /* let the server decide */
+    const DEFAULT_TIMEOUT: i32 = -1;
+
+    /// Notifications through the freedesktop notification server.
+    pub struct DesktopNotifier {
+        connection: Connection,
+    }
+
+    impl DesktopNotifier {
+        pub fn new() -> Result<Self, String> {
+            let connection =
+                Connection::session().map_err(|e| format!("Failed to connect to the session bus. {}", e))?;
+            Ok(Self { connection })
+        }
+
+        pub fn show(&self, notice: &Notice) -> Result<(), String> {
+            debug!("Notification: {:?}", notice);
+
+            let body = notice_text(notice);
+            let hints: HashMap<&str, Value> = HashMap::new();
+            self.connection
+                .call_method(
+                    Some("org.freedesktop.Notifications"),
+                    "/org/freedesktop/Notifications",
+                    Some("org.freedesktop.Notifications"),
+                    "Notify",
+                    &(APP_TITLE, 0u32, ICON, APP_TITLE, body, Vec::<&str>::new(), hints, DEFAULT_TIMEOUT),
+                )
+                .map(|_| ())
+                .map_err(|e| format!("Failed to show notification. {}", e))
+        }
+    }
+
+    fn notice_text(notice: &Notice) -> String {
+        match notice {
+            Notice::DeviceLost(error) => format!("{}\n{}", DEVICE_LOST, error),
+            Notice::DeviceRecovered => DEVICE_RECOVERED.to_string(),
+            Notice::KeepsFailing(count) => format!("{} ({})", KEEPS_FAILING, count),
+            Notice::Paused(reason) => format!("{} ({})", PAUSED, reason_text(*reason)),
+            Notice::Resumed => KEEPING_AUDIO_DEVICE_AWAKE.to_string(),
+            Notice::AudioRestarted => AUDIO_RESTARTED.to_string(),
+        }
+    }
+
+    fn reason_text(reason: PauseReason) -> &'static str {
+        match reason {
+            PauseReason::User => REASON_USER,
+            PauseReason::UserIdle => REASON_USER_IDLE,
+            PauseReason::SessionLocked => REASON_SESSION_LOCKED,
+            PauseReason::OnBattery => REASON_ON_BATTERY,
+            PauseReason::Snooze => REASON_SNOOZE,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::audio::PauseReason;
+    use crate::notifications::{Notice, RateLimiter, BURST_WINDOW, NOTICE_INTERVAL};
+    use std::time::{Duration, Instant};
+
+    #[test]
+    fn test_same_notice() {
+        let mut limiter = RateLimiter::default();
+        let start = Instant::now();
+
+        assert!(limiter.allow(&Notice::DeviceRecovered, start));
+        assert!(!limiter.allow(&Notice::DeviceRecovered, start + Duration::from_secs(10)));
+        assert!(limiter.allow(&Notice::DeviceRecovered, start + NOTICE_INTERVAL));
+    }
+
+    #[test]
+    fn test_burst() {
+        let mut limiter = RateLimiter::default();
+        let start = Instant::now();
+
+        assert!(limiter.allow(&Notice::DeviceLost("Gone".to_string()), start));
+        assert!(limiter.allow(&Notice::KeepsFailing(5), start));
+        assert!(limiter.allow(&Notice::Paused(PauseReason::UserIdle), start));
+        assert!(!limiter.allow(&Notice::Resumed, start));
+        assert!(limiter.allow(&Notice::Resumed, start + BURST_WINDOW));
+    }
+}
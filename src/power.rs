@@ -0,0 +1,230 @@
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum PowerEvent {
+    Suspend,
+    Resume,
+}
+
+#[cfg(windows)]
+pub use windows_power::{power_event, PowerNotifications};
+
+#[cfg(target_os = "linux")]
+pub use linux_power::{watch_sleep, SleepInhibitor};
+
+#[cfg(windows)]
+mod windows_power {
+    use crate::power::PowerEvent;
+    use log::warn;
+    use windows::Win32::Foundation::{HANDLE, HWND};
+    use windows::Win32::System::Power::{
+        RegisterSuspendResumeNotification, UnregisterSuspendResumeNotification, HPOWERNOTIFY,
+    };
+    use windows::Win32::UI::WindowsAndMessaging::{
+        DEVICE_NOTIFY_WINDOW_HANDLE, PBT_APMRESUMEAUTOMATIC, PBT_APMSUSPEND, WM_POWERBROADCAST,
+    };
+
+    /// Subscription of a window to system suspend and resume notifications.
+    /// Message-only windows do not receive `WM_POWERBROADCAST` without it.
+    #[derive(Default)]
+    pub struct PowerNotifications {
+        handle: Option<HPOWERNOTIFY>,
+    }
+
+    impl PowerNotifications {
+        pub fn register(&mut self, hwnd: Option<HWND>) -> Result<(), String> {
+            let hwnd = hwnd.ok_or("No window to receive power notifications")?;
+            let handle = unsafe {
+                RegisterSuspendResumeNotification(HANDLE(hwnd.0), DEVICE_NOTIFY_WINDOW_HANDLE)
+            }
+            .map_err(|e| format!("Failed to register for power notifications. {}", e.message()))?;
+
+            self.handle = Some(handle);
+            Ok(())
+        }
+
+        pub fn unregister(&mut self) {
+            if let Some(handle) = self.handle.take() {
+                unsafe { UnregisterSuspendResumeNotification(handle) }.unwrap_or_else(|e| {
+                    warn!("Failed to unregister power notifications. {}", e.message());
+                });
+            }
+        }
+    }
+
+    /// Translates a window message into a power event.
+    pub fn power_event(msg: u32, wparam: usize) -> Option<PowerEvent> {
+        if msg != WM_POWERBROADCAST {
+            return None;
+        }
+
+        /* PBT_APMRESUMESUSPEND follows only user-triggered wake-ups while
+        PBT_APMRESUMEAUTOMATIC is sent on every resume */
+        match wparam as u32 {
+            PBT_APMSUSPEND => Some(PowerEvent::Suspend),
+            PBT_APMRESUMEAUTOMATIC => Some(PowerEvent::Resume),
+            _ => None,
+        }
+    }
+}
+
+#[cfg(target_os = "linux")]
+mod linux_power {
+    use crate::logind::{self, MANAGER_PATH};
+    use crate::power::PowerEvent;
+    use log::warn;
+    use std::sync::mpsc::Receiver;
+    use zbus::blocking::Connection;
+    use zbus::zvariant::OwnedFd;
+
+    /// Delay lock that holds off suspend until the audio device is closed. `PrepareForSleep` is
+    /// only advisory without it. The lock is held while the system is awake, released once the
+    /// suspend is handled and taken again on resume.
+    pub struct SleepInhibitor {
+        connection: Connection,
+        pub(super) lock: Option<OwnedFd>,
+    }
+
+    impl SleepInhibitor {
+        pub fn new(connection: &Connection) -> Self {
+            let mut inhibitor = Self {
+                connection: connection.clone(),
+                lock: None,
+            };
+            inhibitor.take();
+            inhibitor
+        }
+
+        pub fn take(&mut self) {
+            if self.lock.is_some() {
+                return;
+            }
+
+            let lock = logind::manager_proxy(&self.connection).and_then(|manager| {
+                manager.call("Inhibit", &("sleep", "keep-audio-awake", "Close the audio device", "delay"))
+            });
+            match lock {
+                Ok(lock) => self.lock = Some(lock),
+                Err(e) => warn!("Failed to delay suspend. {}", e),
+            }
+        }
+
+        /// Lets the system go to sleep.
+        pub fn release(&mut self) {
+            /* logind drops the lock once the descriptor is closed */
+            self.lock = None;
+        }
+    }
+
+    /// Reports suspend and resume from logind's `PrepareForSleep` signal.
+    pub fn watch_sleep<W>(connection: &Connection, wake: W) -> Result<Receiver<PowerEvent>, String>
+    where
+        W: Fn() + Send + 'static,
+    {
+        logind::watch(
+            connection,
+            MANAGER_PATH,
+            |member, message| match member {
+                /* true before sleeping, false after waking up */
+                "PrepareForSleep" => message.body().deserialize::<bool>().ok().map(|start| {
+                    if start {
+                        PowerEvent::Suspend
+                    } else {
+                        PowerEvent::Resume
+                    }
+                }),
+                _ => None,
+            },
+            wake,
+        )
+    }
+}
+
+#[cfg(all(test, target_os = "linux"))]
+mod tests {
+    use crate::dbus::tests::Bus;
+    use crate::logind::{MANAGER_INTERFACE, MANAGER_PATH};
+    use crate::power::{watch_sleep, PowerEvent, SleepInhibitor};
+    use std::fs::File;
+    use std::sync::{Arc, Mutex};
+    use std::time::Duration;
+    use zbus::blocking::connection::Builder;
+    use zbus::interface;
+    use zbus::zvariant::OwnedFd;
+
+    /// Manager of a fake logind that records the locks asked for.
+    struct Manager {
+        inhibits: Arc<Mutex<Vec<(String, String)>>>,
+    }
+
+    #[interface(name = "org.freedesktop.login1.Manager")]
+    impl Manager {
+        fn inhibit(&self, what: &str, _who: &str, _why: &str, mode: &str) -> OwnedFd {
+            self.inhibits.lock().unwrap().push((what.to_string(), mode.to_string()));
+            std::os::fd::OwnedFd::from(File::open("/dev/null").unwrap()).into()
+        }
+    }
+
+    #[test]
+    fn test_sleep_inhibitor() {
+        let Some(bus) = Bus::start() else {
+            return;
+        };
+        let inhibits = Arc::new(Mutex::new(Vec::new()));
+        let _logind = Builder::address(bus.address.as_str())
+            .and_then(|builder| builder.name("org.freedesktop.login1"))
+            .and_then(|builder| {
+                builder.serve_at(
+                    MANAGER_PATH,
+                    Manager {
+                        inhibits: inhibits.clone(),
+                    },
+                )
+            })
+            .and_then(|builder| builder.build())
+            .unwrap();
+        let connection = Builder::address(bus.address.as_str()).unwrap().build().unwrap();
+
+        let mut inhibitor = SleepInhibitor::new(&connection);
+        assert_eq!(vec![("sleep".to_string(), "delay".to_string())], *inhibits.lock().unwrap());
+        /* held already */
+        inhibitor.take();
+        assert_eq!(1, inhibits.lock().unwrap().len());
+
+        inhibitor.release();
+        assert!(inhibitor.lock.is_none());
+        inhibitor.take();
+        assert_eq!(2, inhibits.lock().unwrap().len());
+        assert!(inhibitor.lock.is_some());
+    }
+
+    #[test]
+    fn test_watch_sleep() {
+        let Some(bus) = Bus::start() else {
+            return;
+        };
+        let logind = Builder::address(bus.address.as_str())
+            .and_then(|builder| builder.name("org.freedesktop.login1"))
+            .and_then(|builder| builder.build())
+            .unwrap();
+        let connection = Builder::address(bus.address.as_str()).unwrap().build().unwrap();
+        let events = watch_sleep(&connection, || {}).unwrap();
+
+        /* only logind is listened to */
+        connection
+            .emit_signal(None::<&str>, MANAGER_PATH, MANAGER_INTERFACE, "PrepareForSleep", &(false,))
+            .unwrap();
+        for start in [true, false] {
+            logind
+                .emit_signal(None::<&str>, MANAGER_PATH, MANAGER_INTERFACE, "PrepareForSleep", &(start,))
+                .unwrap();
+        }
+        /* not a sleep signal */
+        logind
+            .emit_signal(None::<&str>, MANAGER_PATH, MANAGER_INTERFACE, "SessionNew", &("2", "/session/2"))
+            .unwrap();
+
+        let timeout = Duration::from_secs(5);
+        assert_eq!(Ok(PowerEvent::Suspend), events.recv_timeout(timeout));
+        assert_eq!(Ok(PowerEvent::Resume), events.recv_timeout(timeout));
+        assert!(events.recv_timeout(Duration::from_millis(200)).is_err());
+    }
+}
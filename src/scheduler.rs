@@ -0,0 +1,103 @@
+use crate::settings::Settings;
+use log::{debug, trace};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// Computes delays between keep-alive pings.
+pub struct Scheduler {
+    period_ms: u32,
+    jitter_percent: u32,
+    random: Random,
+}
+
+impl Default for Scheduler {
+    fn default() -> Self {
+        Self::new(&Settings::default())
+    }
+}
+
+impl Scheduler {
+    pub fn new(settings: &Settings) -> Self {
+        Self {
+            period_ms: settings.period_ms,
+            jitter_percent: settings.jitter_percent,
+            random: Random::new(),
+        }
+    }
+
+    pub fn period_ms(&self) -> u32 {
+        self.period_ms
+    }
+
+    pub fn set_period_ms(&mut self, period_ms: u32) {
+        debug!(event = "period_changed", period_ms, previous_period_ms = self.period_ms; "Changing period");
+        self.period_ms = period_ms;
+    }
+
+    /// Returns the delay before the next ping randomly shifted by up to the jitter percentage.
+    pub fn next_delay_ms(&mut self) -> u32 {
+        let delay_ms = if self.jitter_percent == 0 {
+            self.period_ms
+        } else {
+            let spread = self.period_ms as u64 * self.jitter_percent as u64 / 100;
+            let offset = self.random.next() % (2 * spread + 1);
+            (self.period_ms as u64 - spread + offset) as u32
+        };
+
+        trace!(event = "ping_scheduled", period_ms = self.period_ms, delay_ms; "Next ping scheduled");
+        delay_ms
+    }
+}
+
+/// Xorshift generator. Jitter does not need anything stronger.
+struct Random(u64);
+
+impl Random {
+    fn new() -> Self {
+        let seed = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|d| d.as_nanos() as u64)
+            .unwrap_or_default();
+
+        Self(seed | 1)
+    }
+
+    fn next(&mut self) -> u64 {
+        let mut x = self.0;
+        x ^= x << 13;
+        x ^= x >> 7;
+        x ^= x << 17;
+        self.0 = x;
+        x
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::scheduler::Scheduler;
+    use crate::settings::Settings;
+
+    #[test]
+    fn test_no_jitter() {
+        let settings = Settings {
+            period_ms: 5000,
+            ..Settings::default()
+        };
+        let mut scheduler = Scheduler::new(&settings);
+        assert_eq!(5000, scheduler.next_delay_ms());
+        assert_eq!(5000, scheduler.next_delay_ms());
+    }
+
+    #[test]
+    fn test_jitter() {
+        let settings = Settings {
+            period_ms: 5000,
+            jitter_percent: 10,
+            ..Settings::default()
+        };
+        let mut scheduler = Scheduler::new(&settings);
+        for _ in 0..1000 {
+            let delay = scheduler.next_delay_ms();
+            assert!((4500..=5500).contains(&delay), "{}", delay);
+        }
+    }
+}
@@ -0,0 +1,509 @@
+use crate::ipc::{Command, Response};
+use log::{debug, warn};
+
+/// State of the service as the service manager sees it.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ServiceState {
+    StartPending,
+    Running,
+    PausePending,
+    Paused,
+    ContinuePending,
+    StopPending,
+    Stopped,
+}
+
+/// Request of the service manager.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ServiceControl {
+    Stop,
+    /// The system is shutting down.
+    Shutdown,
+    Pause,
+    Continue,
+    /// Report the current state again.
+    Interrogate,
+}
+
+/// Tells the service manager about state changes. `exit_code` is only meaningful when stopped.
+pub trait StatusReporter {
+    fn report(&mut self, state: ServiceState, exit_code: u32);
+}
+
+/// Turns service manager requests into commands for the front-end and keeps the reported state
+/// in step with their outcome.
+pub struct ServiceController<R: StatusReporter> {
+    reporter: R,
+    state: ServiceState,
+}
+
+impl<R: StatusReporter> ServiceController<R> {
+    pub fn new(mut reporter: R) -> Self {
+        reporter.report(ServiceState::StartPending, 0);
+        Self {
+            reporter,
+            state: ServiceState::StartPending,
+        }
+    }
+
+    pub fn started(&mut self) {
+        if self.state == ServiceState::StartPending {
+            self.set_state(ServiceState::Running);
+        }
+    }
+
+    /// Returns the command to carry out the request, if it takes one. Its response goes to
+    /// [`Self::completed`].
+    pub fn control(&mut self, control: ServiceControl) -> Option<Command> {
+        match (control, self.state) {
+            (ServiceControl::Interrogate, state) => {
+                self.reporter.report(state, 0);
+                None
+            }
+            (ServiceControl::Stop | ServiceControl::Shutdown, ServiceState::StopPending | ServiceState::Stopped) => {
+                None
+            }
+            (ServiceControl::Stop | ServiceControl::Shutdown, _) => {
+                self.set_state(ServiceState::StopPending);
+                Some(Command::Stop)
+            }
+            (ServiceControl::Pause, ServiceState::Running) => {
+                self.set_state(ServiceState::PausePending);
+                Some(Command::Pause)
+            }
+            (ServiceControl::Continue, ServiceState::Paused) => {
+                self.set_state(ServiceState::ContinuePending);
+                Some(Command::Resume)
+            }
+            (control, state) => {
+                debug!("Ignoring {:?} while {:?}", control, state);
+                None
+            }
+        }
+    }
+
+    pub fn completed(&mut self, response: &Response) {
+        let succeeded = match response {
+            Response::Ok(_) => true,
+            Response::Error(e) => {
+                warn!("Service control failed. {}", e);
+                false
+            }
+        };
+
+        let state = match (self.state, succeeded) {
+            (ServiceState::PausePending, true) | (ServiceState::ContinuePending, false) => ServiceState::Paused,
+            (ServiceState::ContinuePending, true) | (ServiceState::PausePending, false) => ServiceState::Running,
+            /* a stopped front-end is reported by `stopped` */
+            (ServiceState::StopPending, false) => ServiceState::Running,
+            (state, _) => state,
+        };
+        if state != self.state {
+            self.set_state(state);
+        }
+    }
+
+    /// The front-end has exited.
+    pub fn stopped(&mut self, result: &Result<(), String>) {
+        self.state = ServiceState::Stopped;
+        match result {
+            Ok(()) => self.reporter.report(ServiceState::Stopped, 0),
+            Err(e) => {
+                warn!("{}", e);
+                self.reporter.report(ServiceState::Stopped, 1);
+            }
+        }
+    }
+
+    fn set_state(&mut self, state: ServiceState) {
+        debug!("Service state {:?}", state);
+        self.state = state;
+        self.reporter.report(state, 0);
+    }
+}
+
+#[cfg(windows)]
+pub use windows_service::{install, run_dispatcher, start, stop, uninstall, RUN_ARG};
+
+#[cfg(windows)]
+mod windows_service {
+    use crate::headless;
+    use crate::ipc::{Command, Request, Response};
+    use crate::service::{ServiceControl, ServiceController, ServiceState, StatusReporter};
+    use crate::settings::{settings_path, CONFIG_ARG_PREFIX};
+    use log::warn;
+    use std::ffi::c_void;
+    use std::path::PathBuf;
+    use std::sync::mpsc::{channel, Sender};
+    use std::sync::{Mutex, MutexGuard, OnceLock, PoisonError};
+    use std::time::Duration;
+    use windows::core::{HSTRING, PCWSTR, PWSTR};
+    use windows::Win32::Foundation::{ERROR_CALL_NOT_IMPLEMENTED, ERROR_SERVICE_SPECIFIC_ERROR, NO_ERROR};
+    use windows::Win32::System::Services::{
+        ChangeServiceConfig2W, CloseServiceHandle, ControlService, CreateServiceW, DeleteService, OpenSCManagerW,
+        OpenServiceW, RegisterServiceCtrlHandlerExW, SetServiceStatus, StartServiceCtrlDispatcherW, StartServiceW,
+        SC_ACTION, SC_ACTION_RESTART, SC_HANDLE, SC_MANAGER_CONNECT, SC_MANAGER_CREATE_SERVICE,
+        SERVICE_ACCEPT_PAUSE_CONTINUE, SERVICE_ACCEPT_SHUTDOWN, SERVICE_ACCEPT_STOP, SERVICE_ALL_ACCESS,
+        SERVICE_AUTO_START, SERVICE_CONFIG_FAILURE_ACTIONS, SERVICE_CONFIG_FAILURE_ACTIONS_FLAG,
+        SERVICE_CONTINUE_PENDING, SERVICE_CONTROL_CONTINUE, SERVICE_CONTROL_INTERROGATE, SERVICE_CONTROL_PAUSE,
+        SERVICE_CONTROL_SHUTDOWN, SERVICE_CONTROL_STOP, SERVICE_ERROR_NORMAL, SERVICE_FAILURE_ACTIONSW,
+        SERVICE_FAILURE_ACTIONS_FLAG, SERVICE_PAUSED, SERVICE_PAUSE_PENDING, SERVICE_RUNNING, SERVICE_START,
+        SERVICE_START_PENDING, SERVICE_STATUS, SERVICE_STATUS_HANDLE, SERVICE_STOP, SERVICE_STOPPED,
+        SERVICE_STOP_PENDING, SERVICE_TABLE_ENTRYW, SERVICE_WIN32_OWN_PROCESS,
+    };
+
+    const SERVICE_NAME: &str = "KeepAudioAwake";
+    /* the same string as in the Windows resources */
+    const DISPLAY_NAME: &str = "KeepAuAwake";
+    /// Argument the service manager starts the executable with.
+    pub const RUN_ARG: &str = "run";
+    const PENDING_WAIT_HINT_MS: u32 = 10_000;
+    /* the service manager waits up to 30 s for a control handler */
+    const CONTROL_TIMEOUT: Duration = Duration::from_secs(20);
+    /* the service is restarted this long after it fails, up to three times a day */
+    const RESTART_DELAY_MS: u32 = 60_000;
+    const FAILURE_RESET_SECS: u32 = 24 * 60 * 60;
+
+    static SERVICE: OnceLock<Service> = OnceLock::new();
+
+    struct Service {
+        controller: Mutex<ServiceController<ScmReporter>>,
+        front_end: Mutex<Option<FrontEnd>>,
+    }
+
+    /// Where control requests go while the front-end runs.
+    struct FrontEnd {
+        requests: Sender<Request>,
+        wake: Box<dyn Fn() + Send + Sync>,
+    }
+
+    impl Service {
+        fn controller(&self) -> MutexGuard<'_, ServiceController<ScmReporter>> {
+            self.controller.lock().unwrap_or_else(PoisonError::into_inner)
+        }
+
+        fn execute(&self, command: Command) -> Response {
+            let (request, response) = Request::new(command);
+            match self.front_end.lock().unwrap_or_else(PoisonError::into_inner).as_ref() {
+                Some(front_end) if front_end.requests.send(request).is_ok() => (front_end.wake)(),
+                _ => return Response::Error("Application is not running".to_string()),
+            }
+            response
+                .recv_timeout(CONTROL_TIMEOUT)
+                .unwrap_or(Response::Error("No response from the application".to_string()))
+        }
+    }
+
+    struct ScmReporter {
+        handle: SERVICE_STATUS_HANDLE,
+        checkpoint: u32,
+    }
+
+    /* the status handle may be used from any thread */
+    unsafe impl Send for ScmReporter {}
+
+    impl StatusReporter for ScmReporter {
+        fn report(&mut self, state: ServiceState, exit_code: u32) {
+            let (current_state, pending) = match state {
+                ServiceState::StartPending => (SERVICE_START_PENDING, true),
+                ServiceState::Running => (SERVICE_RUNNING, false),
+                ServiceState::PausePending => (SERVICE_PAUSE_PENDING, true),
+                ServiceState::Paused => (SERVICE_PAUSED, false),
+                ServiceState::ContinuePending => (SERVICE_CONTINUE_PENDING, true),
+                ServiceState::StopPending => (SERVICE_STOP_PENDING, true),
+                ServiceState::Stopped => (SERVICE_STOPPED, false),
+            };
+            self.checkpoint = if pending { self.checkpoint + 1 } else { 0 };
+
+            let status = SERVICE_STATUS {
+                dwServiceType: SERVICE_WIN32_OWN_PROCESS,
+                dwCurrentState: current_state,
+                dwControlsAccepted: match state {
+                    ServiceState::Running | ServiceState::Paused => {
+                        SERVICE_ACCEPT_STOP | SERVICE_ACCEPT_SHUTDOWN | SERVICE_ACCEPT_PAUSE_CONTINUE
+                    }
+                    _ => 0,
+                },
+                dwWin32ExitCode: if exit_code == 0 {
+                    NO_ERROR.0
+                } else {
+                    ERROR_SERVICE_SPECIFIC_ERROR.0
+                },
+                dwServiceSpecificExitCode: exit_code,
+                dwCheckPoint: self.checkpoint,
+                dwWaitHint: if pending { PENDING_WAIT_HINT_MS } else { 0 },
+            };
+            unsafe { SetServiceStatus(self.handle, &status) }
+                .unwrap_or_else(|e| warn!("Failed to report service status. {}", e));
+        }
+    }
+
+    /// Service manager handle closed on drop.
+    struct ScHandle(SC_HANDLE);
+
+    impl Drop for ScHandle {
+        fn drop(&mut self) {
+            unsafe { CloseServiceHandle(self.0) }.unwrap_or_else(|e| warn!("{}", e));
+        }
+    }
+
+    /// Hands the main thread over to the service manager until the service stops.
+    pub fn run_dispatcher() -> Result<(), String> {
+        let mut name: Vec<u16> = SERVICE_NAME.encode_utf16().chain([0]).collect();
+        let table = [
+            SERVICE_TABLE_ENTRYW {
+                lpServiceName: PWSTR(name.as_mut_ptr()),
+                lpServiceProc: Some(service_main),
+            },
+            SERVICE_TABLE_ENTRYW::default(),
+        ];
+        unsafe { StartServiceCtrlDispatcherW(table.as_ptr()) }
+            .map_err(|e| format!("Failed to connect to the service manager. {}", e))
+    }
+
+    unsafe extern "system" fn service_main(_argc: u32, _argv: *mut PWSTR) {
+        let handle = match unsafe {
+            RegisterServiceCtrlHandlerExW(&HSTRING::from(SERVICE_NAME), Some(control_handler), None)
+        } {
+            Ok(handle) => handle,
+            Err(e) => {
+                warn!("Failed to register service control handler. {}", e);
+                return;
+            }
+        };
+
+        let service = SERVICE.get_or_init(|| Service {
+            controller: Mutex::new(ServiceController::new(ScmReporter { handle, checkpoint: 0 })),
+            front_end: Mutex::new(None),
+        });
+
+        let result = headless::run_service(|wake| {
+            let (requests, receiver) = channel();
+            service
+                .front_end
+                .lock()
+                .unwrap_or_else(PoisonError::into_inner)
+                .replace(FrontEnd { requests, wake });
+            service.controller().started();
+            receiver
+        });
+        service.front_end.lock().unwrap_or_else(PoisonError::into_inner).take();
+        service.controller().stopped(&result);
+    }
+
+    unsafe extern "system" fn control_handler(
+        control: u32,
+        _event_type: u32,
+        _event_data: *mut c_void,
+        _context: *mut c_void,
+    ) -> u32 {
+        let control = match control {
+            SERVICE_CONTROL_STOP => ServiceControl::Stop,
+            SERVICE_CONTROL_SHUTDOWN => ServiceControl::Shutdown,
+            SERVICE_CONTROL_PAUSE => ServiceControl::Pause,
+            SERVICE_CONTROL_CONTINUE => ServiceControl::Continue,
+            SERVICE_CONTROL_INTERROGATE => ServiceControl::Interrogate,
+            _ => return ERROR_CALL_NOT_IMPLEMENTED.0,
+        };
+        let Some(service) = SERVICE.get() else {
+            return ERROR_CALL_NOT_IMPLEMENTED.0;
+        };
+
+        /* the lock is not held while the front-end works on the command */
+        let command = service.controller().control(control);
+        if let Some(command) = command {
+            let response = service.execute(command);
+            service.controller().completed(&response);
+        }
+        NO_ERROR.0
+    }
+
+    /// Registers the current executable as a service started with the system. Returns the
+    /// settings file the service reads.
+    pub fn install() -> Result<PathBuf, String> {
+        let exe = std::env::current_exe().map_err(|e| format!("Failed to get executable path. {}", e))?;
+        /* LocalSystem has a profile of its own, so the service reads the settings of the installer */
+        let config = settings_path().and_then(|path| {
+            std::path::absolute(&path).map_err(|e| format!("Failed to resolve {}. {}", path.display(), e))
+        })?;
+        let command = format!(
+            "\"{}\" {}\"{}\" {} {}",
+            exe.display(),
+            CONFIG_ARG_PREFIX,
+            config.display(),
+            crate::SERVICE_COMMAND,
+            RUN_ARG
+        );
+
+        let manager = open_manager(SC_MANAGER_CREATE_SERVICE)?;
+        let service = unsafe {
+            CreateServiceW(
+                manager.0,
+                &HSTRING::from(SERVICE_NAME),
+                &HSTRING::from(DISPLAY_NAME),
+                SERVICE_ALL_ACCESS,
+                SERVICE_WIN32_OWN_PROCESS,
+                SERVICE_AUTO_START,
+                SERVICE_ERROR_NORMAL,
+                &HSTRING::from(command),
+                PCWSTR::null(),
+                None,
+                PCWSTR::null(),
+                PCWSTR::null(),
+                PCWSTR::null(),
+            )
+        }
+        .map(ScHandle)
+        .map_err(|e| format!("Failed to install the service. {}", e))?;
+
+        set_failure_actions(&service)?;
+
+        Ok(config)
+    }
+
+    /// Makes the service manager restart the service when it crashes or exits with an error,
+    /// which is how the application gives up on a crash loop.
+    fn set_failure_actions(service: &ScHandle) -> Result<(), String> {
+        let mut actions = [SC_ACTION {
+            Type: SC_ACTION_RESTART,
+            Delay: RESTART_DELAY_MS,
+        }; 3];
+        let failure_actions = SERVICE_FAILURE_ACTIONSW {
+            dwResetPeriod: FAILURE_RESET_SECS,
+            cActions: actions.len() as u32,
+            lpsaActions: actions.as_mut_ptr(),
+            ..Default::default()
+        };
+        let flag = SERVICE_FAILURE_ACTIONS_FLAG {
+            fFailureActionsOnNonCrashFailures: true.into(),
+        };
+
+        unsafe {
+            ChangeServiceConfig2W(
+                service.0,
+                SERVICE_CONFIG_FAILURE_ACTIONS,
+                Some(&failure_actions as *const _ as *const c_void),
+            )
+            .and_then(|_| {
+                ChangeServiceConfig2W(
+                    service.0,
+                    SERVICE_CONFIG_FAILURE_ACTIONS_FLAG,
+                    Some(&flag as *const _ as *const c_void),
+                )
+            })
+        }
+        .map_err(|e| format!("Failed to set the service recovery actions. {}", e))
+    }
+
+    pub fn uninstall() -> Result<(), String> {
+        let service = open_service(SERVICE_ALL_ACCESS)?;
+        unsafe { DeleteService(service.0) }.map_err(|e| format!("Failed to uninstall the service. {}", e))
+    }
+
+    pub fn start() -> Result<(), String> {
+        let service = open_service(SERVICE_START)?;
+        unsafe { StartServiceW(service.0, None) }.map_err(|e| format!("Failed to start the service. {}", e))
+    }
+
+    pub fn stop() -> Result<(), String> {
+        let service = open_service(SERVICE_STOP)?;
+        let mut status = SERVICE_STATUS::default();
+        unsafe { ControlService(service.0, SERVICE_CONTROL_STOP, &mut status) }
+            .map_err(|e| format!("Failed to stop the service. {}", e))
+    }
+
+    fn open_manager(access: u32) -> Result<ScHandle, String> {
+        unsafe { OpenSCManagerW(PCWSTR::null(), PCWSTR::null(), access) }
+            .map(ScHandle)
+            .map_err(|e| format!("Failed to open the service manager. {}", e))
+    }
+
+    fn open_service(access: u32) -> Result<ScHandle, String> {
+        let manager = open_manager(SC_MANAGER_CONNECT)?;
+        unsafe { OpenServiceW(manager.0, &HSTRING::from(SERVICE_NAME), access) }
+            .map(ScHandle)
+            .map_err(|e| format!("Failed to open the service. {}", e))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::ipc::{Command, Response};
+    use crate::service::{ServiceControl, ServiceController, ServiceState, StatusReporter};
+    use std::cell::RefCell;
+    use std::rc::Rc;
+
+    #[derive(Clone, Default)]
+    struct Reports(Rc<RefCell<Vec<(ServiceState, u32)>>>);
+
+    impl Reports {
+        fn take(&self) -> Vec<(ServiceState, u32)> {
+            self.0.take()
+        }
+    }
+
+    impl StatusReporter for Reports {
+        fn report(&mut self, state: ServiceState, exit_code: u32) {
+            self.0.borrow_mut().push((state, exit_code));
+        }
+    }
+
+    fn started() -> (ServiceController<Reports>, Reports) {
+        let reports = Reports::default();
+        let mut controller = ServiceController::new(reports.clone());
+        controller.started();
+        assert_eq!(
+            vec![(ServiceState::StartPending, 0), (ServiceState::Running, 0)],
+            reports.take()
+        );
+        (controller, reports)
+    }
+
+    #[test]
+    fn test_pause_continue() {
+        let (mut controller, reports) = started();
+
+        assert_eq!(Some(Command::Pause), controller.control(ServiceControl::Pause));
+        controller.completed(&Response::Ok(String::new()));
+        assert_eq!(
+            vec![(ServiceState::PausePending, 0), (ServiceState::Paused, 0)],
+            reports.take()
+        );
+
+        assert_eq!(None, controller.control(ServiceControl::Pause));
+        assert!(reports.take().is_empty());
+
+        assert_eq!(Some(Command::Resume), controller.control(ServiceControl::Continue));
+        controller.completed(&Response::Error("Failed".to_string()));
+        assert_eq!(
+            vec![(ServiceState::ContinuePending, 0), (ServiceState::Paused, 0)],
+            reports.take()
+        );
+    }
+
+    #[test]
+    fn test_stop() {
+        let (mut controller, reports) = started();
+
+        assert_eq!(Some(Command::Stop), controller.control(ServiceControl::Stop));
+        controller.completed(&Response::Ok(String::new()));
+        assert_eq!(None, controller.control(ServiceControl::Shutdown));
+
+        controller.stopped(&Err("Failed to start".to_string()));
+        assert_eq!(
+            vec![(ServiceState::StopPending, 0), (ServiceState::Stopped, 1)],
+            reports.take()
+        );
+
+        /* the response may come after the front-end has exited */
+        controller.completed(&Response::Ok(String::new()));
+        assert!(reports.take().is_empty());
+    }
+
+    #[test]
+    fn test_interrogate() {
+        let (mut controller, reports) = started();
+        assert_eq!(None, controller.control(ServiceControl::Interrogate));
+        assert_eq!(vec![(ServiceState::Running, 0)], reports.take());
+    }
+}
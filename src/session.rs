@@ -0,0 +1,164 @@
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum SessionEvent {
+    Locked,
+    Unlocked,
+}
+
+#[cfg(windows)]
+pub use windows_session::{session_event, SessionNotifications};
+
+#[cfg(target_os = "linux")]
+pub use linux_session::watch_lock;
+
+#[cfg(windows)]
+mod windows_session {
+    use crate::session::SessionEvent;
+    use log::warn;
+    use windows::Win32::Foundation::HWND;
+    use windows::Win32::System::RemoteDesktop::{
+        WTSRegisterSessionNotification, WTSUnRegisterSessionNotification, NOTIFY_FOR_THIS_SESSION,
+    };
+    use windows::Win32::UI::WindowsAndMessaging::{
+        WM_WTSSESSION_CHANGE, WTS_SESSION_LOCK, WTS_SESSION_UNLOCK,
+    };
+
+    /// Subscription of a window to lock and unlock notifications of the current session.
+    #[derive(Default)]
+    pub struct SessionNotifications {
+        window: Option<HWND>,
+    }
+
+    impl SessionNotifications {
+        pub fn register(&mut self, hwnd: Option<HWND>) -> Result<(), String> {
+            let hwnd = hwnd.ok_or("No window to receive session notifications")?;
+            unsafe { WTSRegisterSessionNotification(hwnd, NOTIFY_FOR_THIS_SESSION) }
+                .map_err(|e| format!("Failed to register for session notifications. {}", e.message()))?;
+
+            self.window = Some(hwnd);
+            Ok(())
+        }
+
+        pub fn unregister(&mut self) {
+            if let Some(hwnd) = self.window.take() {
+                unsafe { WTSUnRegisterSessionNotification(hwnd) }.unwrap_or_else(|e| {
+                    warn!("Failed to unregister session notifications. {}", e.message());
+                });
+            }
+        }
+    }
+
+    /// Translates a window message into a session event.
+    pub fn session_event(msg: u32, wparam: usize) -> Option<SessionEvent> {
+        if msg != WM_WTSSESSION_CHANGE {
+            return None;
+        }
+
+        match wparam as u32 {
+            WTS_SESSION_LOCK => Some(SessionEvent::Locked),
+            WTS_SESSION_UNLOCK => Some(SessionEvent::Unlocked),
+            _ => None,
+        }
+    }
+}
+
+#[cfg(target_os = "linux")]
+mod linux_session {
+    use crate::logind::{self, SESSION_INTERFACE};
+    use crate::session::SessionEvent;
+    use std::collections::HashMap;
+    use std::sync::mpsc::Receiver;
+    use zbus::blocking::Connection;
+    use zbus::zvariant::OwnedValue;
+    use zbus::Message;
+
+    /// Reports locks of the login session at `path`. logind sends `Lock` and `Unlock` to the screen
+    /// locker, which sets `LockedHint` once it is done. Some desktops only do the latter.
+    pub fn watch_lock<W>(connection: &Connection, path: &str, wake: W) -> Result<Receiver<SessionEvent>, String>
+    where
+        W: Fn() + Send + 'static,
+    {
+        logind::watch(
+            connection,
+            path,
+            |member, message| match member {
+                "Lock" => Some(SessionEvent::Locked),
+                "Unlock" => Some(SessionEvent::Unlocked),
+                "PropertiesChanged" => locked_hint(message).map(|locked| {
+                    if locked {
+                        SessionEvent::Locked
+                    } else {
+                        SessionEvent::Unlocked
+                    }
+                }),
+                _ => None,
+            },
+            wake,
+        )
+    }
+
+    fn locked_hint(message: &Message) -> Option<bool> {
+        let (interface, changed, _) = message
+            .body()
+            .deserialize::<(String, HashMap<String, OwnedValue>, Vec<String>)>()
+            .ok()?;
+        if interface != SESSION_INTERFACE {
+            return None;
+        }
+        changed.get("LockedHint")?.downcast_ref::<bool>().ok()
+    }
+}
+
+#[cfg(all(test, target_os = "linux"))]
+mod tests {
+    use crate::dbus::tests::Bus;
+    use crate::logind::SESSION_INTERFACE;
+    use crate::session::{watch_lock, SessionEvent};
+    use std::collections::HashMap;
+    use std::time::Duration;
+    use zbus::blocking::connection::Builder;
+    use zbus::zvariant::Value;
+
+    const SESSION_PATH: &str = "/org/freedesktop/login1/session/_32";
+
+    #[test]
+    fn test_watch_lock() {
+        let Some(bus) = Bus::start() else {
+            return;
+        };
+        let logind = Builder::address(bus.address.as_str())
+            .and_then(|builder| builder.name("org.freedesktop.login1"))
+            .and_then(|builder| builder.build())
+            .unwrap();
+        let connection = Builder::address(bus.address.as_str()).unwrap().build().unwrap();
+        let events = watch_lock(&connection, SESSION_PATH, || {}).unwrap();
+
+        /* another session */
+        logind
+            .emit_signal(None::<&str>, "/org/freedesktop/login1/session/_33", SESSION_INTERFACE, "Lock", &())
+            .unwrap();
+        for signal in ["Lock", "Unlock"] {
+            logind
+                .emit_signal(None::<&str>, SESSION_PATH, SESSION_INTERFACE, signal, &())
+                .unwrap();
+        }
+        for locked in [true, false] {
+            let changed = HashMap::from([("LockedHint", Value::from(locked))]);
+            logind
+                .emit_signal(
+                    None::<&str>,
+                    SESSION_PATH,
+                    "org.freedesktop.DBus.Properties",
+                    "PropertiesChanged",
+                    &(SESSION_INTERFACE, changed, Vec::<String>::new()),
+                )
+                .unwrap();
+        }
+
+        let timeout = Duration::from_secs(5);
+        for _ in 0..2 {
+            assert_eq!(Ok(SessionEvent::Locked), events.recv_timeout(timeout));
+            assert_eq!(Ok(SessionEvent::Unlocked), events.recv_timeout(timeout));
+        }
+        assert!(events.recv_timeout(Duration::from_millis(200)).is_err());
+    }
+}
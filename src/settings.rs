@@ -0,0 +1,345 @@
+use crate::hooks::Hook;
+use crate::instance::InstanceScope;
+use crate::logging::LogSettings;
+use crate::mqtt::MqttSettings;
+use log::debug;
+use serde::{Deserialize, Serialize};
+use std::fs;
+use std::path::PathBuf;
+use std::sync::OnceLock;
+
+const APP_DIR: &str = "keep-audio-awake";
+const SETTINGS_FILE: &str = "settings.toml";
+/// `--config=<path>` reads and writes the settings file at the path instead of the default one.
+pub const CONFIG_ARG_PREFIX: &str = "--config=";
+
+/* chosen on the command line before the settings are loaded */
+static CONFIG_PATH: OnceLock<PathBuf> = OnceLock::new();
+
+#[cfg(not(feature = "debug"))]
+const DEFAULT_PERIOD_MS: u32 = 5000;
+#[cfg(feature = "debug")]
+const DEFAULT_PERIOD_MS: u32 = 2000;
+
+const MIN_PERIOD_MS: u32 = 1000;
+const MAX_PERIOD_MS: u32 = 3_600_000;
+const MAX_JITTER_PERCENT: u32 = 50;
+const MAX_IDLE_TIMEOUT_MIN: u32 = 24 * 60;
+const DEFAULT_BATTERY_PERIOD_MS: u32 = 60_000;
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize, Default)]
+#[serde(rename_all = "snake_case")]
+pub enum BatteryMode {
+    /// Keep the device awake regardless of the power source.
+    #[default]
+    Ignore,
+    /// Stop keep-alive on battery.
+    Pause,
+    /// Use `battery_period_ms` on battery.
+    SlowDown,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Settings {
+    /// Keep-alive period in milliseconds.
+    pub period_ms: u32,
+    /// Random deviation of the period in percents. Zero disables jitter.
+    pub jitter_percent: u32,
+    /// Play the shortest buffer that still registers as device activity.
+    pub min_energy: bool,
+    /// Minutes without keyboard or mouse input after which keep-alive stops. Zero disables.
+    pub idle_timeout_min: u32,
+    /// Stop keep-alive while the session is locked.
+    pub pause_when_locked: bool,
+    /// What to do while running on battery.
+    pub battery_mode: BatteryMode,
+    /// Battery mode applies only below this charge. 100 applies it whenever on battery.
+    pub battery_threshold_percent: u8,
+    /// Keep-alive period on battery in the slow down mode.
+    pub battery_period_ms: u32,
+    /// Output devices to keep awake by name. Empty keeps the default device awake.
+    pub devices: Vec<String>,
+    /// Show desktop notifications for lost devices, failures and pauses.
+    pub notifications: bool,
+    /// Whether one instance may run per user or per machine.
+    pub instance_scope: InstanceScope,
+    /// Serve Prometheus metrics on this port of 127.0.0.1.
+    pub metrics_port: Option<u16>,
+    /// Broker to publish the state to and take commands from.
+    pub mqtt: Option<MqttSettings>,
+    /// Commands run on keep-alive events.
+    pub hooks: Vec<Hook>,
+    /// Log level, files and console output.
+    pub log: LogSettings,
+}
+
+impl Default for Settings {
+    fn default() -> Self {
+        Self {
+            period_ms: DEFAULT_PERIOD_MS,
+            jitter_percent: 0,
+            min_energy: false,
+            idle_timeout_min: 0,
+            pause_when_locked: false,
+            battery_mode: BatteryMode::Ignore,
+            battery_threshold_percent: 100,
+            battery_period_ms: DEFAULT_BATTERY_PERIOD_MS,
+            devices: Vec::new(),
+            notifications: true,
+            instance_scope: InstanceScope::Machine,
+            metrics_port: None,
+            mqtt: None,
+            hooks: Vec::new(),
+            log: LogSettings::default(),
+        }
+    }
+}
+
+impl Settings {
+    /// Loads settings from the settings file or returns defaults if there is no such file.
+    pub fn load() -> Result<Self, String> {
+        let path = settings_path()?;
+        if !path.exists() {
+            debug!("No settings file found. Using defaults");
+            return Ok(Self::default());
+        }
+
+        let text = fs::read_to_string(&path)
+            .map_err(|e| format!("Error reading settings file {}. {}", path.display(), e))?;
+        let settings = Self::parse(&text)?;
+
+        debug!("Settings loaded from {}", path.display());
+
+        Ok(settings)
+    }
+
+    /// Writes the settings file. Comments in the file are not preserved.
+    pub fn save(&self) -> Result<(), String> {
+        let path = settings_path()?;
+        let text = toml::to_string(self).map_err(|e| format!("Error serializing settings. {}", e))?;
+
+        if let Some(dir) = path.parent() {
+            fs::create_dir_all(dir)
+                .map_err(|e| format!("Error creating settings directory {}. {}", dir.display(), e))?;
+        }
+        fs::write(&path, text)
+            .map_err(|e| format!("Error writing settings file {}. {}", path.display(), e))?;
+
+        debug!("Settings saved to {}", path.display());
+
+        Ok(())
+    }
+
+    pub fn parse(text: &str) -> Result<Self, String> {
+        let settings: Self =
+            toml::from_str(text).map_err(|e| format!("Error parsing settings. {}", e))?;
+        settings.validate()?;
+
+        Ok(settings)
+    }
+
+    pub fn validate(&self) -> Result<(), String> {
+        if !(MIN_PERIOD_MS..=MAX_PERIOD_MS).contains(&self.period_ms) {
+            Err(format!(
+                "Period must be between {} and {} ms",
+                MIN_PERIOD_MS, MAX_PERIOD_MS
+            ))?
+        }
+        if !(MIN_PERIOD_MS..=MAX_PERIOD_MS).contains(&self.battery_period_ms) {
+            Err(format!(
+                "Battery period must be between {} and {} ms",
+                MIN_PERIOD_MS, MAX_PERIOD_MS
+            ))?
+        }
+        if self.battery_threshold_percent > 100 {
+            Err("Battery threshold must not exceed 100%")?
+        }
+        if self.metrics_port == Some(0) {
+            Err("Metrics port must not be zero")?
+        }
+        if self.jitter_percent > MAX_JITTER_PERCENT {
+            Err(format!("Jitter must not exceed {}%", MAX_JITTER_PERCENT))?
+        }
+        if self.idle_timeout_min > MAX_IDLE_TIMEOUT_MIN {
+            Err(format!(
+                "Idle timeout must not exceed {} minutes",
+                MAX_IDLE_TIMEOUT_MIN
+            ))?
+        }
+        if self.devices.iter().any(|device| device.trim().is_empty()) {
+            Err("Device name must not be empty")?
+        }
+        if let Some(mqtt) = &self.mqtt {
+            mqtt.validate()?;
+        }
+        for hook in &self.hooks {
+            if hook.command.trim().is_empty() {
+                Err(format!("Hook command for {} is empty", hook.event.as_str()))?
+            }
+            if hook.timeout_secs == 0 {
+                Err(format!("Hook timeout for {} must not be zero", hook.event.as_str()))?
+            }
+        }
+        self.log.validate()
+    }
+}
+
+#[cfg(windows)]
+pub fn config_dir() -> Result<PathBuf, String> {
+    std::env::var_os("APPDATA")
+        .map(|dir| PathBuf::from(dir).join(APP_DIR))
+        .ok_or("APPDATA environment variable is not set".to_string())
+}
+
+#[cfg(target_os = "linux")]
+pub fn config_dir() -> Result<PathBuf, String> {
+    Ok(config_home()?.join(APP_DIR))
+}
+
+/// Base directory of user configuration, `$XDG_CONFIG_HOME` or `~/.config`.
+#[cfg(target_os = "linux")]
+pub fn config_home() -> Result<PathBuf, String> {
+    std::env::var_os("XDG_CONFIG_HOME")
+        .map(PathBuf::from)
+        .or_else(|| std::env::var_os("HOME").map(|home| PathBuf::from(home).join(".config")))
+        .ok_or("Neither XDG_CONFIG_HOME nor HOME environment variable is set".to_string())
+}
+
+/// Directory of files written at run time such as logs.
+#[cfg(windows)]
+pub fn state_dir() -> Result<PathBuf, String> {
+    std::env::var_os("LOCALAPPDATA")
+        .map(|dir| PathBuf::from(dir).join(APP_DIR))
+        .ok_or("LOCALAPPDATA environment variable is not set".to_string())
+}
+
+/// Directory of files written at run time such as logs, `$XDG_STATE_HOME` or `~/.local/state`.
+#[cfg(target_os = "linux")]
+pub fn state_dir() -> Result<PathBuf, String> {
+    std::env::var_os("XDG_STATE_HOME")
+        .map(PathBuf::from)
+        .or_else(|| std::env::var_os("HOME").map(|home| PathBuf::from(home).join(".local").join("state")))
+        .map(|dir| dir.join(APP_DIR))
+        .ok_or("Neither XDG_STATE_HOME nor HOME environment variable is set".to_string())
+}
+
+pub fn settings_path() -> Result<PathBuf, String> {
+    match CONFIG_PATH.get() {
+        Some(path) => Ok(path.clone()),
+        None => Ok(config_dir()?.join(SETTINGS_FILE)),
+    }
+}
+
+/// Takes the settings file option out of `args`. The last one wins.
+pub fn take_config_arg(args: &mut Vec<String>) -> Result<Option<PathBuf>, String> {
+    let mut path = None;
+    args.retain(|arg| match arg.strip_prefix(CONFIG_ARG_PREFIX) {
+        Some(value) => {
+            path = Some(value.to_string());
+            false
+        }
+        None => true,
+    });
+
+    match path {
+        Some(path) if path.is_empty() => Err(format!("Option {} expects a path", CONFIG_ARG_PREFIX)),
+        path => Ok(path.map(PathBuf::from)),
+    }
+}
+
+/// Makes [`settings_path`] return `path`. Only the first call has an effect.
+pub fn set_config_path(path: PathBuf) {
+    CONFIG_PATH.set(path).unwrap_or_default();
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::hooks::HookEvent;
+    use crate::settings::{take_config_arg, BatteryMode, Settings};
+    use std::path::PathBuf;
+
+    #[test]
+    fn test_parse_empty() {
+        assert_eq!(Settings::default(), Settings::parse("").unwrap());
+    }
+
+    #[test]
+    fn test_parse() {
+        let settings = Settings::parse("period_ms = 10000\njitter_percent = 20\nmin_energy = true").unwrap();
+        assert_eq!(10000, settings.period_ms);
+        assert_eq!(20, settings.jitter_percent);
+        assert!(settings.min_energy);
+    }
+
+    #[test]
+    fn test_parse_battery_mode() {
+        let settings = Settings::parse("battery_mode = \"slow_down\"").unwrap();
+        assert_eq!(BatteryMode::SlowDown, settings.battery_mode);
+        assert!(Settings::parse("battery_mode = \"sleep\"").is_err());
+    }
+
+    #[test]
+    fn test_parse_hooks() {
+        let settings = Settings::parse(
+            "[[hooks]]\nevent = \"device_lost\"\ncommand = \"notify\"\n\n\
+             [[hooks]]\nevent = \"paused\"\ncommand = \"log\"\ntimeout_secs = 3",
+        )
+        .unwrap();
+        assert_eq!(2, settings.hooks.len());
+        assert_eq!(HookEvent::DeviceLost, settings.hooks[0].event);
+        assert_eq!(10, settings.hooks[0].timeout_secs);
+        assert_eq!(3, settings.hooks[1].timeout_secs);
+        assert!(Settings::parse("[[hooks]]\nevent = \"paused\"\ncommand = \"\"").is_err());
+    }
+
+    #[test]
+    fn test_parse_mqtt() {
+        let settings = Settings::parse("[mqtt]\nhost = \"broker.lan\"").unwrap();
+        let mqtt = settings.mqtt.unwrap();
+        assert_eq!("broker.lan", mqtt.host);
+        assert_eq!(1883, mqtt.port);
+        assert!(Settings::parse("[mqtt]\nport = 0").is_err());
+    }
+
+    #[test]
+    fn test_parse_log() {
+        let settings = Settings::parse("[log]\nlevel = \"debug\"\nstdout = false").unwrap();
+        assert_eq!("debug", settings.log.level);
+        assert!(!settings.log.stdout);
+        assert_eq!(7, settings.log.keep_files);
+        assert!(Settings::parse("[log]\nrotate_size_mb = 0").is_err());
+    }
+
+    #[test]
+    fn test_serialize() {
+        let settings = Settings::parse(
+            "devices = [\"Speakers\", \"Headphones\"]\nbattery_mode = \"pause\"\n\n\
+             [[hooks]]\nevent = \"paused\"\ncommand = \"log\"",
+        )
+        .unwrap();
+        assert_eq!(vec!["Speakers", "Headphones"], settings.devices);
+        assert_eq!(settings, Settings::parse(&toml::to_string(&settings).unwrap()).unwrap());
+        assert!(Settings::parse("devices = [\"\"]").is_err());
+    }
+
+    #[test]
+    fn test_validate() {
+        assert!(Settings::parse("period_ms = 10").is_err());
+        assert!(Settings::parse("jitter_percent = 90").is_err());
+        assert!(Settings::parse("idle_timeout_min = 100000").is_err());
+        assert!(Settings::parse("battery_threshold_percent = 101").is_err());
+        assert!(Settings::parse("battery_period_ms = 0").is_err());
+        assert!(Settings::parse("period_ms = \"fast\"").is_err());
+    }
+
+    #[test]
+    fn test_take_config_arg() {
+        let mut args = vec!["--config=/etc/keep-audio-awake.toml".to_string(), "status".to_string()];
+        assert_eq!(Ok(Some(PathBuf::from("/etc/keep-audio-awake.toml"))), take_config_arg(&mut args));
+        assert_eq!(vec!["status"], args);
+
+        assert_eq!(Ok(None), take_config_arg(&mut args));
+        assert!(take_config_arg(&mut vec!["--config=".to_string()]).is_err());
+    }
+}
@@ -0,0 +1,144 @@
+use crate::logging::LogSettings;
+use crate::settings::{BatteryMode, Settings};
+
+/// Contents of the settings window. Numbers are kept as typed so that the window can be filled
+/// back with invalid input.
+#[derive(Debug, Clone, PartialEq)]
+pub struct SettingsForm {
+    pub period_ms: String,
+    pub jitter_percent: String,
+    pub min_energy: bool,
+    /// Known devices and whether each one is selected. Nothing selected is the default device.
+    pub devices: Vec<(String, bool)>,
+    pub idle_timeout_min: String,
+    pub pause_when_locked: bool,
+    pub battery_mode: BatteryMode,
+    pub battery_threshold_percent: String,
+    pub battery_period_ms: String,
+    pub notifications: bool,
+    /// Not a setting: the application is started at login by the system.
+    pub autostart: bool,
+    pub log_level: String,
+}
+
+impl SettingsForm {
+    #[cfg(windows)]
+    pub const BATTERY_MODES: [BatteryMode; 3] = [BatteryMode::Ignore, BatteryMode::Pause, BatteryMode::SlowDown];
+
+    /// `connected` devices are listed first, then the selected ones that are not connected.
+    pub fn new(settings: &Settings, connected: &[String], autostart: bool) -> Self {
+        let missing = settings.devices.iter().filter(|name| !connected.contains(name));
+        let devices = connected
+            .iter()
+            .chain(missing)
+            .map(|name| (name.clone(), settings.devices.contains(name)))
+            .collect();
+
+        Self {
+            period_ms: settings.period_ms.to_string(),
+            jitter_percent: settings.jitter_percent.to_string(),
+            min_energy: settings.min_energy,
+            devices,
+            idle_timeout_min: settings.idle_timeout_min.to_string(),
+            pause_when_locked: settings.pause_when_locked,
+            battery_mode: settings.battery_mode,
+            battery_threshold_percent: settings.battery_threshold_percent.to_string(),
+            battery_period_ms: settings.battery_period_ms.to_string(),
+            notifications: settings.notifications,
+            autostart,
+            log_level: settings.log.level.clone(),
+        }
+    }
+
+    /// Applies the form to `settings`. Settings that are not in the form are kept. Fails with
+    /// the same rules as the settings file. `autostart` is left to the caller.
+    pub fn apply(&self, settings: &Settings) -> Result<Settings, String> {
+        let settings = Settings {
+            period_ms: number(&self.period_ms, "Period")?,
+            jitter_percent: number(&self.jitter_percent, "Jitter")?,
+            min_energy: self.min_energy,
+            devices: self
+                .devices
+                .iter()
+                .filter(|(_, selected)| *selected)
+                .map(|(name, _)| name.clone())
+                .collect(),
+            idle_timeout_min: number(&self.idle_timeout_min, "Idle timeout")?,
+            pause_when_locked: self.pause_when_locked,
+            battery_mode: self.battery_mode,
+            battery_threshold_percent: number(&self.battery_threshold_percent, "Battery threshold")?,
+            battery_period_ms: number(&self.battery_period_ms, "Battery period")?,
+            notifications: self.notifications,
+            log: LogSettings {
+                level: self.log_level.trim().to_string(),
+                ..settings.log.clone()
+            },
+            ..settings.clone()
+        };
+        settings.validate()?;
+
+        Ok(settings)
+    }
+}
+
+fn number<T: std::str::FromStr>(text: &str, name: &str) -> Result<T, String> {
+    text.trim()
+        .parse()
+        .map_err(|_| format!("{} must be a whole number", name))
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::settings::{BatteryMode, Settings};
+    use crate::settings_form::SettingsForm;
+
+    #[test]
+    fn test_roundtrip() {
+        let settings = Settings {
+            devices: vec!["Headphones".to_string()],
+            battery_mode: BatteryMode::Pause,
+            notifications: false,
+            metrics_port: Some(9185),
+            ..Settings::default()
+        };
+        let form = SettingsForm::new(&settings, &["Speakers".to_string()], true);
+        assert!(form.autostart);
+        assert_eq!(
+            vec![("Speakers".to_string(), false), ("Headphones".to_string(), true)],
+            form.devices
+        );
+        assert_eq!(settings, form.apply(&settings).unwrap());
+    }
+
+    #[test]
+    fn test_apply() {
+        let settings = Settings::default();
+        let mut form = SettingsForm::new(&settings, &["Speakers".to_string()], false);
+        form.period_ms = " 10000 ".to_string();
+        form.devices[0].1 = true;
+
+        let applied = form.apply(&settings).unwrap();
+        assert_eq!(10000, applied.period_ms);
+        assert_eq!(vec!["Speakers"], applied.devices);
+    }
+
+    #[test]
+    fn test_apply_invalid() {
+        let settings = Settings::default();
+        let mut form = SettingsForm::new(&settings, &[], false);
+
+        form.period_ms = "fast".to_string();
+        assert_eq!(Err("Period must be a whole number".to_string()), form.apply(&settings));
+
+        form.period_ms = "10".to_string();
+        assert!(form.apply(&settings).is_err());
+
+        form.period_ms = "5000".to_string();
+        form.battery_threshold_percent = "300".to_string();
+        assert!(form.apply(&settings).is_err());
+
+        form.battery_threshold_percent = "100".to_string();
+        form.log_level = "verbose".to_string();
+        assert!(form.apply(&settings).is_err());
+    }
+}
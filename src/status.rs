@@ -0,0 +1,70 @@
+use crate::audio::PauseReason;
+use chrono::{DateTime, Utc};
+use serde::Serialize;
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum State {
+    Active,
+    Paused,
+    /// The device is closed while the system sleeps.
+    Suspended,
+    /// The last pings failed. Keep-alive keeps retrying.
+    Failing,
+}
+
+impl State {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            State::Active => "active",
+            State::Paused => "paused",
+            State::Suspended => "suspended",
+            State::Failing => "failing",
+        }
+    }
+}
+
+/// Snapshot of what the application is doing.
+#[derive(Debug, Clone, Serialize)]
+pub struct Status {
+    pub state: State,
+    pub devices: Vec<String>,
+    pub period_ms: u32,
+    pub last_play: Option<DateTime<Utc>>,
+    pub consecutive_failures: u32,
+    pub uptime_secs: u64,
+    pub pause_reasons: Vec<PauseReason>,
+    /// Whether the application starts at login. Unknown if it could not be found out.
+    pub autostart: Option<bool>,
+}
+
+impl Status {
+    pub fn to_json(&self) -> String {
+        serde_json::to_string(self).unwrap_or_else(|e| format!("{{\"error\":\"{}\"}}", e))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::audio::PauseReason;
+    use crate::status::{State, Status};
+
+    #[test]
+    fn test_to_json() {
+        let status = Status {
+            state: State::Paused,
+            devices: vec!["Speakers".to_string()],
+            period_ms: 5000,
+            last_play: None,
+            consecutive_failures: 0,
+            uptime_secs: 60,
+            pause_reasons: vec![PauseReason::SessionLocked],
+            autostart: Some(true),
+        };
+
+        assert_eq!(
+            r#"{"state":"paused","devices":["Speakers"],"period_ms":5000,"last_play":null,"consecutive_failures":0,"uptime_secs":60,"pause_reasons":["session_locked"],"autostart":true}"#,
+            status.to_json()
+        );
+    }
+}
@@ -0,0 +1,79 @@
+use log::error;
+use std::any::Any;
+use std::backtrace::Backtrace;
+use std::collections::VecDeque;
+use std::panic::{catch_unwind, AssertUnwindSafe};
+use std::time::{Duration, Instant};
+
+/* the audio subsystem is restarted at most MAX_RESTARTS times in RESTART_WINDOW. more crashes
+ * than that are a crash loop, and the application gives up */
+pub const MAX_RESTARTS: usize = 3;
+const RESTART_WINDOW: Duration = Duration::from_secs(10 * 60);
+
+/// Logs panics with a backtrace. Panics caught by [`catch_panic`] are logged too.
+pub fn install_panic_hook() {
+    std::panic::set_hook(Box::new(|panic_info| {
+        error!(event = "panic"; "{}\n{}", panic_info, Backtrace::force_capture());
+    }));
+}
+
+/// Runs `f` and turns a panic into an error with the panic message.
+pub fn catch_panic<T>(f: impl FnOnce() -> T) -> Result<T, String> {
+    /* whatever `f` left half done is dropped or restarted by the caller */
+    catch_unwind(AssertUnwindSafe(f)).map_err(|payload| panic_message(payload.as_ref()))
+}
+
+fn panic_message(payload: &(dyn Any + Send)) -> String {
+    match (payload.downcast_ref::<&str>(), payload.downcast_ref::<String>()) {
+        (Some(message), _) => message.to_string(),
+        (_, Some(message)) => message.clone(),
+        _ => "Unknown panic".to_string(),
+    }
+}
+
+/// Tells a crashing subsystem that is worth restarting from a crash loop.
+#[derive(Default)]
+pub struct RestartGuard {
+    restarts: VecDeque<Instant>,
+}
+
+impl RestartGuard {
+    /// Tells whether a restart at `now` is allowed and counts it if so.
+    pub fn allow(&mut self, now: Instant) -> bool {
+        while let Some(restart) = self.restarts.front()
+            && now.duration_since(*restart) >= RESTART_WINDOW
+        {
+            self.restarts.pop_front();
+        }
+        if self.restarts.len() >= MAX_RESTARTS {
+            return false;
+        }
+
+        self.restarts.push_back(now);
+        true
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::supervisor::{catch_panic, RestartGuard, MAX_RESTARTS, RESTART_WINDOW};
+    use std::time::{Duration, Instant};
+
+    #[test]
+    fn test_catch_panic() {
+        assert_eq!(Ok(5), catch_panic(|| 5));
+        assert_eq!(Err("Device is gone".to_string()), catch_panic(|| panic!("Device is gone")));
+        assert_eq!(Err("Device 3 is gone".to_string()), catch_panic(|| panic!("Device {} is gone", 3)));
+    }
+
+    #[test]
+    fn test_restart_guard() {
+        let mut guard = RestartGuard::default();
+        let start = Instant::now();
+        for i in 0..MAX_RESTARTS {
+            assert!(guard.allow(start + Duration::from_secs(i as u64)));
+        }
+        assert!(!guard.allow(start + Duration::from_secs(60)));
+        assert!(guard.allow(start + RESTART_WINDOW));
+    }
+}
@@ -0,0 +1,210 @@
+use crate::ipc::socket_path;
+use crate::settings::{config_home, Settings};
+use log::{debug, warn};
+use std::os::fd::{FromRawFd, RawFd};
+use std::os::linux::net::SocketAddrExt;
+use std::os::unix::net::{SocketAddr, UnixDatagram, UnixListener};
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+use std::{env, fs};
+
+const SERVICE_FILE: &str = "keep-audio-awake.service";
+const SOCKET_FILE: &str = "keep-audio-awake.socket";
+/* the first descriptor passed by socket activation */
+const LISTEN_FDS_START: RawFd = 3;
+/* the watchdog of the generated unit. raised at run time for longer periods */
+const DEFAULT_WATCHDOG_SECS: u64 = 30;
+/* slack for a ping to finish after it is due */
+const WATCHDOG_MARGIN: Duration = Duration::from_secs(10);
+
+/// `sd_notify` messages to the service manager. Exists only if the service manager asked for
+/// them with `Type=notify`.
+pub struct SystemdNotifier {
+    socket: UnixDatagram,
+    address: SocketAddr,
+    /* watchdog timeout if enabled for this process */
+    watchdog: Option<Duration>,
+}
+
+impl SystemdNotifier {
+    pub fn from_env() -> Option<Self> {
+        let path = env::var("NOTIFY_SOCKET").ok()?;
+        let address = match path.strip_prefix('@') {
+            Some(name) => SocketAddr::from_abstract_name(name),
+            None => SocketAddr::from_pathname(&path),
+        }
+        .inspect_err(|e| warn!("Invalid NOTIFY_SOCKET {}. {}", path, e))
+        .ok()?;
+        let socket = UnixDatagram::unbound()
+            .inspect_err(|e| warn!("Failed to create notification socket. {}", e))
+            .ok()?;
+
+        let watchdog = env::var("WATCHDOG_USEC")
+            .ok()
+            .and_then(|usec| usec.parse().ok())
+            .filter(|_| is_for_this_process("WATCHDOG_PID"))
+            .map(Duration::from_micros);
+        debug!("Service manager notifications enabled, watchdog {:?}", watchdog);
+
+        Some(Self {
+            socket,
+            address,
+            watchdog,
+        })
+    }
+
+    pub fn ready(&self) {
+        self.send("READY=1");
+    }
+
+    pub fn stopping(&self) {
+        self.send("STOPPING=1");
+    }
+
+    /// Shows a line in `systemctl status`.
+    pub fn status(&self, text: &str) {
+        self.send(&format!("STATUS={}", text.replace('\n', ", ")));
+    }
+
+    /// Tells the watchdog that the application is alive.
+    pub fn watchdog(&self) {
+        if self.watchdog.is_some() {
+            self.send("WATCHDOG=1");
+        }
+    }
+
+    /// How often the watchdog has to be told while nothing is played.
+    pub fn watchdog_interval(&self) -> Option<Duration> {
+        self.watchdog.map(|timeout| timeout / 2)
+    }
+
+    /// Raises the watchdog timeout if the next ping is due later than it fires.
+    pub fn expect_ping_within(&mut self, delay: Duration) {
+        let Some(timeout) = self.watchdog else {
+            return;
+        };
+        let needed = delay + WATCHDOG_MARGIN;
+        if needed > timeout {
+            debug!("Raising watchdog timeout to {:?}", needed);
+            self.watchdog = Some(needed);
+            self.send(&format!("WATCHDOG=1\nWATCHDOG_USEC={}", needed.as_micros()));
+        }
+    }
+
+    fn send(&self, message: &str) {
+        self.socket
+            .send_to_addr(message.as_bytes(), &self.address)
+            .map(|_| ())
+            .unwrap_or_else(|e| warn!("Failed to notify the service manager. {}", e));
+    }
+}
+
+/// The IPC socket passed by socket activation, if any.
+pub fn activated_listener() -> Option<UnixListener> {
+    let count: RawFd = env::var("LISTEN_FDS").ok()?.parse().ok()?;
+    if count < 1 || !is_for_this_process("LISTEN_PID") {
+        return None;
+    }
+    if count > 1 {
+        warn!("Expected one socket from the service manager, got {}", count);
+    }
+
+    debug!("Using the IPC socket of the service manager");
+    /* the descriptor is handed over to this process and owned by nothing else */
+    Some(unsafe { UnixListener::from_raw_fd(LISTEN_FDS_START) })
+}
+
+/// Writes a user service and a socket unit for the IPC endpoint. Returns the paths of the
+/// written files.
+pub fn install_service(settings: &Settings) -> Result<Vec<PathBuf>, String> {
+    let dir = config_home()?.join("systemd").join("user");
+    fs::create_dir_all(&dir).map_err(|e| format!("Failed to create {}. {}", dir.display(), e))?;
+    let exe = env::current_exe().map_err(|e| format!("Failed to get executable path. {}", e))?;
+
+    let units = [
+        (dir.join(SERVICE_FILE), service_unit(&exe)),
+        (dir.join(SOCKET_FILE), socket_unit(&socket_path(settings.instance_scope))),
+    ];
+    for (path, unit) in &units {
+        fs::write(path, unit).map_err(|e| format!("Failed to write {}. {}", path.display(), e))?;
+    }
+
+    Ok(units.into_iter().map(|(path, _)| path).collect())
+}
+
+fn is_for_this_process(variable: &str) -> bool {
+    env::var(variable).ok().and_then(|pid| pid.parse().ok()) == Some(std::process::id())
+}
+
+fn service_unit(exe: &Path) -> String {
+    format!(
+        "[Unit]\n\
+         Description=Keeps the audio device awake\n\
+         After={socket} sound.target\n\
+         Wants={socket}\n\
+         \n\
+         [Service]\n\
+         Type=notify\n\
+         ExecStart={exe} --headless --log-system=true --log-stdout=false\n\
+         WatchdogSec={watchdog}\n\
+         Restart=on-failure\n\
+         \n\
+         [Install]\n\
+         WantedBy=default.target\n",
+        socket = SOCKET_FILE,
+        exe = exec_arg(&exe.to_string_lossy()),
+        watchdog = DEFAULT_WATCHDOG_SECS
+    )
+}
+
+fn socket_unit(path: &Path) -> String {
+    format!(
+        "[Unit]\n\
+         Description=Keeps the audio device awake (control socket)\n\
+         \n\
+         [Socket]\n\
+         ListenStream={}\n\
+         SocketMode=0600\n\
+         \n\
+         [Install]\n\
+         WantedBy=sockets.target\n",
+        path.display()
+    )
+}
+
+/// Quotes an argument of a unit command line.
+fn exec_arg(arg: &str) -> String {
+    /* specifiers start with a percent sign */
+    let arg = arg.replace('%', "%%");
+    if arg.contains([' ', '\t', '"', '\'', '\\']) {
+        format!("\"{}\"", arg.replace('\\', "\\\\").replace('"', "\\\""))
+    } else {
+        arg
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::systemd::{service_unit, socket_unit};
+    use std::path::Path;
+
+    #[test]
+    fn test_service_unit() {
+        let unit = service_unit(Path::new("/usr/bin/keep-audio-awake"));
+        assert!(unit.contains("\nType=notify\n"));
+        assert!(unit.contains(
+            "\nExecStart=/usr/bin/keep-audio-awake --headless --log-system=true --log-stdout=false\n"
+        ));
+
+        let unit = service_unit(Path::new("/opt/Keep \"Audio\"/100%/keep-audio-awake"));
+        assert!(unit.contains(
+            "\nExecStart=\"/opt/Keep \\\"Audio\\\"/100%%/keep-audio-awake\" --headless --log-system=true"
+        ));
+    }
+
+    #[test]
+    fn test_socket_unit() {
+        let unit = socket_unit(Path::new("/run/user/1000/keep-audio-awake-alice.sock"));
+        assert!(unit.contains("\nListenStream=/run/user/1000/keep-audio-awake-alice.sock\n"));
+    }
+}
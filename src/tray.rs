@@ -0,0 +1,53 @@
+pub mod render;
+#[cfg(target_os = "linux")]
+pub mod sni;
+
+/// How long the busy icon is shown after a ping.
+pub const ICON_BLINK_PERIOD_MS: u32 = 500;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum TrayIcon {
+    Active,
+    /// Paused for any reason or suspended with the system.
+    Paused,
+    /// The last pings failed and keep-alive is retrying.
+    Degraded,
+    /// Pings keep failing.
+    Error,
+    /// Shown for a moment after each ping.
+    Busy,
+}
+
+impl TrayIcon {
+    pub const ALL: [TrayIcon; 5] = [
+        TrayIcon::Active,
+        TrayIcon::Paused,
+        TrayIcon::Degraded,
+        TrayIcon::Error,
+        TrayIcon::Busy,
+    ];
+}
+
+/// Entries of the tray context menu.
+#[derive(Debug, Clone, PartialEq)]
+pub enum MenuAction {
+    /// Select or deselect an output device. `None` is the default device.
+    ToggleDevice(Option<String>),
+    /// Start at login or stop doing so.
+    ToggleAutostart,
+    Settings,
+    Exit,
+}
+
+/// Entry of the devices submenu. `None` is the default device.
+#[derive(Debug, Clone, PartialEq)]
+pub struct DeviceItem {
+    pub name: Option<String>,
+    pub checked: bool,
+}
+
+/// Notification area icon. Implemented on top of the native tray of each platform.
+pub trait Tray {
+    fn show_icon(&self, icon: TrayIcon);
+    fn show_tip(&self, tip: &str);
+}
@@ -0,0 +1,246 @@
+use crate::tray::TrayIcon;
+
+const APP_ICON: &[u8] = include_bytes!("../../res/app.ico");
+const APP_ICON_GRAY: &[u8] = include_bytes!("../../res/app_gray.ico");
+
+/* straight RGBA */
+const BADGE_PAUSED: [u8; 4] = [0x60, 0x70, 0x80, 0xff];
+const BADGE_DEGRADED: [u8; 4] = [0xf0, 0xa0, 0x20, 0xff];
+const BADGE_ERROR: [u8; 4] = [0xd0, 0x30, 0x30, 0xff];
+const WHITE: [u8; 4] = [0xff, 0xff, 0xff, 0xff];
+
+/* each pixel is sampled on a grid of this size for smooth edges */
+const SUPERSAMPLING: u32 = 4;
+
+/// Square image in straight RGBA, rows top-down.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Image {
+    pub size: u32,
+    pub rgba: Vec<u8>,
+}
+
+impl Image {
+    fn pixel(&self, x: u32, y: u32) -> [u8; 4] {
+        let i = ((y * self.size + x) * 4) as usize;
+        [self.rgba[i], self.rgba[i + 1], self.rgba[i + 2], self.rgba[i + 3]]
+    }
+
+    /// Blends `color` over the pixel with the given coverage in 0..=1.
+    fn blend(&mut self, x: u32, y: u32, color: [u8; 4], coverage: f32) {
+        let i = ((y * self.size + x) * 4) as usize;
+        let src_a = color[3] as f32 / 255.0 * coverage;
+        let dst_a = self.rgba[i + 3] as f32 / 255.0;
+        let out_a = src_a + dst_a * (1.0 - src_a);
+        if out_a <= 0.0 {
+            return;
+        }
+
+        for (c, value) in color.iter().take(3).enumerate() {
+            let src = *value as f32 * src_a;
+            let dst = self.rgba[i + c] as f32 * dst_a * (1.0 - src_a);
+            self.rgba[i + c] = ((src + dst) / out_a).round() as u8;
+        }
+        self.rgba[i + 3] = (out_a * 255.0).round() as u8;
+    }
+
+    /// Draws a shape given by `inside` in coordinates relative to the image size.
+    fn fill(&mut self, color: [u8; 4], inside: impl Fn(f32, f32) -> bool) {
+        let step = 1.0 / (self.size * SUPERSAMPLING) as f32;
+        for y in 0..self.size {
+            for x in 0..self.size {
+                let mut hits = 0;
+                for sy in 0..SUPERSAMPLING {
+                    for sx in 0..SUPERSAMPLING {
+                        let u = (x * SUPERSAMPLING + sx) as f32 * step + step / 2.0;
+                        let v = (y * SUPERSAMPLING + sy) as f32 * step + step / 2.0;
+                        if inside(u, v) {
+                            hits += 1;
+                        }
+                    }
+                }
+                if hits > 0 {
+                    self.blend(x, y, color, hits as f32 / (SUPERSAMPLING * SUPERSAMPLING) as f32);
+                }
+            }
+        }
+    }
+
+    /// Pixels as ARGB32 in network byte order, as StatusNotifierItem expects.
+    #[cfg(target_os = "linux")]
+    pub fn to_argb(&self) -> Vec<u8> {
+        self.rgba
+            .chunks_exact(4)
+            .flat_map(|p| [p[3], p[0], p[1], p[2]])
+            .collect()
+    }
+
+    /// Pixels as BGRA, as Windows bitmaps expect.
+    #[cfg(windows)]
+    pub fn to_bgra(&self) -> Vec<u8> {
+        self.rgba
+            .chunks_exact(4)
+            .flat_map(|p| [p[2], p[1], p[0], p[3]])
+            .collect()
+    }
+}
+
+/// Draws the icon of the given state at the given size. The application icon is scaled and
+/// the non-active states get a badge in the bottom right corner.
+pub fn render(icon: TrayIcon, size: u32) -> Image {
+    let source = match icon {
+        TrayIcon::Busy => APP_ICON_GRAY,
+        _ => APP_ICON,
+    };
+    let mut image = decode_ico(source)
+        .map(|image| scale(&image, size))
+        .unwrap_or_else(|| Image {
+            size,
+            rgba: vec![0; (size * size * 4) as usize],
+        });
+
+    let badge = match icon {
+        TrayIcon::Active | TrayIcon::Busy => return image,
+        TrayIcon::Paused => BADGE_PAUSED,
+        TrayIcon::Degraded => BADGE_DEGRADED,
+        TrayIcon::Error => BADGE_ERROR,
+    };
+
+    /* a white ring keeps the badge visible on any icon color */
+    let (cx, cy, r) = (0.75, 0.75, 0.25);
+    let in_circle = move |u: f32, v: f32, r: f32| (u - cx).powi(2) + (v - cy).powi(2) <= r * r;
+    image.fill(WHITE, move |u, v| in_circle(u, v, r));
+    image.fill(badge, move |u, v| in_circle(u, v, r * 0.8));
+
+    match icon {
+        TrayIcon::Paused => image.fill(WHITE, move |u, v| {
+            let bar = |left: f32| (left..left + 0.05).contains(&u);
+            (0.66..0.84).contains(&v) && (bar(0.68) || bar(0.77))
+        }),
+        TrayIcon::Error => image.fill(WHITE, move |u, v| {
+            /* a cross */
+            let (du, dv) = (u - cx, v - cy);
+            (du.abs() <= 0.1 && dv.abs() <= 0.1) && ((du - dv).abs() <= 0.035 || (du + dv).abs() <= 0.035)
+        }),
+        TrayIcon::Degraded => image.fill(WHITE, move |u, v| {
+            /* an exclamation mark */
+            (u - cx).abs() <= 0.025 && ((0.64..0.78).contains(&v) || (0.81..0.86).contains(&v))
+        }),
+        TrayIcon::Active | TrayIcon::Busy => {}
+    }
+
+    image
+}
+
+/// Decodes the 32-bit bitmap of the first image of an icon file.
+fn decode_ico(ico: &[u8]) -> Option<Image> {
+    let u16_at = |offset: usize| Some(u16::from_le_bytes(ico.get(offset..offset + 2)?.try_into().ok()?));
+    let u32_at = |offset: usize| Some(u32::from_le_bytes(ico.get(offset..offset + 4)?.try_into().ok()?));
+
+    /* ICONDIR, the first ICONDIRENTRY, then BITMAPINFOHEADER */
+    if u16_at(2)? != 1 || u16_at(4)? == 0 {
+        return None;
+    }
+    let bitmap = u32_at(18)? as usize;
+    let header_size = u32_at(bitmap)? as usize;
+    let width = u32_at(bitmap + 4)? as usize;
+    /* the height covers both the color bitmap and the mask */
+    let height = u32_at(bitmap + 8)? as usize / 2;
+    if u16_at(bitmap + 14)? != 32 || width != height {
+        return None;
+    }
+
+    let pixels = ico.get(bitmap + header_size..bitmap + header_size + width * height * 4)?;
+    let mut rgba = Vec::with_capacity(pixels.len());
+    /* rows are stored bottom-up as BGRA */
+    for row in pixels.chunks_exact(width * 4).rev() {
+        for bgra in row.chunks_exact(4) {
+            rgba.extend_from_slice(&[bgra[2], bgra[1], bgra[0], bgra[3]]);
+        }
+    }
+
+    Some(Image {
+        size: width as u32,
+        rgba,
+    })
+}
+
+/// Resamples the image with a box filter. Color is weighted by alpha so that transparent
+/// pixels do not darken the edges.
+fn scale(image: &Image, size: u32) -> Image {
+    if image.size == size {
+        return image.clone();
+    }
+
+    let ratio = image.size as f32 / size as f32;
+    let mut rgba = Vec::with_capacity((size * size * 4) as usize);
+
+    for y in 0..size {
+        for x in 0..size {
+            /* the source area of the pixel, at least one source pixel wide */
+            let x0 = (x as f32 * ratio) as u32;
+            let y0 = (y as f32 * ratio) as u32;
+            let x1 = (((x + 1) as f32 * ratio).ceil() as u32).clamp(x0 + 1, image.size);
+            let y1 = (((y + 1) as f32 * ratio).ceil() as u32).clamp(y0 + 1, image.size);
+
+            let mut sum = [0f32; 4];
+            for sy in y0..y1 {
+                for sx in x0..x1 {
+                    let p = image.pixel(sx, sy);
+                    let a = p[3] as f32;
+                    for c in 0..3 {
+                        sum[c] += p[c] as f32 * a;
+                    }
+                    sum[3] += a;
+                }
+            }
+
+            let count = ((x1 - x0) * (y1 - y0)) as f32;
+            if sum[3] > 0.0 {
+                rgba.extend(sum[..3].iter().map(|c| (c / sum[3]).round() as u8));
+            } else {
+                rgba.extend([0, 0, 0]);
+            }
+            rgba.push((sum[3] / count).round() as u8);
+        }
+    }
+
+    Image { size, rgba }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::tray::render::{decode_ico, render, scale, APP_ICON, BADGE_ERROR};
+    use crate::tray::TrayIcon;
+
+    #[test]
+    fn test_decode_ico() {
+        let image = decode_ico(APP_ICON).unwrap();
+        assert_eq!(32, image.size);
+        assert_eq!(32 * 32 * 4, image.rgba.len());
+        assert!(decode_ico(&[0; 8]).is_none());
+    }
+
+    #[test]
+    fn test_scale() {
+        let image = decode_ico(APP_ICON).unwrap();
+        for size in [16, 20, 24, 48, 64] {
+            assert_eq!((size * size * 4) as usize, scale(&image, size).rgba.len());
+        }
+    }
+
+    #[test]
+    fn test_render() {
+        let active = render(TrayIcon::Active, 16);
+        let error = render(TrayIcon::Error, 16);
+        /* the badge is drawn only in the bottom right quarter */
+        assert_eq!(active.pixel(2, 2), error.pixel(2, 2));
+        assert_eq!(BADGE_ERROR, error.pixel(14, 12));
+
+        let icons = [TrayIcon::Active, TrayIcon::Paused, TrayIcon::Degraded, TrayIcon::Error];
+        for (i, a) in icons.iter().enumerate() {
+            for b in &icons[i + 1..] {
+                assert_ne!(render(*a, 24), render(*b, 24));
+            }
+        }
+    }
+}
@@ -0,0 +1,248 @@
+use crate::core::TipLine;
+use crate::status::State;
+use crate::tray::render::render;
+use crate::tray::{DeviceItem, MenuAction, Tray, TrayIcon, ICON_BLINK_PERIOD_MS};
+use chrono::Local;
+use ksni::blocking::{Handle, TrayMethods};
+use ksni::menu::{CheckmarkItem, StandardItem, SubMenu};
+use ksni::{Icon, MenuItem, ToolTip};
+use log::{debug, warn};
+use std::collections::HashMap;
+use std::sync::mpsc::{channel, Receiver, Sender};
+use std::thread;
+use std::time::Duration;
+
+const TRAY_ID: &str = "keep-audio-awake";
+/* hosts pick the closest size and scale it */
+const ICON_SIZES: [u32; 6] = [16, 22, 24, 32, 48, 64];
+/* the same strings as in the Windows resources */
+const APP_TITLE: &str = "KeepAuAwake";
+const EXIT: &str = "Exit";
+const SETTINGS: &str = "Settings";
+const START_AT_LOGIN: &str = "Start at login";
+const DEVICES: &str = "Devices";
+const DEFAULT_DEVICE: &str = "Default device";
+const KEEPING_AUDIO_DEVICE_AWAKE: &str = "Keeping audio device awake";
+const PAUSED: &str = "Paused";
+const SUSPENDED: &str = "Suspended";
+const RETRYING: &str = "Retrying";
+const FAILED_PINGS: &str = "Failed pings";
+const DEVICE: &str = "Device";
+const LAST_PING: &str = "Last ping";
+const ON_BATTERY: &str = "On battery";
+const PAUSED_ON_BATTERY: &str = "Paused to save battery";
+const SLOWED_DOWN_ON_BATTERY: &str = "Slowed down to save battery";
+
+/// StatusNotifierItem shown by KDE and by GNOME with the AppIndicator extension.
+pub struct SniTray {
+    handle: Handle<Item>,
+}
+
+struct Item {
+    icon: TrayIcon,
+    /* the icon to return to after a blink */
+    status_icon: TrayIcon,
+    tip: String,
+    devices: Vec<DeviceItem>,
+    autostart: bool,
+    icons: HashMap<TrayIcon, Vec<Icon>>,
+    actions: Sender<MenuAction>,
+    wake: Box<dyn Fn() + Send>,
+}
+
+impl Item {
+    fn select(&self, action: MenuAction) {
+        if self.actions.send(action).is_ok() {
+            (self.wake)();
+        }
+    }
+}
+
+impl ksni::Tray for Item {
+    const MENU_ON_ACTIVATE: bool = true;
+
+    fn id(&self) -> String {
+        TRAY_ID.to_string()
+    }
+
+    fn title(&self) -> String {
+        APP_TITLE.to_string()
+    }
+
+    fn icon_pixmap(&self) -> Vec<Icon> {
+        self.icons.get(&self.icon).cloned().unwrap_or_default()
+    }
+
+    fn tool_tip(&self) -> ToolTip {
+        ToolTip {
+            title: APP_TITLE.to_string(),
+            description: self.tip.clone(),
+            ..Default::default()
+        }
+    }
+
+    fn menu(&self) -> Vec<MenuItem<Self>> {
+        let devices = self
+            .devices
+            .iter()
+            .map(|device| {
+                let name = device.name.clone();
+                CheckmarkItem {
+                    /* underscores mark access keys */
+                    label: name.as_deref().unwrap_or(DEFAULT_DEVICE).replace('_', "__"),
+                    checked: device.checked,
+                    activate: Box::new(move |item: &mut Self| {
+                        item.select(MenuAction::ToggleDevice(name.clone()))
+                    }),
+                    ..Default::default()
+                }
+                .into()
+            })
+            .collect();
+
+        vec![
+            SubMenu {
+                label: DEVICES.to_string(),
+                icon_name: "audio-speakers".to_string(),
+                submenu: devices,
+                ..Default::default()
+            }
+            .into(),
+            CheckmarkItem {
+                label: START_AT_LOGIN.to_string(),
+                checked: self.autostart,
+                activate: Box::new(|item: &mut Self| item.select(MenuAction::ToggleAutostart)),
+                ..Default::default()
+            }
+            .into(),
+            StandardItem {
+                label: SETTINGS.to_string(),
+                icon_name: "preferences-system".to_string(),
+                activate: Box::new(|item: &mut Self| item.select(MenuAction::Settings)),
+                ..Default::default()
+            }
+            .into(),
+            MenuItem::Separator,
+            StandardItem {
+                label: EXIT.to_string(),
+                icon_name: "application-exit".to_string(),
+                activate: Box::new(|item: &mut Self| item.select(MenuAction::Exit)),
+                ..Default::default()
+            }
+            .into(),
+        ]
+    }
+}
+
+impl SniTray {
+    /// Registers the item with the StatusNotifierWatcher. Selected menu entries are queued to
+    /// the returned receiver, and `wake` is called after each one.
+    pub fn start<F>(tip: &str, wake: F) -> Result<(Self, Receiver<MenuAction>), String>
+    where
+        F: Fn() + Send + 'static,
+    {
+        let (sender, receiver) = channel();
+        let item = Item {
+            icon: TrayIcon::Active,
+            status_icon: TrayIcon::Active,
+            tip: tip.to_string(),
+            devices: Vec::new(),
+            autostart: false,
+            icons: TrayIcon::ALL
+                .into_iter()
+                .map(|icon| (icon, ICON_SIZES.into_iter().map(|size| icon_pixmap(icon, size)).collect()))
+                .collect(),
+            actions: sender,
+            wake: Box::new(wake),
+        };
+
+        /* started with the session, the tray host may not be up yet */
+        let handle = item
+            .assume_sni_available(true)
+            .spawn()
+            .map_err(|e| format!("Failed to create tray icon. {}", e))?;
+
+        debug!("Tray icon registered");
+
+        Ok((Self { handle }, receiver))
+    }
+
+    /// Shows the busy icon for a moment.
+    pub fn blink(&self) {
+        self.show_icon(TrayIcon::Busy);
+
+        let handle = self.handle.clone();
+        thread::Builder::new()
+            .name("tray-blink".to_string())
+            .spawn(move || {
+                thread::sleep(Duration::from_millis(ICON_BLINK_PERIOD_MS as u64));
+                handle.update(|item| item.icon = item.status_icon);
+            })
+            .map(|_| ())
+            .unwrap_or_else(|e| {
+                warn!("Failed to blink tray icon. {}", e);
+                self.handle.update(|item| item.icon = item.status_icon);
+            });
+    }
+
+    /// Fills the devices submenu.
+    pub fn show_devices(&self, devices: Vec<DeviceItem>) {
+        self.handle.update(|item| item.devices = devices);
+    }
+
+    pub fn show_autostart(&self, enabled: bool) {
+        self.handle.update(|item| item.autostart = enabled);
+    }
+}
+
+impl Tray for SniTray {
+    fn show_icon(&self, icon: TrayIcon) {
+        self.handle.update(|item| {
+            item.icon = icon;
+            if icon != TrayIcon::Busy {
+                item.status_icon = icon;
+            }
+        });
+    }
+
+    fn show_tip(&self, tip: &str) {
+        self.handle.update(|item| item.tip = tip.to_string());
+    }
+}
+
+impl Drop for SniTray {
+    fn drop(&mut self) {
+        self.handle.shutdown().wait();
+    }
+}
+
+pub fn tip_text(lines: &[TipLine]) -> String {
+    lines
+        .iter()
+        .map(|line| match line {
+            TipLine::State(State::Active) => KEEPING_AUDIO_DEVICE_AWAKE.to_string(),
+            TipLine::State(State::Paused) => PAUSED.to_string(),
+            TipLine::State(State::Suspended) => SUSPENDED.to_string(),
+            TipLine::State(State::Failing) => RETRYING.to_string(),
+            TipLine::FailedPings(count) => format!("{}: {}", FAILED_PINGS, count),
+            TipLine::Device(name) => format!("{}: {}", DEVICE, name),
+            TipLine::LastPing(time) => {
+                format!("{}: {}", LAST_PING, time.with_timezone(&Local).format("%H:%M:%S"))
+            }
+            TipLine::OnBattery(Some(charge)) => format!("{} ({}%)", ON_BATTERY, charge),
+            TipLine::OnBattery(None) => ON_BATTERY.to_string(),
+            TipLine::PausedOnBattery => PAUSED_ON_BATTERY.to_string(),
+            TipLine::SlowedDownOnBattery => SLOWED_DOWN_ON_BATTERY.to_string(),
+        })
+        .collect::<Vec<_>>()
+        .join("\n")
+}
+
+fn icon_pixmap(icon: TrayIcon, size: u32) -> Icon {
+    let image = render(icon, size);
+    Icon {
+        width: image.size as i32,
+        height: image.size as i32,
+        data: image.to_argb(),
+    }
+}
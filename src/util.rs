@@ -1,34 +1,27 @@
 use log::warn;
 use native_windows_gui::{ControlHandle};
-use std::time::Duration;
-use std::{ptr, thread};
-use windows::core::{HRESULT, PCSTR};
-use windows::Win32::Foundation::{GetLastError, ERROR_ALREADY_EXISTS, HWND};
-use windows::Win32::Storage::FileSystem::SYNCHRONIZE;
-use windows::Win32::System::Threading::CreateMutexExA;
-use windows::Win32::UI::WindowsAndMessaging::{KillTimer, SetTimer};
+use windows::core::HRESULT;
+use windows::Win32::Foundation::HWND;
+use windows::Win32::System::Console::{AttachConsole, ATTACH_PARENT_PROCESS};
+use windows::Win32::UI::WindowsAndMessaging::{GetSystemMetrics, KillTimer, SetTimer, SM_CXSMICON};
 
 pub fn hwnd(handle: ControlHandle) -> Option<HWND> {
-    Some(HWND(handle.hwnd().unwrap() as _))
+    handle.hwnd().map(|hwnd| HWND(hwnd as _))
 }
 
-pub fn check_app_running() -> Result<(), String> {
-    let mutex_id = b"Global\\8e22f9ab-0f7f-4f01-8dc2-6047b74a2a99\0";
-
+/// Attaches to the console of the parent process so that a GUI subsystem
+/// executable can print to the terminal it was started from.
+pub fn attach_console() {
     unsafe {
-        let handle = CreateMutexExA(
-            Some(ptr::null_mut()),
-            PCSTR(mutex_id.as_ptr()),
-            0,
-            SYNCHRONIZE.0,
-        )
-        .map_err(|e| e.message().to_owned())?;
+        AttachConsole(ATTACH_PARENT_PROCESS).unwrap_or_default();
+    }
+}
 
-        if handle.is_invalid() || GetLastError() == ERROR_ALREADY_EXISTS {
-            Err("Already running.".to_string())
-        } else {
-            Ok(())
-        }
+/// Size of the icons in the notification area and window captions. Depends on the DPI.
+pub fn small_icon_size() -> u32 {
+    match unsafe { GetSystemMetrics(SM_CXSMICON) } {
+        size if size > 0 => size as u32,
+        _ => 16,
     }
 }
 
@@ -44,27 +37,6 @@ pub fn from_utf16(s: &[u16]) -> String {
         .unwrap_or("Decoding error".to_string())
 }
 
-pub fn sleep_cancelable<F>(duration: Duration, should_cancel: F) -> bool
-where
-    F: Fn() -> bool,
-{
-    let short = Duration::from_millis(10);
-    let steps = duration.as_millis() / short.as_millis();
-    let remainder = duration - (short * steps as u32);
-
-    for _ in 0..steps {
-        if should_cancel() {
-            return true;
-        }
-        thread::sleep(short);
-    }
-
-    if !should_cancel() && remainder > Duration::ZERO {
-        thread::sleep(remainder);
-    }
-    false
-}
-
 pub fn start_timer(hwnd: Option<HWND>, timer_id: usize, period: u32) -> Result<(), String> {
     unsafe {
         if SetTimer(hwnd, timer_id, period, None) == 0 {